@@ -0,0 +1,95 @@
+//! Compares the latency of `GET /version` over the available API transports.
+//!
+//! By default requests go to an in-process stand-in for the Firecracker API.
+//! Set `FIRECRACKER_API_SOCKET` to the socket of a running Firecracker to measure it instead.
+
+use std::{
+    env,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use firecracker_sdk::{
+    domain::http::{Http, HttpDecoder},
+    infrastructure::connection::{Transport, TransportKind},
+};
+use http::{Method, StatusCode};
+use tempfile::TempDir;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{UnixListener, UnixStream},
+    runtime::Runtime,
+};
+
+/// Answers every request like `GET /version` of Firecracker
+async fn serve(listener: UnixListener) {
+    while let Ok((socket, _)) = listener.accept().await {
+        tokio::spawn(answer(socket));
+    }
+}
+
+async fn answer(mut socket: UnixStream) -> anyhow::Result<()> {
+    let mut decoder = HttpDecoder::new();
+    let mut buf = [0; 4096];
+    loop {
+        while decoder.decode()?.is_some() {
+            let res = Http::new_response(StatusCode::OK)
+                .add_header("Content-Type", "application/json")
+                .body(r#"{"firecracker_version":"1.13.1"}"#);
+            socket.write_all(&res.build()).await?;
+        }
+        match socket.read(&mut buf).await? {
+            0 => return Ok(()),
+            n => decoder.extend(&buf[..n]),
+        }
+    }
+}
+
+fn api_latency(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let _dir: TempDir;
+    let socket = match env::var_os("FIRECRACKER_API_SOCKET") {
+        Some(socket) => PathBuf::from(socket),
+        None => {
+            _dir = tempfile::tempdir().unwrap();
+            let socket = _dir.path().join("api.socket");
+            let listener = runtime
+                .block_on(async { UnixListener::bind(&socket) })
+                .unwrap();
+            runtime.spawn(serve(listener));
+            socket
+        }
+    };
+
+    let kinds = [
+        ("stream", TransportKind::Stream),
+        #[cfg(feature = "hyper")]
+        ("hyper", TransportKind::Hyper),
+    ];
+    let mut group = c.benchmark_group("GET /version");
+    for (name, kind) in kinds {
+        group.bench_function(name, |b| {
+            b.iter_custom(|iters| {
+                runtime.block_on(async {
+                    let mut transport = Transport::connect(kind, &socket).await.unwrap();
+                    let start = Instant::now();
+                    for _ in 0..iters {
+                        let req = Http::new_request("/version", Method::GET)
+                            .add_header("Host", "localhost");
+                        transport.exchange(req, None).await.unwrap();
+                    }
+                    start.elapsed()
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().measurement_time(Duration::from_secs(3));
+    targets = api_latency
+}
+criterion_main!(benches);
@@ -1 +1,2 @@
 pub mod startup;
+pub mod vm;
@@ -1,18 +1,52 @@
-use std::path::{Path, PathBuf};
+use std::{
+    env,
+    fs::Permissions,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
-use anyhow::Result;
+use anyhow::{Result, anyhow, bail};
 use serde::Serialize;
-use tempfile::tempdir;
+use serde_json::Value;
+use tokio::sync::broadcast;
 
 use crate::{
-    domain::config::{BootSource, Drives, FirecrackerConfiguration, NetInterface, VSock},
-    infrastructure::{fs::FileManager, process::FirecrackerProcess, s3::S3Downloader},
+    api::vm::{Configuring, Vm},
+    domain::{
+        compat::Version,
+        config::FirecrackerConfiguration,
+        models::{
+            Balloon, BootSource, Drive, MachineConfiguration, NetworkInterface, RateLimiter, Vsock,
+        },
+    },
+    infrastructure::{
+        cache::ArtifactCache,
+        client::ClientOptions,
+        connection::TransportKind,
+        download::DownloadProgress,
+        installer::FirecrackerInstaller,
+        lockfile::Lockfile,
+        process::{
+            FirecrackerProcess,
+            events::{EventChannel, VmEvent},
+        },
+        s3::{Arch, Credentials, S3Downloader},
+        source::{Artifact, ArtifactContext, ArtifactKind, ArtifactSource, LocalSource},
+        supervisor::{RestartPolicy, Supervisor},
+        workspace::{CleanupPolicy, VmWorkspace},
+    },
 };
 
 /// A structure for configuring the launch of FirecrackerVM. Helps to preconfigure and start the virtual machine.
 ///
 /// Note: Firecracker must be installed globally.
 ///
+/// Every file of the VM lives in its [`VmWorkspace`], a fresh temporary directory by default.
+/// Unless [`FirecrackerStartup::detached`] is set, the started process is killed and the workspace
+/// cleanup policy is applied once the returned `FirecrackerProcess` is dropped.
+///
 /// Exemple:
 /// ```no_compile
 /// let process = FirecrackerStartup::new()
@@ -21,26 +55,68 @@ use crate::{
 ///     .download_kernel(true)
 ///     .start().await.unwrap();
 /// ```
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct FirecrackerStartup {
-    api_socket: PathBuf,
-    vsock: PathBuf,
+    api_socket: Option<PathBuf>,
+    vsock: Option<PathBuf>,
     stdout: bool,
     download_kernel: bool,
     download_rootfs: bool,
+    copy_rootfs: bool,
+    cache: ArtifactCache,
+    s3: S3Downloader,
+    lockfile: Option<PathBuf>,
+    update_lockfile: bool,
+    installer: FirecrackerInstaller,
+    release: Option<Version>,
+    #[serde(skip)]
+    source: Arc<dyn ArtifactSource>,
+    initrd: Option<PathBuf>,
+    vcpu_count: u8,
+    mem_size_mib: usize,
+    detached: bool,
+    workspace: Option<VmWorkspace>,
+    cleanup_policy: Option<CleanupPolicy>,
+    state_poll_interval: Duration,
+    rootfs_rate_limiter: Option<RateLimiter>,
+    balloon: Option<Balloon>,
+    mmds: Option<Value>,
+    transport: TransportKind,
+    client_options: ClientOptions,
+    #[serde(skip)]
+    events: EventChannel,
 }
 
 impl FirecrackerStartup {
     /// Creates a new instance of FirecrackerStartup
     pub fn new() -> Self {
-        let mut tempdir = tempdir().unwrap();
-        tempdir.disable_cleanup(true);
         Self {
-            api_socket: tempdir.path().join("firecracker.socket"),
+            api_socket: None,
             download_kernel: false,
             download_rootfs: false,
             stdout: false,
-            vsock: tempdir.path().join("vsock.socket"),
+            vsock: None,
+            copy_rootfs: false,
+            cache: ArtifactCache::default(),
+            s3: S3Downloader::default(),
+            lockfile: None,
+            update_lockfile: false,
+            installer: FirecrackerInstaller::default(),
+            release: None,
+            source: Arc::new(LocalSource::default()),
+            initrd: None,
+            vcpu_count: 1,
+            mem_size_mib: 128,
+            detached: false,
+            workspace: None,
+            cleanup_policy: None,
+            state_poll_interval: Duration::from_secs(1),
+            rootfs_rate_limiter: None,
+            balloon: None,
+            mmds: None,
+            transport: TransportKind::default(),
+            client_options: ClientOptions::default(),
+            events: EventChannel::default(),
         }
     }
 
@@ -48,15 +124,15 @@ impl FirecrackerStartup {
     ///
     /// Note: For the best documentation, please refer to [here](https://github.com/firecracker-microvm/firecracker/blob/main/docs/getting-started.md).
     pub fn set_api_socket<P: AsRef<Path>>(mut self, path: P) -> Self {
-        self.api_socket = path.as_ref().to_path_buf();
+        self.api_socket = Some(path.as_ref().to_path_buf());
         self
     }
 
-    /// Returns the --api-sock startup argument with the path to the unix socket
+    /// Returns the --api-sock startup argument with the path to the unix socket, if set
     ///
-    /// Note: For the best documentation, please refer to [here](https://github.com/firecracker-microvm/firecracker/blob/main/docs/getting-started.md).
-    pub fn get_api_socket(&self) -> &PathBuf {
-        &self.api_socket
+    /// Note: Without it, the VM uses the API socket of its workspace.
+    pub fn get_api_socket(&self) -> Option<&PathBuf> {
+        self.api_socket.as_ref()
     }
 
     /// Flag to enable/disable vm's stdout
@@ -65,9 +141,9 @@ impl FirecrackerStartup {
         self
     }
 
-    /// Set vsock path for vm
+    /// Set vsock path for vm, the vsock socket of the workspace by default
     pub fn vsocket<P: AsRef<Path>>(mut self, path: P) -> Self {
-        self.vsock = path.as_ref().to_path_buf();
+        self.vsock = Some(path.as_ref().to_path_buf());
         self
     }
 
@@ -76,56 +152,443 @@ impl FirecrackerStartup {
         self.stdout
     }
 
-    /// Flag to download the latest kernel version for microVM
+    /// Flag to download the latest kernel version for microVM instead of using the artifact source
+    ///
+    /// Downloads are stored in the [`ArtifactCache`] and reused by the next starts.
     pub fn download_kernel(mut self, flag: bool) -> Self {
         self.download_kernel = flag;
         self
     }
-    /// Flag to download the ubuntu-22.04.ext4 for microVM
+    /// Flag to download the rootfs of the configured distro for microVM instead of using the artifact source
+    ///
+    /// Downloads are stored in the [`ArtifactCache`], the VM always boots from a copy in its workspace.
     pub fn download_rootfs(mut self, flag: bool) -> Self {
         self.download_rootfs = flag;
         self
     }
 
+    /// Flag to leave the VM running after `FirecrackerProcess` is dropped
+    ///
+    /// A detached process is not killed on drop or on the death of the parent,
+    /// and its sockets and temporary directory are left in place.
+    pub fn detached(mut self, flag: bool) -> Self {
+        self.detached = flag;
+        self
+    }
+
+    /// Returns current flag of detached mode
+    pub fn is_detached(&self) -> bool {
+        self.detached
+    }
+
+    /// Set the directory holding every file of the VM
+    ///
+    /// Note: The sockets set with `set_api_socket` and `vsocket` take precedence over the ones of the workspace.
+    pub fn workspace(mut self, workspace: VmWorkspace) -> Self {
+        self.workspace = Some(workspace);
+        self
+    }
+
+    /// Returns the workspace of the VM, if set
+    ///
+    /// Note: Without it, a temporary workspace is created when the VM starts.
+    pub fn get_workspace(&self) -> Option<&VmWorkspace> {
+        self.workspace.as_ref()
+    }
+
+    /// Set the policy applied to the workspace when the VM ends, overriding the one of the workspace
+    pub fn cleanup_policy(mut self, policy: CleanupPolicy) -> Self {
+        self.cleanup_policy = Some(policy);
+        self
+    }
+
+    /// Set the initrd image to boot with
+    pub fn initrd<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.initrd = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set the number of vCPUs, 1 by default
+    pub fn vcpu_count(mut self, count: u8) -> Self {
+        self.vcpu_count = count;
+        self
+    }
+
+    /// Set the guest memory size in MiB, 128 by default
+    pub fn mem_size_mib(mut self, size: usize) -> Self {
+        self.mem_size_mib = size;
+        self
+    }
+
+    /// Flag to boot from a private copy of the rootfs placed in the workspace
+    pub fn copy_rootfs(mut self, flag: bool) -> Self {
+        self.copy_rootfs = flag;
+        self
+    }
+
+    /// Set where the kernel, initrd and rootfs come from, [`LocalSource::default`] by default
+    ///
+    /// Note: `download_kernel` and `download_rootfs` take precedence for the kernel and rootfs,
+    /// and `initrd` for the initrd.
+    pub fn artifact_source<S: ArtifactSource + 'static>(mut self, source: S) -> Self {
+        self.source = Arc::new(source);
+        self
+    }
+
+    /// Set the cache holding downloaded artifacts, `~/.firecracker/cache` by default
+    pub fn artifact_cache(mut self, cache: ArtifactCache) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Flag to use cached artifacts only, failing if one was never downloaded
+    pub fn offline(mut self, flag: bool) -> Self {
+        self.cache = self.cache.offline(flag);
+        self
+    }
+
+    /// Set a callback receiving the progress of kernel and rootfs downloads
+    pub fn on_download_progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&DownloadProgress) + Send + Sync + 'static,
+    {
+        self.s3 = self.s3.progress(Some(Arc::new(callback)));
+        self
+    }
+
+    /// Set the URL listing the artifact bucket and the URL artifacts are downloaded from,
+    /// to use a mirror of `spec.ccfc.min`
+    pub fn artifact_urls(
+        mut self,
+        list_url: impl Into<String>,
+        download_url: impl Into<String>,
+    ) -> Self {
+        self.s3 = self.s3.urls(list_url, download_url);
+        self
+    }
+
+    /// Set an S3-compatible store and the bucket to download the artifacts from, in place of `artifact_urls`
+    pub fn artifact_endpoint(
+        mut self,
+        endpoint: impl Into<String>,
+        bucket: impl Into<String>,
+    ) -> Self {
+        self.s3 = self.s3.endpoint(endpoint, bucket);
+        self
+    }
+
+    /// Flag to address the bucket of `artifact_endpoint` by path instead of by subdomain
+    pub fn artifact_path_style(mut self, flag: bool) -> Self {
+        self.s3 = self.s3.path_style(flag);
+        self
+    }
+
+    /// Set the region of the artifact bucket, `AWS_REGION` or `us-east-1` by default
+    pub fn artifact_region(mut self, region: impl Into<String>) -> Self {
+        self.s3 = self.s3.region(region);
+        self
+    }
+
+    /// Set the credentials to read a private artifact bucket, see [`Credentials::load`]
+    pub fn artifact_credentials(mut self, credentials: Option<Credentials>) -> Self {
+        self.s3 = self.s3.credentials(credentials);
+        self
+    }
+
+    /// Set the version of the Firecracker CI artifacts to download, `v1.10` by default
+    pub fn ci_version(mut self, version: impl Into<String>) -> Self {
+        self.s3 = self.s3.ci_version(version);
+        self
+    }
+
+    /// Set the architecture of the artifacts to download, the one of the host by default
+    pub fn arch(mut self, arch: Arch) -> Self {
+        self.s3 = self.s3.arch(arch);
+        self
+    }
+
+    /// Set the series of the kernel to download, `5.10` by default, or a complete version such as `5.10.239`
+    pub fn kernel_series(mut self, series: impl Into<String>) -> Self {
+        self.s3 = self.s3.kernel_series(series);
+        self
+    }
+
+    /// Set the distro of the rootfs to download, `ubuntu-22.04` by default
+    pub fn rootfs_distro(mut self, distro: impl Into<String>) -> Self {
+        self.s3 = self.s3.distro(distro);
+        self
+    }
+
+    /// Set the lockfile pinning the downloaded kernel, initrd and rootfs, see [`Lockfile`]
+    ///
+    /// The artifacts it records are used instead of the latest ones,
+    /// and the VM does not start if a download differs from its recorded digest.
+    pub fn lockfile<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.lockfile = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Flag to download the latest artifacts and record them in the lockfile
+    pub fn update_lockfile(mut self, flag: bool) -> Self {
+        self.update_lockfile = flag;
+        self
+    }
+
+    /// Set the installed Firecracker release to run, see [`FirecrackerInstaller`]
+    ///
+    /// Without one, the binary is taken from the `FIRECRACKER` environment variable, `firecracker` by default.
+    pub fn firecracker_release(mut self, version: Version) -> Self {
+        self.release = Some(version);
+        self
+    }
+
+    /// Set the installer whose releases are run, `~/.firecracker/releases` by default
+    pub fn installer(mut self, installer: FirecrackerInstaller) -> Self {
+        self.installer = installer;
+        self
+    }
+
+    /// Returns the Firecracker binary to run
+    pub(crate) fn current_binary(&self) -> PathBuf {
+        match self.release {
+            Some(version) => self.installer.path(version).join("firecracker"),
+            None => env::var_os("FIRECRACKER")
+                .map(PathBuf::from)
+                .unwrap_or("firecracker".into()),
+        }
+    }
+
+    /// Returns the release of the Firecracker binary this startup runs, see [`FirecrackerProcess::binary_version`]
+    pub async fn binary_version(&self) -> Result<Version> {
+        FirecrackerProcess::binary_version(self.current_binary()).await
+    }
+
+    /// Set the rate limiter of the rootfs
+    pub fn rootfs_rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.rootfs_rate_limiter = Some(limiter);
+        self
+    }
+
+    /// Attach a memory balloon device
+    pub fn balloon(mut self, balloon: Balloon) -> Self {
+        self.balloon = Some(balloon);
+        self
+    }
+
+    /// Set the initial content of the MMDS data store
+    pub fn mmds(mut self, data: Value) -> Self {
+        self.mmds = Some(data);
+        self
+    }
+
+    /// Subscribes to the lifecycle events of the VM
+    ///
+    /// Subscribe before `start` to receive the `Spawned` and `ApiReady` events.
+    /// A supervised VM publishes the events of every relaunched process here as well.
+    pub fn subscribe(&self) -> broadcast::Receiver<VmEvent> {
+        self.events.subscribe()
+    }
+
+    /// Set how often the VM state is polled via `GET /` to detect changes made outside of the SDK
+    pub fn state_poll_interval(mut self, interval: Duration) -> Self {
+        self.state_poll_interval = interval;
+        self
+    }
+
+    pub(crate) fn current_state_poll_interval(&self) -> Duration {
+        self.state_poll_interval
+    }
+
+    /// Set the implementation of the connection to the API socket
+    pub fn transport(mut self, kind: TransportKind) -> Self {
+        self.transport = kind;
+        self
+    }
+
+    pub(crate) fn current_transport(&self) -> TransportKind {
+        self.transport
+    }
+
+    /// Set the timeouts and retries of the API requests, see [`ClientOptions`]
+    pub fn client_options(mut self, options: ClientOptions) -> Self {
+        self.client_options = options;
+        self
+    }
+
+    pub(crate) fn current_client_options(&self) -> ClientOptions {
+        self.client_options
+    }
+
+    pub(crate) fn events(&self) -> &EventChannel {
+        &self.events
+    }
+
+    /// Returns the workspace of the VM, a new temporary one unless set
+    fn resolve_workspace(&self) -> Result<VmWorkspace> {
+        let workspace = match &self.workspace {
+            Some(workspace) => workspace.clone(),
+            None => VmWorkspace::temporary()?,
+        };
+        Ok(match self.cleanup_policy {
+            Some(policy) => workspace.cleanup_policy(policy),
+            None => workspace,
+        })
+    }
+
+    /// Resolves an artifact from its source, pinned by the lockfile
+    async fn resolve(&self, kind: ArtifactKind, lockfile: &Lockfile) -> Result<Option<Artifact>> {
+        let source: &dyn ArtifactSource = match kind {
+            ArtifactKind::Kernel if self.download_kernel => &self.s3,
+            ArtifactKind::Rootfs if self.download_rootfs => &self.s3,
+            _ => self.source.as_ref(),
+        };
+        let context = ArtifactContext {
+            cache: &self.cache,
+            locked: lockfile.get(kind),
+        };
+        source.resolve(kind, context).await
+    }
+
+    /// Creates the workspace and resolves the kernel, rootfs and initrd paths
+    async fn prepare(
+        &self,
+        workspace: &VmWorkspace,
+    ) -> Result<(PathBuf, PathBuf, Option<PathBuf>)> {
+        if let Some(version) = self.release
+            && self.installer.installation(version).await.is_none()
+        {
+            bail!(
+                "Firecracker {version} is not installed in {}",
+                self.installer.root().display()
+            );
+        }
+        workspace.create()?;
+
+        let lockfile = match &self.lockfile {
+            Some(path) if !self.update_lockfile => Lockfile::read(path).await?.unwrap_or_default(),
+            _ => Lockfile::default(),
+        };
+        let kernel = self
+            .resolve(ArtifactKind::Kernel, &lockfile)
+            .await?
+            .ok_or_else(|| anyhow!("the artifact source has no kernel"))?;
+        let rootfs = self
+            .resolve(ArtifactKind::Rootfs, &lockfile)
+            .await?
+            .ok_or_else(|| anyhow!("the artifact source has no rootfs"))?;
+        let initrd = match &self.initrd {
+            Some(path) => Some(Artifact::local(path)),
+            None => self.resolve(ArtifactKind::Initrd, &lockfile).await?,
+        };
+        if let (Some(path), true) = (&self.lockfile, self.update_lockfile) {
+            let mut updated = Lockfile::default();
+            updated.set(ArtifactKind::Kernel, kernel.locked);
+            updated.set(ArtifactKind::Rootfs, rootfs.locked);
+            updated.set(
+                ArtifactKind::Initrd,
+                initrd.as_ref().and_then(|i| i.locked.clone()),
+            );
+            updated.write(path).await?;
+        }
+
+        let mut rootfs_path = rootfs.path;
+        // Cached artifacts are read-only and shared
+        if self.copy_rootfs || rootfs_path.starts_with(self.cache.root()) {
+            let copy = workspace.drive_path("rootfs", &rootfs_path);
+            tokio::fs::copy(&rootfs_path, &copy).await?;
+            tokio::fs::set_permissions(&copy, Permissions::from_mode(0o644)).await?;
+            rootfs_path = copy;
+        }
+        Ok((kernel.path, rootfs_path, initrd.map(|i| i.path)))
+    }
+
     /// Starts a VM with specified parameters
     /// Returns a structure for working with the Firecracker process
     pub async fn start(self) -> Result<FirecrackerProcess> {
-        let fs = FileManager::default();
-        let s3 = S3Downloader::default();
-        let kernel_path = fs.resolve_kernel_path(self.download_kernel, &s3).await?;
-        let rootfs_path = fs.resolve_rootfs_path(self.download_rootfs, &s3).await?;
+        FirecrackerProcess::new(self.configure().await?).await
+    }
+
+    /// Starts a VM with specified parameters
+    /// Returns a type-state handle of the VM, ready to be configured and booted
+    pub async fn launch(self) -> Result<Vm<Configuring>> {
+        Ok(Vm::new(self.start().await?))
+    }
 
-        let host_dev_name = "tap0".into();
+    /// Starts and boots a VM that is relaunched according to the restart policy
+    /// Returns a structure for working with the supervised VM
+    pub async fn supervise(self, policy: RestartPolicy) -> Result<Supervisor> {
+        Supervisor::start(self.configure().await?, policy).await
+    }
+
+    /// Prepares the workspace and artifacts and builds and validates the configuration of the VM
+    ///
+    /// The settings are validated before any artifact is downloaded, the artifact files once resolved.
+    async fn configure(self) -> Result<FirecrackerConfiguration> {
+        let workspace = self.resolve_workspace()?;
+        let api_socket = self
+            .api_socket
+            .clone()
+            .unwrap_or_else(|| workspace.api_socket());
+        let vsock = self
+            .vsock
+            .clone()
+            .unwrap_or_else(|| workspace.vsock_socket());
+
+        let host_dev_name = "tap0";
 
         // let _dev = DeviceBuilder::new()
         //     .name(&host_dev_name)
         //     .ipv4(tap_ip, "255.255.255.252", None)
         //     .build_async()?;
 
-        FirecrackerProcess::new(FirecrackerConfiguration {
+        let mut configuration = FirecrackerConfiguration {
             boot_source: BootSource {
-                kernel_image_path: kernel_path,
-                boot_args: "console=tty reboot=k panic=1 pci=off".into(),
+                kernel_image_path: PathBuf::new(),
+                initrd_path: None,
+                boot_args: Some("console=tty reboot=k panic=1 pci=off".into()),
             },
-            drives: Drives {
-                drive_id: "rootfs".into(),
-                path_on_host: rootfs_path,
+            machine_config: MachineConfiguration::new(self.vcpu_count, self.mem_size_mib),
+            drives: Drive {
                 is_root_device: true,
-                is_read_only: false,
+                rate_limiter: self.rootfs_rate_limiter,
+                ..Drive::new("rootfs", PathBuf::new())
             },
-            vsock: VSock {
-                vsock_id: "vsock0".into(),
+            vsock: Vsock {
+                vsock_id: Some("vsock0".into()),
                 guest_cid: 3,
-                uds_path: self.vsock.to_string_lossy().to_string(),
+                uds_path: vsock,
             },
-            network_interfaces: vec![NetInterface {
-                iface_id: "net1".into(),
-                guest_mac: "06:00:AC:10:00:02".into(),
+            network_interfaces: vec![NetworkInterface::new(
+                "net1",
+                "06:00:AC:10:00:02",
                 host_dev_name,
-            }],
+            )],
+            balloon: self.balloon,
+            mmds: self.mmds.clone(),
+            workspace,
+            api_socket,
             startup_config: self,
-        })
-        .await
+        };
+        let prepared = async {
+            configuration.validate_settings()?;
+            let (kernel_path, rootfs_path, initrd_path) = configuration
+                .startup_config
+                .prepare(&configuration.workspace)
+                .await?;
+            configuration.boot_source.kernel_image_path = kernel_path;
+            configuration.boot_source.initrd_path = initrd_path;
+            configuration.drives.path_on_host = Some(rootfs_path);
+            configuration.validate()?;
+            Ok::<_, anyhow::Error>(())
+        }
+        .await;
+        if let Err(e) = prepared {
+            if !configuration.startup_config.detached {
+                configuration.cleanup();
+            }
+            return Err(e);
+        }
+        Ok(configuration)
     }
 }
 
@@ -0,0 +1,382 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use tokio::sync::broadcast;
+
+use crate::{
+    domain::{
+        config::{ConfigDiff, FirecrackerConfiguration, ReconcileReport, SnapshotLoadOptions},
+        models::{FullVmConfiguration, InstanceInfo, NetworkInterface, SnapshotType},
+    },
+    infrastructure::process::{FirecrackerProcess, ProcessExit, events::VmEvent},
+};
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// State of a [`Vm`], only implemented by the states of this module
+pub trait VmState: sealed::Sealed {}
+
+/// The process is running and the VM can be configured before boot
+pub struct Configuring {
+    process: FirecrackerProcess,
+}
+
+/// The guest is running
+pub struct Running {
+    process: FirecrackerProcess,
+}
+
+/// The guest is paused
+pub struct Paused {
+    process: FirecrackerProcess,
+}
+
+/// The process has exited, only the post-mortem information is left
+pub struct Stopped {
+    configuration: FirecrackerConfiguration,
+    exit: ProcessExit,
+}
+
+impl sealed::Sealed for Configuring {}
+impl sealed::Sealed for Running {}
+impl sealed::Sealed for Paused {}
+impl sealed::Sealed for Stopped {}
+impl VmState for Configuring {}
+impl VmState for Running {}
+impl VmState for Paused {}
+impl VmState for Stopped {}
+
+/// A VM handle that only exposes the operations valid in its current state
+///
+/// Exemple:
+/// ```no_compile
+/// let vm = FirecrackerStartup::new()
+///     .launch().await?
+///     .boot_args("console=ttyS0 reboot=k panic=1 pci=off")
+///     .start().await?;
+/// let vm = vm.pause().await?;
+/// let stopped = vm.stop().await?;
+/// println!("{:?}", stopped.exit());
+/// ```
+pub struct Vm<S: VmState> {
+    state: S,
+}
+
+/// A failed state transition, which gives back the VM in the state it was in
+///
+/// Exemple:
+/// ```no_compile
+/// let vm = match vm.pause().await {
+///     Ok(paused) => paused.resume().await?,
+///     Err(e) => e.into_vm(),
+/// };
+/// ```
+pub struct TransitionError<S: VmState> {
+    vm: Vm<S>,
+    error: anyhow::Error,
+}
+
+impl<S: VmState> TransitionError<S> {
+    /// Returns the cause of the failure
+    pub fn error(&self) -> &anyhow::Error {
+        &self.error
+    }
+
+    /// Returns the VM, still in its previous state
+    pub fn into_vm(self) -> Vm<S> {
+        self.vm
+    }
+
+    pub fn into_parts(self) -> (Vm<S>, anyhow::Error) {
+        (self.vm, self.error)
+    }
+}
+
+impl<S: VmState> fmt::Debug for TransitionError<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TransitionError")
+            .field("error", &self.error)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: VmState> fmt::Display for TransitionError<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl<S: VmState> std::error::Error for TransitionError<S> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
+impl<S: VmState> Vm<S> {
+    /// Moves the VM into the next state, or gives it back with the error
+    fn transition<T: VmState>(
+        self,
+        res: Result<()>,
+        next: impl FnOnce(S) -> T,
+    ) -> Result<Vm<T>, TransitionError<S>> {
+        match res {
+            Ok(()) => Ok(Vm {
+                state: next(self.state),
+            }),
+            Err(error) => Err(TransitionError { vm: self, error }),
+        }
+    }
+}
+
+impl Vm<Configuring> {
+    pub(crate) fn new(process: FirecrackerProcess) -> Self {
+        Self {
+            state: Configuring { process },
+        }
+    }
+
+    pub fn config(&self) -> &FirecrackerConfiguration {
+        self.state.process.config()
+    }
+
+    /// Set the kernel command line
+    pub fn boot_args(mut self, args: impl Into<String>) -> Self {
+        self.state.process.config_mut().boot_source.boot_args = Some(args.into());
+        self
+    }
+
+    /// Attaches the rootfs read-only
+    pub fn read_only_rootfs(mut self, flag: bool) -> Self {
+        self.state.process.config_mut().drives.is_read_only = Some(flag);
+        self
+    }
+
+    /// Adds a network interface backed by the specified host tap device
+    pub fn network_interface(
+        mut self,
+        iface_id: impl Into<String>,
+        guest_mac: impl Into<String>,
+        host_dev_name: impl Into<String>,
+    ) -> Self {
+        self.state
+            .process
+            .config_mut()
+            .network_interfaces
+            .push(NetworkInterface::new(iface_id, guest_mac, host_dev_name));
+        self
+    }
+
+    /// Validates and applies the configuration and boots the guest
+    pub async fn start(self) -> Result<Vm<Running>, TransitionError<Configuring>> {
+        let res = match self.config().validate() {
+            Ok(()) => self.state.process.start_vm().await.map(drop),
+            Err(e) => Err(e.into()),
+        };
+        self.transition(res, |state| Running {
+            process: state.process,
+        })
+    }
+
+    /// Restores the guest from a snapshot instead of booting it, leaving it paused
+    pub async fn restore<P: AsRef<Path>>(
+        self,
+        snapshot_path: P,
+        mem_file_path: P,
+    ) -> Result<Vm<Paused>, TransitionError<Configuring>> {
+        let res = self
+            .state
+            .process
+            .load_snapshot(snapshot_path, mem_file_path, SnapshotLoadOptions::default())
+            .await;
+        self.transition(res, |state| Paused {
+            process: state.process,
+        })
+    }
+
+    /// Stops the process without booting the guest
+    pub async fn stop(self) -> Result<Vm<Stopped>> {
+        Vm::stopped(self.state.process).await
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<VmEvent> {
+        self.state.process.subscribe()
+    }
+}
+
+impl Vm<Running> {
+    pub fn config(&self) -> &FirecrackerConfiguration {
+        self.state.process.config()
+    }
+
+    /// Returns general information about the instance, `GET /`
+    pub async fn instance_info(&self) -> Result<InstanceInfo> {
+        self.state.process.instance_info().await
+    }
+
+    /// Returns the configuration Firecracker has actually applied, `GET /vm/config`
+    pub async fn vm_config(&self) -> Result<FullVmConfiguration> {
+        self.state.process.vm_config().await
+    }
+
+    /// Returns the OS identifier of the Firecracker process
+    pub fn pid(&self) -> Option<u32> {
+        self.state.process.pid()
+    }
+
+    /// Returns the exit of the process if it has already exited
+    pub fn try_wait(&self) -> Option<ProcessExit> {
+        self.state.process.try_wait()
+    }
+
+    /// Compares the desired configuration with the one applied by Firecracker
+    pub async fn diff(&mut self, desired: &FirecrackerConfiguration) -> Result<ConfigDiff> {
+        self.state.process.diff(desired).await
+    }
+
+    /// Applies the live changes of the desired configuration and reports the ones needing a restart
+    pub async fn reconcile(
+        &mut self,
+        desired: &FirecrackerConfiguration,
+    ) -> Result<ReconcileReport> {
+        self.state.process.reconcile(desired).await
+    }
+
+    /// Pauses the guest
+    pub async fn pause(self) -> Result<Vm<Paused>, TransitionError<Running>> {
+        let res = self.state.process.pause().await;
+        self.transition(res, |state| Paused {
+            process: state.process,
+        })
+    }
+
+    /// Pauses the guest, creates a snapshot and resumes the guest
+    pub async fn snapshot<P: AsRef<Path>>(
+        &mut self,
+        snapshot_type: SnapshotType,
+        snapshot_path: P,
+        mem_file_path: P,
+    ) -> Result<()> {
+        let process = &mut self.state.process;
+        process.pause().await?;
+        let created = process
+            .create_snapshot(snapshot_type, snapshot_path, mem_file_path)
+            .await;
+        process.resume().await?;
+        created
+    }
+
+    /// Pauses the guest, creates a snapshot in the snapshots directory of the workspace and resumes the guest
+    /// Returns the paths of the VM state and guest memory files, overwritten by the next snapshot
+    pub async fn snapshot_to_workspace(
+        &mut self,
+        snapshot_type: SnapshotType,
+    ) -> Result<(PathBuf, PathBuf)> {
+        let workspace = self.config().workspace();
+        let paths = (workspace.snapshot_file(), workspace.memory_file());
+        self.snapshot(snapshot_type, &paths.0, &paths.1).await?;
+        Ok(paths)
+    }
+
+    /// Waits for the guest to shut down or the process to exit
+    pub async fn wait(self) -> Result<Vm<Stopped>> {
+        self.state.process.wait().await?;
+        Vm::stopped(self.state.process).await
+    }
+
+    /// Stops the process
+    pub async fn stop(self) -> Result<Vm<Stopped>> {
+        Vm::stopped(self.state.process).await
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<VmEvent> {
+        self.state.process.subscribe()
+    }
+}
+
+impl Vm<Paused> {
+    pub fn config(&self) -> &FirecrackerConfiguration {
+        self.state.process.config()
+    }
+
+    /// Returns general information about the instance, `GET /`
+    pub async fn instance_info(&self) -> Result<InstanceInfo> {
+        self.state.process.instance_info().await
+    }
+
+    /// Returns the configuration Firecracker has actually applied, `GET /vm/config`
+    pub async fn vm_config(&self) -> Result<FullVmConfiguration> {
+        self.state.process.vm_config().await
+    }
+
+    /// Returns the OS identifier of the Firecracker process
+    pub fn pid(&self) -> Option<u32> {
+        self.state.process.pid()
+    }
+
+    /// Returns the exit of the process if it has already exited
+    pub fn try_wait(&self) -> Option<ProcessExit> {
+        self.state.process.try_wait()
+    }
+
+    /// Resumes the guest
+    pub async fn resume(self) -> Result<Vm<Running>, TransitionError<Paused>> {
+        let res = self.state.process.resume().await;
+        self.transition(res, |state| Running {
+            process: state.process,
+        })
+    }
+
+    /// Creates a snapshot of the paused guest
+    pub async fn create_snapshot<P: AsRef<Path>>(
+        &mut self,
+        snapshot_type: SnapshotType,
+        snapshot_path: P,
+        mem_file_path: P,
+    ) -> Result<()> {
+        self.state
+            .process
+            .create_snapshot(snapshot_type, snapshot_path, mem_file_path)
+            .await
+    }
+
+    /// Stops the process
+    pub async fn stop(self) -> Result<Vm<Stopped>> {
+        Vm::stopped(self.state.process).await
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<VmEvent> {
+        self.state.process.subscribe()
+    }
+}
+
+impl Vm<Stopped> {
+    async fn stopped(process: FirecrackerProcess) -> Result<Self> {
+        let configuration = process.config().clone();
+        let exit = process.exit_events();
+        process.stop().await?;
+        let exit = exit
+            .borrow()
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("Firecracker exit was not observed"))?;
+        Ok(Self {
+            state: Stopped {
+                configuration,
+                exit,
+            },
+        })
+    }
+
+    pub fn config(&self) -> &FirecrackerConfiguration {
+        &self.state.configuration
+    }
+
+    /// Returns how the process has exited
+    pub fn exit(&self) -> &ProcessExit {
+        &self.state.exit
+    }
+}
@@ -0,0 +1,156 @@
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+use anyhow::anyhow;
+use serde::Serialize;
+
+/// Oldest Firecracker release the SDK talks to
+pub const MIN_SUPPORTED_VERSION: Version = Version::new(1, 0, 0);
+
+/// Release of a Firecracker binary, as reported by `firecracker --version` or `GET /version`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl Version {
+    pub const fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Returns whether the release has the specified API feature
+    pub fn supports(&self, feature: Feature) -> bool {
+        *self >= feature.since()
+    }
+
+    /// Fails with a descriptive error if the release lacks the specified API feature
+    pub fn require(&self, feature: Feature) -> Result<(), UnsupportedFeature> {
+        match self.supports(feature) {
+            true => Ok(()),
+            false => Err(UnsupportedFeature {
+                feature,
+                version: *self,
+            }),
+        }
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl FromStr for Version {
+    type Err = anyhow::Error;
+
+    /// Parses `1.13.1`, `v1.13.1-dev` or the whole output of `firecracker --version`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |word: &str| {
+            let word = word.strip_prefix('v').unwrap_or(word);
+            let release = word.split(['-', '+']).next()?;
+            let mut numbers = release.split('.').map(|n| n.parse().ok());
+            let version = Self::new(numbers.next()??, numbers.next()??, numbers.next()??);
+            numbers.next().is_none().then_some(version)
+        };
+        s.split_whitespace()
+            .find_map(parse)
+            .ok_or_else(|| anyhow!("no Firecracker version found in {s:?}"))
+    }
+}
+
+/// API features that appeared after [`MIN_SUPPORTED_VERSION`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// `mem_backend` in `PUT /snapshot/load`, replacing `mem_file_path`
+    SnapshotMemBackend,
+    /// `network_overrides` in `PUT /snapshot/load`
+    SnapshotNetworkOverrides,
+    /// `track_dirty_pages` in `PUT /snapshot/load`, replacing `enable_diff_snapshots`
+    SnapshotTrackDirtyPages,
+}
+
+impl Feature {
+    /// Returns the first release with the feature
+    pub fn since(&self) -> Version {
+        match self {
+            Self::SnapshotMemBackend => Version::new(1, 1, 0),
+            Self::SnapshotNetworkOverrides => Version::new(1, 12, 0),
+            Self::SnapshotTrackDirtyPages => Version::new(1, 13, 0),
+        }
+    }
+}
+
+impl Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::SnapshotMemBackend => "snapshot load with a memory backend",
+            Self::SnapshotNetworkOverrides => "snapshot load with network overrides",
+            Self::SnapshotTrackDirtyPages => "snapshot load with dirty page tracking",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A feature was requested from a Firecracker release that does not have it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsupportedFeature {
+    pub feature: Feature,
+    pub version: Version,
+}
+
+impl Display for UnsupportedFeature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} requires Firecracker {} or newer, but {} is running",
+            self.feature,
+            self.feature.since(),
+            self.version
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedFeature {}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn parse_version_test() -> Result<()> {
+        assert_eq!("1.13.1".parse::<Version>()?, Version::new(1, 13, 1));
+        assert_eq!("v1.14.0-dev".parse::<Version>()?, Version::new(1, 14, 0));
+        assert_eq!(
+            "Firecracker v1.10.1\n\nSupported snapshot data format versions: v4.0.0"
+                .parse::<Version>()?,
+            Version::new(1, 10, 1)
+        );
+        assert!("Firecracker".parse::<Version>().is_err());
+        assert!("1.13".parse::<Version>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn require_feature_test() {
+        let version = Version::new(1, 10, 0);
+        assert!(version.supports(Feature::SnapshotMemBackend));
+        assert_eq!(
+            version
+                .require(Feature::SnapshotNetworkOverrides)
+                .unwrap_err()
+                .to_string(),
+            "snapshot load with network overrides requires Firecracker 1.12.0 or newer, but 1.10.0 is running"
+        );
+    }
+}
@@ -0,0 +1,486 @@
+use std::fmt::{self, Display};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::domain::{
+    config::FirecrackerConfiguration,
+    models::{FullVmConfiguration, RateLimiter},
+};
+
+/// The live update that fixes a drift without restarting the VM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum LiveFix {
+    /// `PATCH /drives/{drive_id}`
+    Drive(String),
+    /// `PATCH /network-interfaces/{iface_id}`
+    NetInterface(String),
+    /// `PATCH /balloon`
+    Balloon,
+    /// `PUT /mmds`
+    Mmds,
+}
+
+/// A field whose desired value differs from the one applied by Firecracker
+#[derive(Debug, Clone, PartialEq)]
+pub struct Drift {
+    pub field: String,
+    pub desired: Value,
+    pub actual: Value,
+    pub(crate) fix: Option<LiveFix>,
+}
+
+impl Drift {
+    /// Returns whether the field can be changed without restarting the VM
+    pub fn is_live(&self) -> bool {
+        self.fix.is_some()
+    }
+}
+
+impl Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: desired {}, actual {}",
+            self.field, self.desired, self.actual
+        )
+    }
+}
+
+/// Every difference between a desired configuration and the one applied by Firecracker
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigDiff {
+    drifts: Vec<Drift>,
+}
+
+impl ConfigDiff {
+    pub fn drifts(&self) -> &[Drift] {
+        &self.drifts
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.drifts.is_empty()
+    }
+
+    /// Returns the drifts that can be fixed while the VM is running
+    pub fn live(&self) -> impl Iterator<Item = &Drift> {
+        self.drifts.iter().filter(|d| d.is_live())
+    }
+
+    /// Returns the drifts that can only be fixed by restarting the VM
+    pub fn requires_restart(&self) -> impl Iterator<Item = &Drift> {
+        self.drifts.iter().filter(|d| !d.is_live())
+    }
+
+    /// Returns whether the specified field has drifted, e.g. `drives.rootfs.rate_limiter`
+    pub(crate) fn drifted(&self, field: &str) -> bool {
+        self.drifts.iter().any(|d| d.field == field)
+    }
+
+    /// Returns the live updates to apply, once per device
+    pub(crate) fn fixes(&self) -> Vec<LiveFix> {
+        let mut fixes = vec![];
+        for fix in self.drifts.iter().filter_map(|d| d.fix.as_ref()) {
+            if !fixes.contains(fix) {
+                fixes.push(fix.clone());
+            }
+        }
+        fixes
+    }
+}
+
+/// Outcome of `FirecrackerProcess::reconcile`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReconcileReport {
+    pub(crate) applied: Vec<Drift>,
+    pub(crate) requires_restart: Vec<Drift>,
+}
+
+impl ReconcileReport {
+    /// Returns the drifts fixed by live updates
+    pub fn applied(&self) -> &[Drift] {
+        &self.applied
+    }
+
+    /// Returns the drifts left in place because they need a restart of the VM
+    pub fn requires_restart(&self) -> &[Drift] {
+        &self.requires_restart
+    }
+}
+
+#[derive(Default)]
+struct Differ {
+    drifts: Vec<Drift>,
+}
+
+impl Differ {
+    fn check<T: Serialize + PartialEq>(
+        &mut self,
+        field: impl Into<String>,
+        desired: T,
+        actual: T,
+        fix: Option<LiveFix>,
+    ) {
+        if desired != actual {
+            self.drifts.push(Drift {
+                field: field.into(),
+                desired: serde_json::to_value(desired).unwrap_or_default(),
+                actual: serde_json::to_value(actual).unwrap_or_default(),
+                fix,
+            });
+        }
+    }
+
+    fn limiter(
+        &mut self,
+        field: String,
+        desired: Option<&RateLimiter>,
+        actual: Option<&RateLimiter>,
+        fix: LiveFix,
+    ) {
+        self.check(
+            field,
+            RateLimiter::normalized(desired),
+            RateLimiter::normalized(actual),
+            Some(fix),
+        );
+    }
+}
+
+impl FirecrackerConfiguration {
+    /// Compares the configuration with the one applied by Firecracker, as returned by `GET /vm/config`
+    ///
+    /// `mmds` is the content of the MMDS data store, only compared if the configuration sets it.
+    pub fn diff(&self, actual: &FullVmConfiguration, mmds: Option<&Value>) -> ConfigDiff {
+        let mut differ = Differ::default();
+
+        let boot = actual.boot_source.as_ref();
+        differ.check(
+            "boot_source.kernel_image_path",
+            Some(&self.boot_source.kernel_image_path),
+            boot.map(|b| &b.kernel_image_path),
+            None,
+        );
+        differ.check(
+            "boot_source.initrd_path",
+            self.boot_source.initrd_path.as_ref(),
+            boot.and_then(|b| b.initrd_path.as_ref()),
+            None,
+        );
+        differ.check(
+            "boot_source.boot_args",
+            self.boot_source.boot_args.as_deref(),
+            boot.and_then(|b| b.boot_args.as_deref()),
+            None,
+        );
+
+        let machine = actual.machine_config.as_ref();
+        differ.check(
+            "machine_config.vcpu_count",
+            Some(self.machine_config.vcpu_count),
+            machine.map(|m| m.vcpu_count),
+            None,
+        );
+        differ.check(
+            "machine_config.mem_size_mib",
+            Some(self.machine_config.mem_size_mib),
+            machine.map(|m| m.mem_size_mib),
+            None,
+        );
+
+        let drive = &self.drives;
+        let id = &drive.drive_id;
+        match actual.drives.iter().find(|d| &d.drive_id == id) {
+            Some(applied) => {
+                let fix = LiveFix::Drive(id.clone());
+                differ.check(
+                    format!("drives.{id}.path_on_host"),
+                    drive.path_on_host.as_ref(),
+                    applied.path_on_host.as_ref(),
+                    Some(fix.clone()),
+                );
+                differ.limiter(
+                    format!("drives.{id}.rate_limiter"),
+                    drive.rate_limiter.as_ref(),
+                    applied.rate_limiter.as_ref(),
+                    fix,
+                );
+                differ.check(
+                    format!("drives.{id}.is_root_device"),
+                    drive.is_root_device,
+                    applied.is_root_device,
+                    None,
+                );
+                differ.check(
+                    format!("drives.{id}.is_read_only"),
+                    drive.is_read_only.unwrap_or_default(),
+                    applied.is_read_only.unwrap_or_default(),
+                    None,
+                );
+            }
+            None => differ.check(format!("drives.{id}"), Some(id), None, None),
+        }
+        for applied in actual.drives.iter().filter(|d| &d.drive_id != id) {
+            differ.check(
+                format!("drives.{}", applied.drive_id),
+                None,
+                Some(&applied.drive_id),
+                None,
+            );
+        }
+
+        for inet in &self.network_interfaces {
+            let id = &inet.iface_id;
+            let Some(applied) = actual.network_interfaces.iter().find(|i| &i.iface_id == id) else {
+                differ.check(format!("network_interfaces.{id}"), Some(id), None, None);
+                continue;
+            };
+            differ.check(
+                format!("network_interfaces.{id}.host_dev_name"),
+                &inet.host_dev_name,
+                &applied.host_dev_name,
+                None,
+            );
+            // Firecracker reports MAC addresses in lowercase
+            differ.check(
+                format!("network_interfaces.{id}.guest_mac"),
+                inet.guest_mac.as_ref().map(|m| m.to_ascii_lowercase()),
+                applied.guest_mac.as_ref().map(|m| m.to_ascii_lowercase()),
+                None,
+            );
+            let fix = LiveFix::NetInterface(id.clone());
+            differ.limiter(
+                format!("network_interfaces.{id}.rx_rate_limiter"),
+                inet.rx_rate_limiter.as_ref(),
+                applied.rx_rate_limiter.as_ref(),
+                fix.clone(),
+            );
+            differ.limiter(
+                format!("network_interfaces.{id}.tx_rate_limiter"),
+                inet.tx_rate_limiter.as_ref(),
+                applied.tx_rate_limiter.as_ref(),
+                fix,
+            );
+        }
+        for applied in &actual.network_interfaces {
+            if !self
+                .network_interfaces
+                .iter()
+                .any(|i| i.iface_id == applied.iface_id)
+            {
+                differ.check(
+                    format!("network_interfaces.{}", applied.iface_id),
+                    None,
+                    Some(&applied.iface_id),
+                    None,
+                );
+            }
+        }
+
+        let vsock = actual.vsock.as_ref();
+        differ.check(
+            "vsock.guest_cid",
+            Some(self.vsock.guest_cid),
+            vsock.map(|v| v.guest_cid),
+            None,
+        );
+        differ.check(
+            "vsock.uds_path",
+            Some(&self.vsock.uds_path),
+            vsock.map(|v| &v.uds_path),
+            None,
+        );
+
+        match (&self.balloon, &actual.balloon) {
+            (Some(desired), Some(applied)) => {
+                differ.check(
+                    "balloon.amount_mib",
+                    desired.amount_mib,
+                    applied.amount_mib,
+                    Some(LiveFix::Balloon),
+                );
+                differ.check(
+                    "balloon.deflate_on_oom",
+                    desired.deflate_on_oom,
+                    applied.deflate_on_oom,
+                    None,
+                );
+                differ.check(
+                    "balloon.stats_polling_interval_s",
+                    desired.stats_polling_interval_s,
+                    applied.stats_polling_interval_s,
+                    None,
+                );
+            }
+            (desired, applied) => differ.check("balloon", desired, applied, None),
+        }
+
+        if let Some(desired) = &self.mmds {
+            differ.check(
+                "mmds",
+                desired,
+                mmds.unwrap_or(&Value::Null),
+                Some(LiveFix::Mmds),
+            );
+        }
+
+        ConfigDiff {
+            drifts: differ.drifts,
+        }
+    }
+
+    /// Takes over the fields of `desired` changed by a live update
+    pub(crate) fn adopt(&mut self, desired: &Self, fix: &LiveFix) {
+        match fix {
+            LiveFix::Drive(_) => {
+                self.drives.path_on_host = desired.drives.path_on_host.clone();
+                self.drives.rate_limiter = desired.drives.rate_limiter;
+            }
+            LiveFix::NetInterface(id) => {
+                let find = |config: &Self| {
+                    config
+                        .network_interfaces
+                        .iter()
+                        .position(|i| &i.iface_id == id)
+                };
+                if let (Some(current), Some(wanted)) = (find(self), find(desired)) {
+                    let wanted = &desired.network_interfaces[wanted];
+                    let current = &mut self.network_interfaces[current];
+                    current.rx_rate_limiter = wanted.rx_rate_limiter;
+                    current.tx_rate_limiter = wanted.tx_rate_limiter;
+                }
+            }
+            LiveFix::Balloon => {
+                if let (Some(current), Some(wanted)) = (&mut self.balloon, &desired.balloon) {
+                    current.amount_mib = wanted.amount_mib;
+                }
+            }
+            LiveFix::Mmds => self.mmds = desired.mmds.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        api::startup::FirecrackerStartup,
+        domain::models::{
+            Balloon, BootSource, Drive, MachineConfiguration, NetworkInterface, TokenBucket, Vsock,
+        },
+        infrastructure::workspace::VmWorkspace,
+    };
+
+    fn configuration() -> FirecrackerConfiguration {
+        FirecrackerConfiguration {
+            startup_config: FirecrackerStartup::new(),
+            boot_source: BootSource {
+                kernel_image_path: "/tmp/vmlinux.bin".into(),
+                initrd_path: None,
+                boot_args: Some("console=ttyS0".into()),
+            },
+            machine_config: MachineConfiguration::new(2, 256),
+            drives: Drive {
+                is_root_device: true,
+                ..Drive::new("rootfs", "/tmp/rootfs.ext4")
+            },
+            vsock: Vsock {
+                vsock_id: Some("vsock0".into()),
+                guest_cid: 3,
+                uds_path: "/tmp/vsock.socket".into(),
+            },
+            network_interfaces: vec![NetworkInterface::new("net1", "06:00:AC:10:00:02", "tap0")],
+            balloon: Some(Balloon {
+                amount_mib: 0,
+                deflate_on_oom: true,
+                stats_polling_interval_s: 0,
+            }),
+            mmds: None,
+            workspace: VmWorkspace::new("/tmp/vm0"),
+            api_socket: "/tmp/firecracker.socket".into(),
+        }
+    }
+
+    fn applied() -> Result<FullVmConfiguration> {
+        Ok(serde_json::from_value(json!({
+            "balloon": {"amount_mib": 0, "deflate_on_oom": true, "stats_polling_interval_s": 0},
+            "drives": [{
+                "drive_id": "rootfs",
+                "is_root_device": true,
+                "is_read_only": false,
+                "path_on_host": "/tmp/rootfs.ext4",
+                "rate_limiter": {"bandwidth": null, "ops": null}
+            }],
+            "boot-source": {"kernel_image_path": "/tmp/vmlinux.bin", "boot_args": "console=ttyS0"},
+            "machine-config": {"vcpu_count": 2, "mem_size_mib": 256},
+            "network-interfaces": [{
+                "iface_id": "net1",
+                "host_dev_name": "tap0",
+                "guest_mac": "06:00:ac:10:00:02"
+            }],
+            "vsock": {"guest_cid": 3, "uds_path": "/tmp/vsock.socket", "vsock_id": "vsock0"}
+        }))?)
+    }
+
+    #[test]
+    fn no_drift_test() -> Result<()> {
+        assert!(configuration().diff(&applied()?, None).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn drift_test() -> Result<()> {
+        let limiter = RateLimiter {
+            bandwidth: Some(TokenBucket {
+                size: 1024,
+                one_time_burst: None,
+                refill_time: 100,
+            }),
+            ops: None,
+        };
+        let mut desired = configuration()
+            .set_drive_path("/tmp/other.ext4")
+            .drive_rate_limiter(Some(limiter))
+            .network_rate_limiters("net1", None, Some(limiter))
+            .balloon_amount_mib(64)
+            .mmds(json!({"latest": {"meta-data": {}}}));
+        desired.machine_config.vcpu_count = 4;
+        desired.network_interfaces[0].host_dev_name = "tap1".into();
+
+        let diff = desired.diff(&applied()?, Some(&json!({})));
+        let live: Vec<_> = diff.live().map(|d| d.field.as_str()).collect();
+        assert_eq!(
+            live,
+            [
+                "drives.rootfs.path_on_host",
+                "drives.rootfs.rate_limiter",
+                "network_interfaces.net1.tx_rate_limiter",
+                "balloon.amount_mib",
+                "mmds",
+            ]
+        );
+        let restart: Vec<_> = diff.requires_restart().map(|d| d.field.as_str()).collect();
+        assert_eq!(
+            restart,
+            [
+                "machine_config.vcpu_count",
+                "network_interfaces.net1.host_dev_name"
+            ]
+        );
+        assert_eq!(
+            diff.fixes(),
+            [
+                LiveFix::Drive("rootfs".into()),
+                LiveFix::NetInterface("net1".into()),
+                LiveFix::Balloon,
+                LiveFix::Mmds,
+            ]
+        );
+        assert!(diff.drifted("network_interfaces.net1.tx_rate_limiter"));
+        assert!(!diff.drifted("network_interfaces.net1.rx_rate_limiter"));
+        Ok(())
+    }
+}
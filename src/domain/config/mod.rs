@@ -1,16 +1,43 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use serde::Serialize;
+use serde_json::Value;
 
-use crate::api::startup::FirecrackerStartup;
+use crate::{
+    api::startup::FirecrackerStartup,
+    domain::{
+        compat::{Feature, UnsupportedFeature, Version},
+        models::{
+            Balloon, BootSource, Drive, MachineConfiguration, MemoryBackend, MemoryBackendType,
+            NetworkInterface, NetworkOverride, RateLimiter, SnapshotLoadParams, Vsock,
+        },
+    },
+    infrastructure::workspace::VmWorkspace,
+};
 
-#[derive(Serialize)]
+mod diff;
+mod validation;
+
+pub(crate) use diff::LiveFix;
+pub use diff::{ConfigDiff, Drift, ReconcileReport};
+pub use validation::{ValidationErrors, Violation};
+
+pub use crate::domain::models::{ActionType, SnapshotType};
+
+#[derive(Clone, Serialize)]
 pub struct FirecrackerConfiguration {
     pub(crate) startup_config: FirecrackerStartup,
     pub(crate) boot_source: BootSource,
-    pub(crate) drives: Drives,
-    pub(crate) vsock: VSock,
-    pub(crate) network_interfaces: Vec<NetInterface>,
+    pub(crate) machine_config: MachineConfiguration,
+    pub(crate) drives: Drive,
+    pub(crate) vsock: Vsock,
+    pub(crate) network_interfaces: Vec<NetworkInterface>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) balloon: Option<Balloon>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) mmds: Option<Value>,
+    pub(crate) workspace: VmWorkspace,
+    pub(crate) api_socket: PathBuf,
 }
 
 impl FirecrackerConfiguration {
@@ -19,44 +46,209 @@ impl FirecrackerConfiguration {
     }
 
     pub fn drive_path(&self) -> PathBuf {
-        self.drives.path_on_host.clone()
+        self.drives.path_on_host.clone().unwrap_or_default()
     }
-}
 
-#[derive(Serialize)]
-pub(crate) struct BootSource {
-    pub(crate) kernel_image_path: PathBuf,
-    pub(crate) boot_args: String,
-}
+    pub fn boot_source(&self) -> &BootSource {
+        &self.boot_source
+    }
 
-#[derive(Serialize)]
-pub(crate) struct Drives {
-    pub(crate) drive_id: String,
-    pub(crate) path_on_host: PathBuf,
-    pub(crate) is_root_device: bool,
-    pub(crate) is_read_only: bool,
-}
+    pub fn machine_config(&self) -> &MachineConfiguration {
+        &self.machine_config
+    }
+
+    /// Returns the rootfs drive
+    pub fn drive(&self) -> &Drive {
+        &self.drives
+    }
+
+    pub fn vsock(&self) -> &Vsock {
+        &self.vsock
+    }
+
+    pub fn network_interfaces(&self) -> &[NetworkInterface] {
+        &self.network_interfaces
+    }
+
+    pub fn get_balloon(&self) -> Option<&Balloon> {
+        self.balloon.as_ref()
+    }
+
+    pub fn get_mmds(&self) -> Option<&Value> {
+        self.mmds.as_ref()
+    }
+
+    /// Returns the workspace of the VM
+    pub fn workspace(&self) -> &VmWorkspace {
+        &self.workspace
+    }
+
+    /// Returns the path of the API socket
+    pub fn api_socket(&self) -> &Path {
+        &self.api_socket
+    }
+
+    /// Removes the API and vsock sockets left by a previous process
+    pub(crate) fn remove_sockets(&self) {
+        for path in [&self.api_socket, &self.vsock.uds_path] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    /// Removes the sockets, which may live outside of the workspace, and applies the workspace cleanup policy
+    pub(crate) fn cleanup(&self) {
+        self.workspace
+            .cleanup_with_sockets([&self.api_socket, &self.vsock.uds_path]);
+    }
+
+    /// Set the host file backing the rootfs
+    pub fn set_drive_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.drives.path_on_host = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set the rate limiter of the rootfs, `None` removes it
+    pub fn drive_rate_limiter(mut self, limiter: Option<RateLimiter>) -> Self {
+        self.drives.rate_limiter = limiter;
+        self
+    }
+
+    /// Set the receive and transmit rate limiters of a network interface, `None` removes them
+    pub fn network_rate_limiters(
+        mut self,
+        iface_id: &str,
+        rx: Option<RateLimiter>,
+        tx: Option<RateLimiter>,
+    ) -> Self {
+        if let Some(inet) = self
+            .network_interfaces
+            .iter_mut()
+            .find(|inet| inet.iface_id == iface_id)
+        {
+            inet.rx_rate_limiter = rx;
+            inet.tx_rate_limiter = tx;
+        }
+        self
+    }
 
-#[derive(Serialize)]
-pub(crate) struct VSock {
-    pub(crate) vsock_id: String,
-    pub(crate) guest_cid: usize,
-    pub(crate) uds_path: String,
+    /// Set the memory balloon device, `None` removes it
+    pub fn balloon(mut self, balloon: Option<Balloon>) -> Self {
+        self.balloon = balloon;
+        self
+    }
+
+    /// Set the target size of the memory balloon
+    pub fn balloon_amount_mib(mut self, amount_mib: u32) -> Self {
+        if let Some(balloon) = &mut self.balloon {
+            balloon.amount_mib = amount_mib;
+        }
+        self
+    }
+
+    /// Set the content of the MMDS data store
+    pub fn mmds(mut self, data: Value) -> Self {
+        self.mmds = Some(data);
+        self
+    }
 }
 
-#[derive(Serialize)]
-pub(crate) struct NetInterface {
-    pub(crate) iface_id: String,
-    pub(crate) guest_mac: String,
-    pub(crate) host_dev_name: String,
+/// Options of `FirecrackerProcess::load_snapshot`
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotLoadOptions {
+    /// Resume the guest once the snapshot is loaded
+    pub resume_vm: bool,
+    /// Track dirty pages to allow diff snapshots of the restored guest
+    pub track_dirty_pages: bool,
+    /// Host devices replacing the ones of the snapshotted network interfaces
+    pub network_overrides: Vec<NetworkOverride>,
 }
 
-#[derive(Serialize)]
-pub struct Action {
-    pub(crate) action_type: ActionType,
+impl SnapshotLoadOptions {
+    /// Returns the body of `PUT /snapshot/load`, shaped after the running Firecracker release
+    pub(crate) fn params(
+        self,
+        version: Version,
+        snapshot_path: PathBuf,
+        mem_file_path: PathBuf,
+    ) -> Result<SnapshotLoadParams, UnsupportedFeature> {
+        if !self.network_overrides.is_empty() {
+            version.require(Feature::SnapshotNetworkOverrides)?;
+        }
+        let (mem_file_path, mem_backend) = match version.supports(Feature::SnapshotMemBackend) {
+            true => (
+                None,
+                Some(MemoryBackend {
+                    backend_type: MemoryBackendType::File,
+                    backend_path: mem_file_path,
+                }),
+            ),
+            false => (Some(mem_file_path), None),
+        };
+        let (enable_diff_snapshots, track_dirty_pages) =
+            match version.supports(Feature::SnapshotTrackDirtyPages) {
+                true => (None, Some(self.track_dirty_pages)),
+                false => (Some(self.track_dirty_pages), None),
+            };
+        Ok(SnapshotLoadParams {
+            snapshot_path,
+            mem_file_path,
+            mem_backend,
+            enable_diff_snapshots,
+            track_dirty_pages,
+            resume_vm: Some(self.resume_vm),
+            network_overrides: self.network_overrides,
+        })
+    }
 }
 
-#[derive(Serialize)]
-pub enum ActionType {
-    InstanceStart,
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn snapshot_load_versions_test() -> Result<()> {
+        let load = |version| {
+            SnapshotLoadOptions {
+                resume_vm: true,
+                ..Default::default()
+            }
+            .params(version, "/tmp/snapshot".into(), "/tmp/memory".into())
+        };
+        assert_eq!(
+            serde_json::to_value(load(Version::new(1, 0, 0))?)?,
+            json!({
+                "snapshot_path": "/tmp/snapshot",
+                "mem_file_path": "/tmp/memory",
+                "enable_diff_snapshots": false,
+                "resume_vm": true
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(load(Version::new(1, 13, 1))?)?,
+            json!({
+                "snapshot_path": "/tmp/snapshot",
+                "mem_backend": {"backend_type": "File", "backend_path": "/tmp/memory"},
+                "track_dirty_pages": false,
+                "resume_vm": true
+            })
+        );
+
+        let overrides = SnapshotLoadOptions {
+            network_overrides: vec![NetworkOverride {
+                iface_id: "net1".into(),
+                host_dev_name: "tap1".into(),
+            }],
+            ..Default::default()
+        }
+        .params(
+            Version::new(1, 10, 0),
+            "/tmp/snapshot".into(),
+            "/tmp/memory".into(),
+        );
+        assert!(overrides.is_err());
+        Ok(())
+    }
 }
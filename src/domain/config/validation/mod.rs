@@ -0,0 +1,276 @@
+use std::{
+    collections::HashSet,
+    fmt::{self, Display},
+    fs::File,
+    path::Path,
+};
+
+use crate::{
+    domain::config::FirecrackerConfiguration, infrastructure::workspace::MAX_SOCKET_PATH_LEN,
+};
+
+/// Maximum number of vCPUs supported by Firecracker
+const MAX_VCPUS: u8 = 32;
+/// Context identifiers 0-2 are reserved for the hypervisor and the host
+const MIN_GUEST_CID: u32 = 3;
+/// `VMADDR_CID_ANY`, a wildcard that cannot identify a guest
+const CID_ANY: u32 = u32::MAX;
+
+/// A single problem found in a `FirecrackerConfiguration`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub field: String,
+    pub message: String,
+}
+
+/// Every problem found in a `FirecrackerConfiguration` before launching the VM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationErrors {
+    violations: Vec<Violation>,
+}
+
+impl ValidationErrors {
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+}
+
+impl Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid Firecracker configuration:")?;
+        for violation in &self.violations {
+            write!(f, "\n  {}: {}", violation.field, violation.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+impl FirecrackerConfiguration {
+    /// Checks the configuration before launching the VM and returns all violations at once
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        self.check(true)
+    }
+
+    /// Checks everything but the kernel, initrd and drive files, which may not be downloaded yet
+    pub(crate) fn validate_settings(&self) -> Result<(), ValidationErrors> {
+        self.check(false)
+    }
+
+    fn check(&self, files: bool) -> Result<(), ValidationErrors> {
+        let mut violations = vec![];
+        let mut violation = |field: &str, message: String| {
+            violations.push(Violation {
+                field: field.into(),
+                message,
+            })
+        };
+
+        if files && let Err(message) = check_readable(&self.boot_source.kernel_image_path) {
+            violation("boot_source.kernel_image_path", message);
+        }
+        if let Some(initrd) = &self.boot_source.initrd_path
+            && files
+            && let Err(message) = check_readable(initrd)
+        {
+            violation("boot_source.initrd_path", message);
+        }
+        match &self.drives.path_on_host {
+            Some(path) => {
+                if files && let Err(message) = check_readable(path) {
+                    violation("drives.path_on_host", message);
+                }
+            }
+            None if self.drives.socket.is_none() => violation(
+                "drives.path_on_host",
+                "a backing file or socket is required".into(),
+            ),
+            None => {}
+        }
+
+        let mut macs = HashSet::new();
+        for inet in &self.network_interfaces {
+            let Some(mac) = &inet.guest_mac else {
+                continue;
+            };
+            let field = format!("network_interfaces.{}.guest_mac", inet.iface_id);
+            if !is_mac_address(mac) {
+                violation(&field, format!("{mac:?} is not a MAC address"));
+            } else if !macs.insert(mac.to_ascii_lowercase()) {
+                violation(&field, format!("{mac} is used twice"));
+            }
+        }
+
+        if self.vsock.guest_cid < MIN_GUEST_CID {
+            violation(
+                "vsock.guest_cid",
+                format!(
+                    "{} is reserved, at least {MIN_GUEST_CID} is required",
+                    self.vsock.guest_cid
+                ),
+            );
+        } else if self.vsock.guest_cid == CID_ANY {
+            violation(
+                "vsock.guest_cid",
+                format!("{CID_ANY} is VMADDR_CID_ANY and cannot identify a guest"),
+            );
+        }
+
+        if !(1..=MAX_VCPUS).contains(&self.machine_config.vcpu_count) {
+            violation(
+                "machine_config.vcpu_count",
+                format!(
+                    "{} is outside of 1..={MAX_VCPUS}",
+                    self.machine_config.vcpu_count
+                ),
+            );
+        }
+        if self.machine_config.mem_size_mib == 0 {
+            violation("machine_config.mem_size_mib", "must be positive".into());
+        }
+
+        for (field, path) in [
+            ("api_socket", self.api_socket.as_os_str()),
+            ("vsock.uds_path", self.vsock.uds_path.as_os_str()),
+        ] {
+            if path.len() > MAX_SOCKET_PATH_LEN {
+                violation(
+                    field,
+                    format!(
+                        "{} bytes do not fit into sun_path, at most {MAX_SOCKET_PATH_LEN} are allowed",
+                        path.len()
+                    ),
+                );
+            }
+        }
+
+        match violations.is_empty() {
+            true => Ok(()),
+            false => Err(ValidationErrors { violations }),
+        }
+    }
+}
+
+fn check_readable(path: &Path) -> Result<(), String> {
+    if !path.is_file() {
+        return Err(format!(
+            "{} does not exist or is not a file",
+            path.display()
+        ));
+    }
+    File::open(path)
+        .map(|_| ())
+        .map_err(|e| format!("{} is not readable: {e}", path.display()))
+}
+
+fn is_mac_address(mac: &str) -> bool {
+    let octets: Vec<_> = mac.split(':').collect();
+    octets.len() == 6
+        && octets
+            .iter()
+            .all(|o| o.len() == 2 && o.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{
+        api::startup::FirecrackerStartup,
+        domain::models::{BootSource, Drive, MachineConfiguration, NetworkInterface, Vsock},
+        infrastructure::workspace::VmWorkspace,
+    };
+
+    fn configuration(kernel: &Path, rootfs: &Path) -> FirecrackerConfiguration {
+        FirecrackerConfiguration {
+            startup_config: FirecrackerStartup::new(),
+            boot_source: BootSource {
+                kernel_image_path: kernel.to_path_buf(),
+                initrd_path: None,
+                boot_args: None,
+            },
+            machine_config: MachineConfiguration::new(1, 128),
+            drives: Drive {
+                is_root_device: true,
+                ..Drive::new("rootfs", rootfs)
+            },
+            vsock: Vsock {
+                vsock_id: Some("vsock0".into()),
+                guest_cid: 3,
+                uds_path: "/tmp/vsock.socket".into(),
+            },
+            network_interfaces: vec![NetworkInterface::new("net1", "06:00:AC:10:00:02", "tap0")],
+            balloon: None,
+            mmds: None,
+            workspace: VmWorkspace::new("/tmp/vm0"),
+            api_socket: "/tmp/firecracker.socket".into(),
+        }
+    }
+
+    #[test]
+    fn valid_configuration_test() -> Result<()> {
+        let dir = tempdir()?;
+        let kernel = dir.path().join("vmlinux.bin");
+        let rootfs = dir.path().join("rootfs.ext4");
+        std::fs::write(&kernel, b"")?;
+        std::fs::write(&rootfs, b"")?;
+
+        assert_eq!(configuration(&kernel, &rootfs).validate(), Ok(()));
+        Ok(())
+    }
+
+    #[test]
+    fn aggregated_violations_test() {
+        let mut config = configuration(Path::new("/missing/kernel"), Path::new("/missing/rootfs"));
+        config
+            .network_interfaces
+            .push(NetworkInterface::new("net2", "06:00:ac:10:00:02", "tap1"));
+        config
+            .network_interfaces
+            .push(NetworkInterface::new("net3", "06:00:AC:10:00", "tap2"));
+        config.vsock.guest_cid = 2;
+        config.vsock.uds_path = format!("/tmp/{}", "a".repeat(120)).into();
+        config.machine_config.vcpu_count = 0;
+        config.machine_config.mem_size_mib = 0;
+
+        let fields: Vec<_> = config
+            .validate()
+            .unwrap_err()
+            .violations()
+            .iter()
+            .map(|v| v.field.clone())
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                "boot_source.kernel_image_path",
+                "drives.path_on_host",
+                "network_interfaces.net2.guest_mac",
+                "network_interfaces.net3.guest_mac",
+                "vsock.guest_cid",
+                "machine_config.vcpu_count",
+                "machine_config.mem_size_mib",
+                "vsock.uds_path",
+            ]
+        );
+    }
+
+    #[test]
+    fn guest_cid_test() {
+        let mut config = configuration(Path::new("/missing/kernel"), Path::new("/missing/rootfs"));
+        // Files are left to `validate`, once the artifacts are resolved
+        assert_eq!(config.validate_settings(), Ok(()));
+        for (cid, valid) in [
+            (2, false),
+            (3, true),
+            (u32::MAX - 1, true),
+            (u32::MAX, false),
+        ] {
+            config.vsock.guest_cid = cid;
+            assert_eq!(config.validate_settings().is_ok(), valid, "cid {cid}");
+        }
+    }
+}
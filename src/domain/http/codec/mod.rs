@@ -0,0 +1,420 @@
+use std::collections::HashMap;
+
+use anyhow::{Result, anyhow, bail};
+use http::{Method, StatusCode};
+
+use crate::domain::http::Http;
+
+/// Largest accepted start line and headers, protecting against a peer that never ends them
+const MAX_HEAD_LEN: usize = 64 * 1024;
+/// Largest accepted message body
+const MAX_BODY_LEN: usize = 16 * 1024 * 1024;
+
+/// How the end of a message body is found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    Length(usize),
+    Chunked,
+    /// The body ends when the peer closes the connection, only valid for responses
+    UntilClose,
+}
+
+struct Head {
+    start: Start,
+    header_map: HashMap<String, String>,
+    /// Length of the start line, headers and the empty line
+    len: usize,
+}
+
+enum Start {
+    Request { method: Method, path: String },
+    Response { code: StatusCode },
+}
+
+/// Incremental decoder of HTTP/1.1 requests and responses received over a byte stream
+///
+/// Bytes are appended as they arrive, complete messages are taken out one by one,
+/// so partial reads and pipelined messages are both handled.
+#[derive(Debug, Default)]
+pub struct HttpDecoder {
+    buf: Vec<u8>,
+    /// Chunked body of the message being received
+    chunked: Option<Chunked>,
+}
+
+impl HttpDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends received bytes
+    pub fn extend(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Returns the buffer to read into directly
+    pub(crate) fn buffer(&mut self) -> &mut Vec<u8> {
+        &mut self.buf
+    }
+
+    /// Returns `true` if no bytes of a next message are buffered
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Takes the next complete message, or returns `None` if more bytes are needed
+    pub fn decode(&mut self) -> Result<Option<Http>> {
+        let Some(head) = parse_head(&self.buf)? else {
+            return Ok(None);
+        };
+        let body = &self.buf[head.len..];
+        let (body, consumed) = match framing(&head)? {
+            Framing::Length(len) if len > MAX_BODY_LEN => {
+                bail!("HTTP body exceeds {MAX_BODY_LEN} bytes")
+            }
+            Framing::Length(len) if body.len() >= len => (body[..len].to_vec(), len),
+            Framing::UntilClose if body.len() > MAX_BODY_LEN => {
+                bail!("HTTP body exceeds {MAX_BODY_LEN} bytes")
+            }
+            Framing::Length(_) | Framing::UntilClose => return Ok(None),
+            Framing::Chunked => {
+                let chunked = self.chunked.get_or_insert_default();
+                match chunked.advance(body)? {
+                    Some(consumed) => (std::mem::take(&mut chunked.body), consumed),
+                    None => return Ok(None),
+                }
+            }
+        };
+        self.chunked = None;
+        self.buf.drain(..head.len + consumed);
+        Ok(Some(message(head, body)))
+    }
+
+    /// Takes the message ended by the closed connection, once `decode` has returned `None`
+    ///
+    /// Returns `None` if the connection was closed between two messages.
+    pub fn decode_eof(&mut self) -> Result<Option<Http>> {
+        if self.buf.is_empty() {
+            return Ok(None);
+        }
+        if let Some(message) = self.decode()? {
+            return Ok(Some(message));
+        }
+        let head = parse_head(&self.buf)?
+            .ok_or_else(|| anyhow!("connection closed in the middle of the HTTP headers"))?;
+        if framing(&head)? != Framing::UntilClose {
+            bail!("connection closed in the middle of the HTTP body");
+        }
+        let body = self.buf.split_off(head.len);
+        self.buf.clear();
+        Ok(Some(message(head, body)))
+    }
+}
+
+fn message(head: Head, body: Vec<u8>) -> Http {
+    match head.start {
+        Start::Request { method, path } => Http::Request {
+            path: path.into(),
+            method,
+            header_map: head.header_map,
+            body,
+        },
+        Start::Response { code } => Http::Response {
+            code,
+            header_map: head.header_map,
+            body,
+        },
+    }
+}
+
+/// Parses the start line and headers once they are complete, header names are lowercased
+fn parse_head(buf: &[u8]) -> Result<Option<Head>> {
+    let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+        if buf.len() > MAX_HEAD_LEN {
+            bail!("HTTP headers exceed {MAX_HEAD_LEN} bytes");
+        }
+        return Ok(None);
+    };
+    let text =
+        str::from_utf8(&buf[..end]).map_err(|e| anyhow!("HTTP headers are not UTF-8: {e}"))?;
+    let mut lines = text.split("\r\n");
+
+    let start_line = lines.next().unwrap_or_default();
+    let start = match start_line.strip_prefix("HTTP/") {
+        Some(status) => {
+            let mut parts = status.splitn(3, ' ');
+            let _version = parts.next();
+            let code = parts
+                .next()
+                .and_then(|c| c.parse::<u16>().ok())
+                .ok_or_else(|| anyhow!("invalid status line {start_line:?}"))?;
+            Start::Response {
+                code: StatusCode::from_u16(code)?,
+            }
+        }
+        None => {
+            let mut parts = start_line.split(' ');
+            let (Some(method), Some(path), Some(version), None) =
+                (parts.next(), parts.next(), parts.next(), parts.next())
+            else {
+                bail!("invalid request line {start_line:?}");
+            };
+            if !version.starts_with("HTTP/") {
+                bail!("invalid request line {start_line:?}");
+            }
+            Start::Request {
+                method: Method::from_bytes(method.as_bytes())?,
+                path: path.into(),
+            }
+        }
+    };
+
+    let mut header_map = HashMap::new();
+    for line in lines {
+        let (name, value) = line
+            .split_once(':')
+            .filter(|(name, _)| !name.is_empty() && !name.contains(char::is_whitespace))
+            .ok_or_else(|| anyhow!("invalid header line {line:?}"))?;
+        let value = value.trim().to_string();
+        header_map
+            .entry(name.to_ascii_lowercase())
+            .and_modify(|e: &mut String| {
+                e.push_str(", ");
+                e.push_str(&value)
+            })
+            .or_insert(value);
+    }
+
+    Ok(Some(Head {
+        start,
+        header_map,
+        len: end + 4,
+    }))
+}
+
+fn framing(head: &Head) -> Result<Framing> {
+    if let Some(te) = head.header_map.get("transfer-encoding") {
+        let chunked = te
+            .rsplit(',')
+            .next()
+            .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"));
+        return match (chunked, &head.start) {
+            (true, _) => Ok(Framing::Chunked),
+            (false, Start::Response { .. }) => Ok(Framing::UntilClose),
+            (false, Start::Request { .. }) => bail!("unsupported transfer-encoding {te:?}"),
+        };
+    }
+    if let Some(cl) = head.header_map.get("content-length") {
+        let mut lengths = cl.split(',').map(|l| l.trim().parse::<usize>());
+        let len = lengths
+            .next()
+            .and_then(|l| l.ok())
+            .ok_or_else(|| anyhow!("invalid content-length {cl:?}"))?;
+        if !lengths.all(|l| l.is_ok_and(|l| l == len)) {
+            bail!("conflicting content-length {cl:?}");
+        }
+        return Ok(Framing::Length(len));
+    }
+    match head.start {
+        Start::Request { .. } => Ok(Framing::Length(0)),
+        Start::Response { code }
+            if code.is_informational()
+                || code == StatusCode::NO_CONTENT
+                || code == StatusCode::NOT_MODIFIED =>
+        {
+            Ok(Framing::Length(0))
+        }
+        Start::Response { .. } => Ok(Framing::UntilClose),
+    }
+}
+
+/// Progress of a chunked body, kept across `decode` calls so that every byte is parsed once
+#[derive(Debug, Default)]
+struct Chunked {
+    body: Vec<u8>,
+    /// Offset of the first unparsed byte of the body
+    pos: usize,
+    /// Set once the last chunk was read and only trailers remain
+    trailers: bool,
+}
+
+impl Chunked {
+    /// Parses the complete chunks of `raw`, returns the number of bytes consumed once the body has ended
+    fn advance(&mut self, raw: &[u8]) -> Result<Option<usize>> {
+        loop {
+            let Some(line_len) = find_crlf(&raw[self.pos..]) else {
+                if raw.len() - self.pos > MAX_HEAD_LEN {
+                    bail!("HTTP chunk line exceeds {MAX_HEAD_LEN} bytes");
+                }
+                return Ok(None);
+            };
+            if self.trailers {
+                // Trailers are skipped up to the empty line ending the body
+                self.pos += line_len + 2;
+                if line_len == 0 {
+                    return Ok(Some(self.pos));
+                }
+                continue;
+            }
+
+            let line = str::from_utf8(&raw[self.pos..self.pos + line_len])?;
+            let size_hex = line.split(';').next().unwrap_or_default().trim();
+            let size = usize::from_str_radix(size_hex, 16)
+                .map_err(|_| anyhow!("invalid chunk size {line:?}"))?;
+            if size == 0 {
+                self.pos += line_len + 2;
+                self.trailers = true;
+                continue;
+            }
+            if size > MAX_BODY_LEN - self.body.len() {
+                bail!("HTTP body exceeds {MAX_BODY_LEN} bytes");
+            }
+
+            let start = self.pos + line_len + 2;
+            if raw.len() < start + size + 2 {
+                return Ok(None);
+            }
+            if &raw[start + size..start + size + 2] != b"\r\n" {
+                bail!("missing CRLF after chunk");
+            }
+            self.body.extend_from_slice(&raw[start..start + size]);
+            self.pos = start + size + 2;
+        }
+    }
+}
+
+fn find_crlf(raw: &[u8]) -> Option<usize> {
+    raw.windows(2).position(|w| w == b"\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(decoder: &mut HttpDecoder) -> Result<Vec<Http>> {
+        std::iter::from_fn(|| decoder.decode().transpose()).collect()
+    }
+
+    #[test]
+    fn split_message_test() -> Result<()> {
+        let raw = b"HTTP/1.1 200 \r\nContent-Type: application/json\r\nContent-Length: 27\r\n\r\n{\"firecracker_version\":\"1\"}";
+        let mut decoder = HttpDecoder::new();
+        for (i, byte) in raw.iter().enumerate() {
+            assert!(decoder.decode()?.is_none(), "decoded early at byte {i}");
+            decoder.extend(&[*byte]);
+        }
+        let message = decoder.decode()?.unwrap();
+        assert_eq!(message.get_body(), br#"{"firecracker_version":"1"}"#);
+        assert!(decoder.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn split_chunked_test() -> Result<()> {
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4;ext=1\r\n\x00\x01\r\n\r\n3\r\n\xff\xfe\xfd\r\n0\r\nX-Trailer: 1\r\n\r\n";
+        let mut decoder = HttpDecoder::new();
+        for chunk in raw.chunks(5) {
+            assert!(decoder.decode()?.is_none());
+            decoder.extend(chunk);
+        }
+        let message = decoder.decode()?.unwrap();
+        assert_eq!(message.get_body(), b"\x00\x01\r\n\xff\xfe\xfd");
+        assert!(decoder.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn pipelined_messages_test() -> Result<()> {
+        let mut decoder = HttpDecoder::new();
+        decoder.extend(b"HTTP/1.1 204 \r\nServer: Firecracker API\r\n\r\n");
+        decoder.extend(b"HTTP/1.1 400 \r\nContent-Length: 2\r\n\r\n{}");
+        decoder.extend(b"PUT /actions HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc");
+        decoder.extend(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\nHTTP/1.1 2");
+
+        let messages = decode_all(&mut decoder)?;
+        assert_eq!(messages.len(), 4);
+        assert!(matches!(
+            &messages[0],
+            Http::Response { code, body, .. } if *code == StatusCode::NO_CONTENT && body.is_empty()
+        ));
+        assert!(matches!(
+            &messages[1],
+            Http::Response { code, body, .. } if *code == StatusCode::BAD_REQUEST && body == b"{}"
+        ));
+        assert!(matches!(
+            &messages[2],
+            Http::Request { method, path, body, .. }
+                if *method == Method::PUT && path.to_str() == Some("/actions") && body == b"abc"
+        ));
+        assert_eq!(messages[3].header("HOST"), Some("localhost"));
+        assert!(!decoder.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn until_close_test() -> Result<()> {
+        let mut decoder = HttpDecoder::new();
+        decoder.extend(b"HTTP/1.0 200 OK\r\n\r\npartial");
+        assert!(decoder.decode()?.is_none());
+        decoder.extend(b" body");
+        assert_eq!(decoder.decode_eof()?.unwrap().get_body(), b"partial body");
+        assert!(decoder.decode_eof()?.is_none());
+
+        decoder.extend(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort");
+        assert!(decoder.decode_eof().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn chunked_pipelined_test() -> Result<()> {
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nab\r\n0\r\n\r\n";
+        let mut decoder = HttpDecoder::new();
+        for _ in 0..2 {
+            for byte in raw {
+                decoder.extend(&[*byte]);
+                if let Some(message) = decoder.decode()? {
+                    assert_eq!(message.get_body(), b"ab");
+                }
+            }
+        }
+        assert!(decoder.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn body_limit_test() {
+        let mut decoder = HttpDecoder::new();
+        decoder.extend(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n");
+        decoder.extend(format!("{:x}\r\n", MAX_BODY_LEN + 1).as_bytes());
+        assert!(decoder.decode().is_err());
+
+        let mut decoder = HttpDecoder::new();
+        decoder.extend(
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n",
+                MAX_BODY_LEN + 1
+            )
+            .as_bytes(),
+        );
+        assert!(decoder.decode().is_err());
+    }
+
+    #[test]
+    fn invalid_messages_test() {
+        for raw in [
+            &b"HTTP/1.1 abc\r\n\r\n"[..],
+            b"GET /\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nno colon\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 1, 2\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n",
+        ] {
+            let mut decoder = HttpDecoder::new();
+            decoder.extend(raw);
+            assert!(
+                decoder.decode().is_err(),
+                "{:?}",
+                String::from_utf8_lossy(raw)
+            );
+        }
+    }
+}
@@ -1,2 +1,4 @@
+pub mod compat;
 pub mod config;
 pub mod http;
+pub mod models;
@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+
+/// Memory balloon device, body of `PUT /balloon` and returned by `GET /balloon`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Balloon {
+    /// Target size of the balloon in MiB
+    pub amount_mib: u32,
+    /// Whether the guest may deflate the balloon when it runs out of memory
+    pub deflate_on_oom: bool,
+    /// Interval in seconds between statistics updates, 0 disables them
+    #[serde(default)]
+    pub stats_polling_interval_s: u32,
+}
+
+/// New target size of the balloon, body of `PATCH /balloon`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalloonUpdate {
+    pub amount_mib: u32,
+}
+
+/// New statistics interval, body of `PATCH /balloon/statistics`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalloonStatsUpdate {
+    pub stats_polling_interval_s: u32,
+}
+
+/// Memory statistics reported by the balloon driver, returned by `GET /balloon/statistics`
+///
+/// Every optional field is only present if the guest reports it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalloonStats {
+    pub target_pages: u64,
+    pub actual_pages: u64,
+    pub target_mib: u64,
+    pub actual_mib: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub swap_in: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub swap_out: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub major_faults: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minor_faults: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub free_memory: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_memory: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub available_memory: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disk_caches: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hugetlb_allocations: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hugetlb_failures: Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn balloon_stats_test() -> Result<()> {
+        let stats: BalloonStats = serde_json::from_str(
+            r#"{
+                "target_pages": 16384,
+                "actual_pages": 16384,
+                "target_mib": 64,
+                "actual_mib": 64,
+                "swap_in": 0,
+                "swap_out": 0,
+                "major_faults": 24,
+                "minor_faults": 7431,
+                "free_memory": 48091136,
+                "total_memory": 126013440,
+                "available_memory": 75583488,
+                "disk_caches": 31801344
+            }"#,
+        )?;
+        assert_eq!(stats.actual_mib, 64);
+        assert_eq!(stats.available_memory, Some(75583488));
+        assert_eq!(stats.hugetlb_failures, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// Kernel and command line of the guest, body of `PUT /boot-source`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BootSource {
+    /// Host path of the kernel image
+    pub kernel_image_path: PathBuf,
+    /// Host path of the initrd image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initrd_path: Option<PathBuf>,
+    /// Kernel command line
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boot_args: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn boot_source_test() -> Result<()> {
+        let boot = BootSource {
+            kernel_image_path: "/tmp/vmlinux.bin".into(),
+            initrd_path: None,
+            boot_args: Some("console=ttyS0 reboot=k panic=1 pci=off".into()),
+        };
+        let value = json!({
+            "kernel_image_path": "/tmp/vmlinux.bin",
+            "boot_args": "console=ttyS0 reboot=k panic=1 pci=off"
+        });
+        assert_eq!(serde_json::to_value(&boot)?, value);
+        assert_eq!(serde_json::from_value::<BootSource>(value)?, boot);
+        Ok(())
+    }
+}
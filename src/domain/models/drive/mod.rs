@@ -0,0 +1,102 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::domain::models::RateLimiter;
+
+/// Block device, body of `PUT /drives/{drive_id}`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Drive {
+    pub drive_id: String,
+    /// Host path of the backing file, required unless `socket` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_on_host: Option<PathBuf>,
+    pub is_root_device: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_read_only: Option<bool>,
+    /// Unique id of the boot partition, only used for the root device
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partuuid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_type: Option<CacheType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub io_engine: Option<IoEngine>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limiter: Option<RateLimiter>,
+    /// Socket of a vhost-user block backend, replacing `path_on_host`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub socket: Option<PathBuf>,
+}
+
+impl Drive {
+    /// A read-write drive backed by a file on the host
+    pub fn new<P: Into<PathBuf>>(drive_id: impl Into<String>, path_on_host: P) -> Self {
+        Self {
+            drive_id: drive_id.into(),
+            path_on_host: Some(path_on_host.into()),
+            is_root_device: false,
+            is_read_only: Some(false),
+            partuuid: None,
+            cache_type: None,
+            io_engine: None,
+            rate_limiter: None,
+            socket: None,
+        }
+    }
+}
+
+/// Whether flush requests of the guest are forwarded to the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CacheType {
+    Unsafe,
+    Writeback,
+}
+
+/// Block I/O engine used by Firecracker
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IoEngine {
+    Sync,
+    Async,
+}
+
+/// Live update of a drive, body of `PATCH /drives/{drive_id}`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartialDrive {
+    pub drive_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_on_host: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limiter: Option<RateLimiter>,
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn drive_test() -> Result<()> {
+        let drive: Drive = serde_json::from_str(
+            r#"{
+                "drive_id": "rootfs",
+                "partuuid": null,
+                "is_root_device": true,
+                "cache_type": "Unsafe",
+                "is_read_only": false,
+                "path_on_host": "/tmp/rootfs.ext4",
+                "rate_limiter": {"bandwidth": {"size": 1048576, "refill_time": 1000}},
+                "io_engine": "Async",
+                "socket": null
+            }"#,
+        )?;
+        assert_eq!(drive.cache_type, Some(CacheType::Unsafe));
+        assert_eq!(drive.io_engine, Some(IoEngine::Async));
+        assert_eq!(drive.rate_limiter.unwrap().bandwidth.unwrap().size, 1048576);
+        assert_eq!(
+            serde_json::to_string(&Drive::new("data", "/tmp/data.ext4"))?,
+            r#"{"drive_id":"data","path_on_host":"/tmp/data.ext4","is_root_device":false,"is_read_only":false}"#
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,10 @@
+use serde::{Deserialize, Serialize};
+
+use crate::domain::models::RateLimiter;
+
+/// Virtio RNG device, body of `PUT /entropy`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntropyDevice {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limiter: Option<RateLimiter>,
+}
@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+
+/// General information about the Firecracker instance, returned by `GET /`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstanceInfo {
+    /// Application name
+    pub app_name: String,
+    /// MicroVM / instance ID
+    pub id: String,
+    /// The current detailed state of the Firecracker instance
+    pub state: InstanceState,
+    /// MicroVM hypervisor build version
+    pub vmm_version: String,
+}
+
+/// State of the Firecracker instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InstanceState {
+    #[serde(rename = "Not started")]
+    NotStarted,
+    Running,
+    Paused,
+}
+
+/// Version of the Firecracker binary, returned by `GET /version`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FirecrackerVersion {
+    pub firecracker_version: String,
+}
+
+/// Synchronous action on the instance, body of `PUT /actions`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstanceActionInfo {
+    pub action_type: ActionType,
+}
+
+/// Type of a synchronous action
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ActionType {
+    /// Flushes the metrics to the configured metrics file
+    FlushMetrics,
+    /// Boots the guest
+    InstanceStart,
+    /// Sends Ctrl+Alt+Del to the guest, x86_64 only
+    SendCtrlAltDel,
+}
+
+/// Requested state of the microVM, body of `PATCH /vm`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Vm {
+    pub state: VmState,
+}
+
+/// State a running microVM can be moved to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VmState {
+    Paused,
+    Resumed,
+}
+
+/// Body of every error response of the API
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiError {
+    /// A description of the error condition
+    pub fault_message: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn instance_info_test() -> Result<()> {
+        let info: InstanceInfo = serde_json::from_str(
+            r#"{"id":"anonymous-instance","state":"Not started","vmm_version":"1.13.1","app_name":"Firecracker"}"#,
+        )?;
+        assert_eq!(info.state, InstanceState::NotStarted);
+        assert_eq!(info.vmm_version, "1.13.1");
+        Ok(())
+    }
+
+    #[test]
+    fn requests_test() -> Result<()> {
+        assert_eq!(
+            serde_json::to_value(InstanceActionInfo {
+                action_type: ActionType::SendCtrlAltDel
+            })?,
+            json!({"action_type": "SendCtrlAltDel"})
+        );
+        assert_eq!(
+            serde_json::to_value(Vm {
+                state: VmState::Paused
+            })?,
+            json!({"state": "Paused"})
+        );
+        let error: ApiError = serde_json::from_str(
+            r#"{"fault_message":"The requested operation is not supported after starting the microVM."}"#,
+        )?;
+        assert!(error.fault_message.starts_with("The requested operation"));
+        Ok(())
+    }
+}
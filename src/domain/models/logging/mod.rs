@@ -0,0 +1,63 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// Logging system of Firecracker, body of `PUT /logger`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Logger {
+    /// Host path of the log file or named pipe, stdout if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_path: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<LogLevel>,
+    /// Prefix every line with its level
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub show_level: Option<bool>,
+    /// Prefix every line with its source file and line
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub show_log_origin: Option<bool>,
+    /// Only log the messages of this module
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub module: Option<String>,
+}
+
+/// Verbosity of the logger
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warning,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// Metrics system of Firecracker, body of `PUT /metrics`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metrics {
+    /// Host path of the metrics file or named pipe
+    pub metrics_path: PathBuf,
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn logger_test() -> Result<()> {
+        let logger = Logger {
+            log_path: Some("/tmp/firecracker.log".into()),
+            level: Some(LogLevel::Warning),
+            show_level: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_value(&logger)?,
+            json!({"log_path": "/tmp/firecracker.log", "level": "Warning", "show_level": true})
+        );
+        Ok(())
+    }
+}
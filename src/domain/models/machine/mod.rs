@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// vCPU and memory configuration, body of `PUT /machine-config` and returned by `GET /machine-config`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MachineConfiguration {
+    /// Number of vCPUs, 1 or an even number up to 32
+    pub vcpu_count: u8,
+    /// Memory size of the guest in MiB
+    pub mem_size_mib: usize,
+    /// Enables simultaneous multithreading, x86_64 only
+    #[serde(default)]
+    pub smt: bool,
+    /// Enables dirty page tracking, required for diff snapshots
+    #[serde(default)]
+    pub track_dirty_pages: bool,
+    /// Deprecated static CPU template, prefer `PUT /cpu-config`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_template: Option<CpuTemplate>,
+    /// Backs the guest memory by huge pages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub huge_pages: Option<HugePages>,
+}
+
+impl MachineConfiguration {
+    pub fn new(vcpu_count: u8, mem_size_mib: usize) -> Self {
+        Self {
+            vcpu_count,
+            mem_size_mib,
+            smt: false,
+            track_dirty_pages: false,
+            cpu_template: None,
+            huge_pages: None,
+        }
+    }
+}
+
+/// Static CPU template masking CPU features to the guest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CpuTemplate {
+    C3,
+    T2,
+    T2S,
+    T2CL,
+    T2A,
+    V1N1,
+    None,
+}
+
+/// Page size backing the guest memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HugePages {
+    None,
+    #[serde(rename = "2M")]
+    TwoMegabytes,
+}
+
+/// Custom CPU template, body of `PUT /cpu-config`
+///
+/// The modifiers are architecture specific, please refer to [here](https://github.com/firecracker-microvm/firecracker/blob/main/docs/cpu_templates/cpu-templates.md).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CpuConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kvm_capabilities: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpuid_modifiers: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub msr_modifiers: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reg_modifiers: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vcpu_features: Option<Value>,
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn machine_configuration_test() -> Result<()> {
+        let config: MachineConfiguration = serde_json::from_str(
+            r#"{"vcpu_count":2,"mem_size_mib":1024,"smt":false,"track_dirty_pages":true,"cpu_template":"T2S","huge_pages":"2M"}"#,
+        )?;
+        assert_eq!(config.cpu_template, Some(CpuTemplate::T2S));
+        assert_eq!(config.huge_pages, Some(HugePages::TwoMegabytes));
+        assert_eq!(
+            serde_json::to_value(MachineConfiguration::new(1, 128))?,
+            json!({"vcpu_count": 1, "mem_size_mib": 128, "smt": false, "track_dirty_pages": false})
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+
+/// Guest access to the microVM metadata service, body of `PUT /mmds/config`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MmdsConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<MmdsVersion>,
+    /// Interfaces allowed to forward packets to MMDS
+    pub network_interfaces: Vec<String>,
+    /// IPv4 address of MMDS in the guest, 169.254.169.254 by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv4_address: Option<String>,
+    /// Answers in the IMDS format of EC2
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub imds_compat: Option<bool>,
+}
+
+/// MMDS protocol, V2 requires session tokens
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MmdsVersion {
+    V1,
+    V2,
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn mmds_config_test() -> Result<()> {
+        let config: MmdsConfig = serde_json::from_str(
+            r#"{"version":"V2","network_interfaces":["net1"],"ipv4_address":"169.254.169.254"}"#,
+        )?;
+        assert_eq!(config.version, Some(MmdsVersion::V2));
+        assert_eq!(config.network_interfaces, ["net1"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,35 @@
+//! Request and response types of the [Firecracker API](https://github.com/firecracker-microvm/firecracker/blob/main/src/firecracker/swagger/firecracker.yaml)
+
+mod balloon;
+mod boot;
+mod drive;
+mod entropy;
+mod instance;
+mod logging;
+mod machine;
+mod mmds;
+mod network;
+mod rate_limiter;
+mod snapshot;
+mod vm_config;
+mod vsock;
+
+pub use balloon::{Balloon, BalloonStats, BalloonStatsUpdate, BalloonUpdate};
+pub use boot::BootSource;
+pub use drive::{CacheType, Drive, IoEngine, PartialDrive};
+pub use entropy::EntropyDevice;
+pub use instance::{
+    ActionType, ApiError, FirecrackerVersion, InstanceActionInfo, InstanceInfo, InstanceState, Vm,
+    VmState,
+};
+pub use logging::{LogLevel, Logger, Metrics};
+pub use machine::{CpuConfig, CpuTemplate, HugePages, MachineConfiguration};
+pub use mmds::{MmdsConfig, MmdsVersion};
+pub use network::{NetworkInterface, PartialNetworkInterface};
+pub use rate_limiter::{RateLimiter, TokenBucket};
+pub use snapshot::{
+    MemoryBackend, MemoryBackendType, NetworkOverride, SnapshotCreateParams, SnapshotLoadParams,
+    SnapshotType,
+};
+pub use vm_config::FullVmConfiguration;
+pub use vsock::Vsock;
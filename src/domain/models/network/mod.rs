@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+
+use crate::domain::models::RateLimiter;
+
+/// Network interface backed by a host tap device, body of `PUT /network-interfaces/{iface_id}`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkInterface {
+    pub iface_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guest_mac: Option<String>,
+    /// Name of the tap device on the host
+    pub host_dev_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rx_rate_limiter: Option<RateLimiter>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx_rate_limiter: Option<RateLimiter>,
+}
+
+impl NetworkInterface {
+    pub fn new(
+        iface_id: impl Into<String>,
+        guest_mac: impl Into<String>,
+        host_dev_name: impl Into<String>,
+    ) -> Self {
+        Self {
+            iface_id: iface_id.into(),
+            guest_mac: Some(guest_mac.into()),
+            host_dev_name: host_dev_name.into(),
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+        }
+    }
+}
+
+/// Live update of a network interface, body of `PATCH /network-interfaces/{iface_id}`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartialNetworkInterface {
+    pub iface_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rx_rate_limiter: Option<RateLimiter>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx_rate_limiter: Option<RateLimiter>,
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn network_interface_test() -> Result<()> {
+        let inet: NetworkInterface = serde_json::from_value(json!({
+            "iface_id": "net1",
+            "host_dev_name": "tap0",
+            "guest_mac": "06:00:ac:10:00:02",
+            "rx_rate_limiter": {"ops": {"size": 100, "one_time_burst": 10, "refill_time": 1000}},
+            "tx_rate_limiter": null
+        }))?;
+        assert_eq!(
+            inet.rx_rate_limiter.unwrap().ops.unwrap().one_time_burst,
+            Some(10)
+        );
+        assert_eq!(inet.tx_rate_limiter, None);
+        assert_eq!(
+            serde_json::to_value(NetworkInterface::new("net1", "06:00:AC:10:00:02", "tap0"))?,
+            json!({"iface_id": "net1", "guest_mac": "06:00:AC:10:00:02", "host_dev_name": "tap0"})
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+
+/// Token bucket of a rate limiter, a size or refill time of 0 disables the bucket
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenBucket {
+    /// Total number of tokens the bucket can hold
+    pub size: u64,
+    /// Initial burst size, consumed once before the bucket starts refilling
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub one_time_burst: Option<u64>,
+    /// Time in milliseconds for the bucket to refill completely
+    pub refill_time: u64,
+}
+
+impl TokenBucket {
+    /// A bucket that disables limiting when sent in an update
+    pub fn disabled() -> Self {
+        Self {
+            size: 0,
+            one_time_burst: None,
+            refill_time: 0,
+        }
+    }
+
+    /// Returns whether the bucket limits anything
+    pub fn is_disabled(&self) -> bool {
+        self.size == 0 || self.refill_time == 0
+    }
+}
+
+/// Bandwidth and operations limits of a drive or network interface
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimiter {
+    /// Limit in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bandwidth: Option<TokenBucket>,
+    /// Limit in operations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ops: Option<TokenBucket>,
+}
+
+impl RateLimiter {
+    /// Returns the limiter without its disabled buckets, or `None` if nothing is limited
+    pub fn normalized(limiter: Option<&Self>) -> Option<Self> {
+        let active = |bucket: Option<TokenBucket>| {
+            bucket.filter(|b| !b.is_disabled()).map(|b| TokenBucket {
+                one_time_burst: b.one_time_burst.filter(|&burst| burst > 0),
+                ..b
+            })
+        };
+        let limiter = limiter?;
+        let normalized = Self {
+            bandwidth: active(limiter.bandwidth),
+            ops: active(limiter.ops),
+        };
+        (normalized != Self::default()).then_some(normalized)
+    }
+
+    /// Returns the limiter as an update, disabling the buckets it does not define
+    pub(crate) fn update(limiter: Option<&Self>) -> Self {
+        let limiter = limiter.copied().unwrap_or_default();
+        Self {
+            bandwidth: Some(limiter.bandwidth.unwrap_or_else(TokenBucket::disabled)),
+            ops: Some(limiter.ops.unwrap_or_else(TokenBucket::disabled)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalized_test() {
+        let bucket = TokenBucket {
+            size: 1024,
+            one_time_burst: Some(0),
+            refill_time: 100,
+        };
+        let limiter = RateLimiter {
+            bandwidth: Some(bucket),
+            ops: Some(TokenBucket::disabled()),
+        };
+        assert_eq!(
+            RateLimiter::normalized(Some(&limiter)),
+            Some(RateLimiter {
+                bandwidth: Some(TokenBucket {
+                    one_time_burst: None,
+                    ..bucket
+                }),
+                ops: None,
+            })
+        );
+        assert_eq!(RateLimiter::normalized(Some(&RateLimiter::default())), None);
+        assert_eq!(
+            RateLimiter::update(None),
+            RateLimiter {
+                bandwidth: Some(TokenBucket::disabled()),
+                ops: Some(TokenBucket::disabled()),
+            }
+        );
+    }
+}
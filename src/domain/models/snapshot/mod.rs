@@ -0,0 +1,93 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// Body of `PUT /snapshot/create`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotCreateParams {
+    /// Host path of the guest memory file
+    pub mem_file_path: PathBuf,
+    /// Host path of the microVM state file
+    pub snapshot_path: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot_type: Option<SnapshotType>,
+}
+
+/// Whether the memory file holds all guest memory or only the pages dirtied since the last snapshot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SnapshotType {
+    Full,
+    Diff,
+}
+
+/// Body of `PUT /snapshot/load`
+///
+/// The memory file and dirty page tracking are set with different fields depending on the
+/// Firecracker release, `FirecrackerProcess::load_snapshot` picks the right ones.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotLoadParams {
+    /// Host path of the microVM state file
+    pub snapshot_path: PathBuf,
+    /// Deprecated since Firecracker 1.1, replaced by `mem_backend`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mem_file_path: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mem_backend: Option<MemoryBackend>,
+    /// Deprecated since Firecracker 1.13, replaced by `track_dirty_pages`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enable_diff_snapshots: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track_dirty_pages: Option<bool>,
+    /// Resumes the guest once the snapshot is loaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resume_vm: Option<bool>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub network_overrides: Vec<NetworkOverride>,
+}
+
+/// Source of the guest memory of a snapshot
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryBackend {
+    pub backend_type: MemoryBackendType,
+    /// Path of the memory file, or of the socket of the userfaultfd handler
+    pub backend_path: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MemoryBackendType {
+    File,
+    Uffd,
+}
+
+/// Backs a snapshotted network interface by another host tap device
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkOverride {
+    pub iface_id: String,
+    pub host_dev_name: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn snapshot_load_test() -> Result<()> {
+        let load: SnapshotLoadParams = serde_json::from_str(
+            r#"{
+                "snapshot_path": "./snapshot_file",
+                "mem_backend": {"backend_path": "./mem_file", "backend_type": "Uffd"},
+                "track_dirty_pages": true,
+                "resume_vm": false,
+                "network_overrides": [{"iface_id": "eth0", "host_dev_name": "vmtap01"}]
+            }"#,
+        )?;
+        assert_eq!(
+            load.mem_backend.unwrap().backend_type,
+            MemoryBackendType::Uffd
+        );
+        assert_eq!(load.network_overrides[0].host_dev_name, "vmtap01");
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+
+use crate::domain::models::{
+    Balloon, BootSource, CpuConfig, Drive, EntropyDevice, Logger, MachineConfiguration, Metrics,
+    MmdsConfig, NetworkInterface, Vsock,
+};
+
+/// The effective configuration of the microVM, returned by `GET /vm/config`
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct FullVmConfiguration {
+    #[serde(rename = "boot-source", default)]
+    pub boot_source: Option<BootSource>,
+    #[serde(rename = "cpu-config", default)]
+    pub cpu_config: Option<CpuConfig>,
+    #[serde(default)]
+    pub drives: Vec<Drive>,
+    #[serde(rename = "machine-config", default)]
+    pub machine_config: Option<MachineConfiguration>,
+    #[serde(rename = "network-interfaces", default)]
+    pub network_interfaces: Vec<NetworkInterface>,
+    #[serde(default)]
+    pub vsock: Option<Vsock>,
+    #[serde(default)]
+    pub balloon: Option<Balloon>,
+    #[serde(default)]
+    pub logger: Option<Logger>,
+    #[serde(default)]
+    pub metrics: Option<Metrics>,
+    #[serde(rename = "mmds-config", default)]
+    pub mmds_config: Option<MmdsConfig>,
+    #[serde(default)]
+    pub entropy: Option<EntropyDevice>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use anyhow::Result;
+
+    use super::*;
+    use crate::domain::models::HugePages;
+
+    #[test]
+    fn full_vm_configuration_test() -> Result<()> {
+        let config: FullVmConfiguration = serde_json::from_str(
+            r#"{
+                "balloon": {"amount_mib": 0, "deflate_on_oom": true, "stats_polling_interval_s": 1},
+                "drives": [{
+                    "drive_id": "rootfs",
+                    "partuuid": null,
+                    "is_root_device": true,
+                    "cache_type": "Unsafe",
+                    "is_read_only": false,
+                    "path_on_host": "/tmp/rootfs.ext4",
+                    "rate_limiter": null,
+                    "io_engine": "Sync",
+                    "socket": null
+                }],
+                "boot-source": {
+                    "kernel_image_path": "/tmp/vmlinux.bin",
+                    "initrd_path": null,
+                    "boot_args": "console=ttyS0 reboot=k panic=1 pci=off"
+                },
+                "cpu-config": null,
+                "logger": {"log_path": "/tmp/fc.log", "level": "Info", "show_level": false, "show_log_origin": false},
+                "machine-config": {
+                    "vcpu_count": 2,
+                    "mem_size_mib": 1024,
+                    "smt": false,
+                    "track_dirty_pages": false,
+                    "huge_pages": "None"
+                },
+                "metrics": {"metrics_path": "/tmp/fc.metrics"},
+                "mmds-config": {"version": "V1", "network_interfaces": ["net1"], "ipv4_address": "169.254.169.254"},
+                "network-interfaces": [{
+                    "iface_id": "net1",
+                    "host_dev_name": "tap0",
+                    "guest_mac": "06:00:AC:10:00:02",
+                    "rx_rate_limiter": null,
+                    "tx_rate_limiter": null
+                }],
+                "vsock": {"guest_cid": 3, "uds_path": "/tmp/vsock.socket", "vsock_id": "vsock0"},
+                "entropy": {"rate_limiter": null}
+            }"#,
+        )?;
+        let machine = config.machine_config.unwrap();
+        assert_eq!(machine.mem_size_mib, 1024);
+        assert_eq!(machine.huge_pages, Some(HugePages::None));
+        assert_eq!(
+            config.drives[0].path_on_host,
+            Some(PathBuf::from("/tmp/rootfs.ext4"))
+        );
+        assert_eq!(config.network_interfaces[0].host_dev_name, "tap0");
+        assert_eq!(config.vsock.unwrap().guest_cid, 3);
+        assert_eq!(config.balloon.unwrap().stats_polling_interval_s, 1);
+        assert_eq!(config.mmds_config.unwrap().network_interfaces, ["net1"]);
+        assert_eq!(config.entropy, Some(EntropyDevice::default()));
+        Ok(())
+    }
+}
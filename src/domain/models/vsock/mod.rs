@@ -0,0 +1,30 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// Virtio vsock device, body of `PUT /vsock`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Vsock {
+    /// Deprecated identifier, ignored by Firecracker
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vsock_id: Option<String>,
+    /// Context identifier of the guest, at least 3
+    pub guest_cid: u32,
+    /// Host path of the unix socket multiplexing the guest connections
+    pub uds_path: PathBuf,
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn vsock_test() -> Result<()> {
+        let vsock: Vsock = serde_json::from_str(r#"{"guest_cid":3,"uds_path":"/tmp/v.sock"}"#)?;
+        assert_eq!(vsock.vsock_id, None);
+        assert_eq!(vsock.uds_path, PathBuf::from("/tmp/v.sock"));
+        Ok(())
+    }
+}
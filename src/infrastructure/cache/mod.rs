@@ -0,0 +1,564 @@
+use std::{
+    env,
+    fs::{File, Permissions},
+    io::SeekFrom,
+    os::{fd::AsRawFd, unix::fs::PermissionsExt},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    task,
+};
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Size of the blocks of zeros left as holes by [`CacheWriter::write_sparse`]
+const SPARSE_BLOCK: usize = 4096;
+
+/// A content-addressed store of downloaded artifacts, shared by the processes of the host
+///
+/// Files are written to `tmp/` and renamed into place once complete, so readers never see
+/// a partial artifact, and fetches of the same key are serialized with file locks.
+///
+/// Layout:
+/// ```text
+/// <root>/
+/// ├── objects/<sha256>   read-only artifact contents, named after their digest
+/// ├── keys/<id>.json     key, ETag and unpacked member of every object
+/// ├── refs/<id>.json     entry a name last resolved to, used in offline mode
+/// ├── locks/<id>.lock    held while an artifact is fetched
+/// └── tmp/               files being written
+/// ```
+#[derive(Debug, Clone, Serialize)]
+pub struct ArtifactCache {
+    root: PathBuf,
+    offline: bool,
+}
+
+/// An artifact stored in the cache
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub key: String,
+    pub etag: Option<String>,
+    /// How the artifact was unpacked from its download, `None` if it is stored as downloaded
+    #[serde(default)]
+    pub unpacked: Option<Unpacked>,
+    pub sha256: String,
+    pub size: u64,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+/// Provenance of an artifact unpacked while it was downloaded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Unpacked {
+    /// Compression the download was decoded from, `gzip`, `zstd` or `xz`
+    pub compression: Option<String>,
+    /// Member of the tar archive the artifact was extracted from
+    pub member: Option<String>,
+}
+
+impl CacheEntry {
+    /// Returns the read-only file of the artifact
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Ref {
+    key: String,
+    etag: Option<String>,
+    #[serde(default)]
+    member: Option<String>,
+}
+
+impl ArtifactCache {
+    /// Creates a cache rooted at the specified directory
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            offline: false,
+        }
+    }
+
+    /// Fail instead of downloading artifacts that are not cached yet
+    pub fn offline(mut self, flag: bool) -> Self {
+        self.offline = flag;
+        self
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the entry stored for the key and ETag, if its object is intact
+    pub async fn get(&self, key: &str, etag: Option<&str>) -> Result<Option<CacheEntry>> {
+        self.get_member(key, etag, None).await
+    }
+
+    /// Returns the entry stored for the member of the tar archive with the key and ETag
+    ///
+    /// The whole download is looked up if no member is specified.
+    pub async fn get_member(
+        &self,
+        key: &str,
+        etag: Option<&str>,
+        member: Option<&str>,
+    ) -> Result<Option<CacheEntry>> {
+        let Some(mut entry) = self
+            .read_json::<CacheEntry>(&self.index(key, etag, member))
+            .await?
+        else {
+            return Ok(None);
+        };
+        entry.path = self.objects_dir().join(&entry.sha256);
+        match fs::metadata(&entry.path).await {
+            Ok(meta) if meta.len() == entry.size => Ok(Some(entry)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the object with the digest as an entry of the key, if it is stored and intact
+    ///
+    /// Lookups by digest need no ETag and so no network access.
+    pub async fn find(&self, key: &str, sha256: &str, size: u64) -> Result<Option<CacheEntry>> {
+        if sha256.len() != 64 || !sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+            bail!("{sha256:?} is not a SHA-256 digest");
+        }
+        let path = self.objects_dir().join(sha256.to_ascii_lowercase());
+        match fs::metadata(&path).await {
+            Ok(meta) if meta.len() == size => Ok(Some(CacheEntry {
+                key: key.into(),
+                etag: None,
+                unpacked: None,
+                sha256: sha256.to_ascii_lowercase(),
+                size,
+                path,
+            })),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the entry the name last resolved to, see [`ArtifactCache::set_ref`]
+    pub async fn resolve(&self, name: &str) -> Result<Option<CacheEntry>> {
+        match self
+            .read_json::<Ref>(&self.refs_dir().join(Self::id(&[name])))
+            .await?
+        {
+            Some(r) => {
+                self.get_member(&r.key, r.etag.as_deref(), r.member.as_deref())
+                    .await
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Records that the name resolves to the entry, for lookups without network access
+    pub(crate) async fn set_ref(&self, name: &str, entry: &CacheEntry) -> Result<()> {
+        let r = Ref {
+            key: entry.key.clone(),
+            etag: entry.etag.clone(),
+            member: entry.unpacked.as_ref().and_then(|u| u.member.clone()),
+        };
+        self.write_json(&self.refs_dir().join(Self::id(&[name])), &r)
+            .await
+    }
+
+    /// Takes the cross-process lock of the key, waiting for other holders to release it
+    pub(crate) async fn lock(&self, key: &str) -> Result<CacheLock> {
+        let path = self
+            .root
+            .join("locks")
+            .join(format!("{}.lock", Self::id(&[key])));
+        fs::create_dir_all(path.parent().unwrap()).await?;
+        task::spawn_blocking(move || {
+            let file = File::options()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)?;
+            // SAFETY: The descriptor is owned by `file`, which outlives the call.
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+                return Err(std::io::Error::last_os_error())
+                    .with_context(|| format!("failed to lock {}", path.display()));
+            }
+            Ok(CacheLock { _file: file })
+        })
+        .await?
+    }
+
+    /// Starts writing a new artifact, which is only stored once committed
+    pub(crate) async fn writer(&self) -> Result<CacheWriter> {
+        let path = self.temp_path();
+        fs::create_dir_all(path.parent().unwrap()).await?;
+        Ok(CacheWriter {
+            file: fs::File::create(&path).await?,
+            path,
+            hasher: Sha256::new(),
+            size: 0,
+            keep: false,
+            cache: self.clone(),
+        })
+    }
+
+    /// Continues writing the artifact of the key and ETag where an interrupted fetch left it
+    ///
+    /// The partial file is kept when the writer is dropped without being committed.
+    /// Hold the lock of the key while writing.
+    pub(crate) async fn resume(&self, key: &str, etag: Option<&str>) -> Result<CacheWriter> {
+        let path = self
+            .root
+            .join("tmp")
+            .join(format!("{}.part", Self::id(&[key, etag.unwrap_or("")])));
+        fs::create_dir_all(path.parent().unwrap()).await?;
+        let mut file = fs::File::options()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+        let mut buf = vec![0; 1 << 20];
+        loop {
+            match file.read(&mut buf).await? {
+                0 => break,
+                n => {
+                    hasher.update(&buf[..n]);
+                    size += n as u64;
+                }
+            }
+        }
+        Ok(CacheWriter {
+            file,
+            path,
+            hasher,
+            size,
+            keep: true,
+            cache: self.clone(),
+        })
+    }
+
+    fn objects_dir(&self) -> PathBuf {
+        self.root.join("objects")
+    }
+
+    fn keys_dir(&self) -> PathBuf {
+        self.root.join("keys")
+    }
+
+    fn refs_dir(&self) -> PathBuf {
+        self.root.join("refs")
+    }
+
+    /// Returns the index file of a download, or of a member of it
+    fn index(&self, key: &str, etag: Option<&str>, member: Option<&str>) -> PathBuf {
+        let etag = etag.unwrap_or("");
+        self.keys_dir().join(match member {
+            Some(member) => Self::id(&[key, etag, member]),
+            None => Self::id(&[key, etag]),
+        })
+    }
+
+    fn temp_path(&self) -> PathBuf {
+        self.root.join("tmp").join(format!(
+            "{}-{}",
+            process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ))
+    }
+
+    fn id(parts: &[&str]) -> String {
+        hex::encode(Sha256::digest(parts.join("\n")))
+    }
+
+    async fn read_json<T: DeserializeOwned>(&self, path: &Path) -> Result<Option<T>> {
+        match fs::read(path.with_extension("json")).await {
+            Ok(raw) => Ok(Some(serde_json::from_slice(&raw)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Replaces the file atomically
+    async fn write_json<T: Serialize>(&self, path: &Path, value: &T) -> Result<()> {
+        let temp = self.temp_path();
+        fs::create_dir_all(temp.parent().unwrap()).await?;
+        fs::create_dir_all(path.parent().unwrap()).await?;
+        fs::write(&temp, serde_json::to_vec(value)?).await?;
+        fs::rename(&temp, path.with_extension("json")).await?;
+        Ok(())
+    }
+}
+
+impl Default for ArtifactCache {
+    /// Rooted at `FIRECRACKER_CACHE`, `~/.firecracker/cache` by default
+    fn default() -> Self {
+        Self::new(
+            env::var_os("FIRECRACKER_CACHE")
+                .map(PathBuf::from)
+                .unwrap_or(
+                    env::home_dir()
+                        .unwrap_or(env::temp_dir())
+                        .join(".firecracker/cache"),
+                ),
+        )
+    }
+}
+
+/// Exclusive right to fetch a key, released once dropped
+pub(crate) struct CacheLock {
+    _file: File,
+}
+
+/// An artifact being written to the cache
+pub(crate) struct CacheWriter {
+    file: fs::File,
+    path: PathBuf,
+    hasher: Sha256,
+    size: u64,
+    keep: bool,
+    cache: ArtifactCache,
+}
+
+impl CacheWriter {
+    /// Returns how many bytes were written, including those of a resumed partial file
+    pub(crate) fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Discards everything written so far
+    pub(crate) async fn truncate(&mut self) -> Result<()> {
+        self.file.set_len(0).await?;
+        self.file.seek(SeekFrom::Start(0)).await?;
+        self.hasher = Sha256::new();
+        self.size = 0;
+        Ok(())
+    }
+
+    pub(crate) async fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.file.write_all(chunk).await?;
+        self.hasher.update(chunk);
+        self.size += chunk.len() as u64;
+        Ok(())
+    }
+
+    /// Writes the chunk, seeking over its blocks of zeros so that they take no space on disk
+    ///
+    /// Only for writers created by [`ArtifactCache::writer`], resumed ones append.
+    pub(crate) async fn write_sparse(&mut self, chunk: &[u8]) -> Result<()> {
+        for block in chunk.chunks(SPARSE_BLOCK) {
+            if block.iter().all(|b| *b == 0) {
+                self.file
+                    .seek(SeekFrom::Current(block.len() as i64))
+                    .await?;
+            } else {
+                self.file.write_all(block).await?;
+            }
+            self.hasher.update(block);
+            self.size += block.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Stores the artifact under its digest and records the key and ETag it was fetched from
+    pub(crate) async fn commit(self, key: &str, etag: Option<&str>) -> Result<CacheEntry> {
+        self.store(key, etag, None).await
+    }
+
+    /// Stores the artifact unpacked from the download of the key and ETag, see [`ArtifactCache::get_member`]
+    pub(crate) async fn commit_unpacked(
+        self,
+        key: &str,
+        etag: Option<&str>,
+        unpacked: Unpacked,
+    ) -> Result<CacheEntry> {
+        self.store(key, etag, Some(unpacked)).await
+    }
+
+    async fn store(
+        self,
+        key: &str,
+        etag: Option<&str>,
+        unpacked: Option<Unpacked>,
+    ) -> Result<CacheEntry> {
+        // Trailing holes are not written
+        self.file.set_len(self.size).await?;
+        self.file.sync_all().await?;
+        let sha256 = hex::encode(self.hasher.clone().finalize());
+        let objects = self.cache.objects_dir();
+        fs::create_dir_all(&objects).await?;
+        fs::set_permissions(&self.path, Permissions::from_mode(0o444)).await?;
+        let path = objects.join(&sha256);
+        fs::rename(&self.path, &path).await?;
+
+        let member = unpacked.as_ref().and_then(|u| u.member.as_deref());
+        let index = self.cache.index(key, etag, member);
+        let entry = CacheEntry {
+            key: key.into(),
+            etag: etag.map(Into::into),
+            unpacked,
+            sha256,
+            size: self.size,
+            path,
+        };
+        self.cache.write_json(&index, &entry).await?;
+        Ok(entry)
+    }
+}
+
+impl Drop for CacheWriter {
+    fn drop(&mut self) {
+        // A no-op once committed, the file was renamed
+        if !self.keep {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn insert_and_get_test() -> Result<()> {
+        let dir = tempdir()?;
+        let cache = ArtifactCache::new(dir.path());
+        assert_eq!(cache.get("vmlinux-6.1.141", Some("\"e1\"")).await?, None);
+
+        let mut writer = cache.writer().await?;
+        writer.write(b"kernel ").await?;
+        writer.write(b"image").await?;
+        let entry = writer.commit("vmlinux-6.1.141", Some("\"e1\"")).await?;
+        assert_eq!(entry.size, 12);
+        assert_eq!(entry.sha256, hex::encode(Sha256::digest(b"kernel image")));
+        assert_eq!(fs::read(entry.path()).await?, b"kernel image");
+        assert!(fs::metadata(entry.path()).await?.permissions().readonly());
+        assert_eq!(
+            fs::read_dir(dir.path().join("tmp"))
+                .await?
+                .next_entry()
+                .await?
+                .map(|e| e.path()),
+            None
+        );
+
+        assert_eq!(
+            cache.get("vmlinux-6.1.141", Some("\"e1\"")).await?,
+            Some(entry.clone())
+        );
+        assert_eq!(cache.get("vmlinux-6.1.141", Some("\"e2\"")).await?, None);
+
+        let found = cache
+            .find("vmlinux-6.1.141", &entry.sha256.to_uppercase(), 12)
+            .await?;
+        assert_eq!(found.as_ref().map(CacheEntry::path), Some(entry.path()));
+        assert_eq!(
+            cache.find("vmlinux-6.1.141", &entry.sha256, 13).await?,
+            None
+        );
+        assert!(cache.find("vmlinux-6.1.141", "../keys", 12).await.is_err());
+
+        assert_eq!(cache.resolve("vmlinux-6.1").await?, None);
+        cache.set_ref("vmlinux-6.1", &entry).await?;
+        assert_eq!(cache.resolve("vmlinux-6.1").await?, Some(entry));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn abandoned_writer_test() -> Result<()> {
+        let dir = tempdir()?;
+        let cache = ArtifactCache::new(dir.path());
+        let mut writer = cache.writer().await?;
+        writer.write(b"partial").await?;
+        drop(writer);
+        assert!(
+            fs::read_dir(dir.path().join("tmp"))
+                .await?
+                .next_entry()
+                .await?
+                .is_none()
+        );
+        assert!(!dir.path().join("objects").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resume_test() -> Result<()> {
+        let dir = tempdir()?;
+        let cache = ArtifactCache::new(dir.path());
+        let mut writer = cache.resume("rootfs.ext4", Some("\"e1\"")).await?;
+        writer.write(b"root").await?;
+        drop(writer);
+
+        let mut writer = cache.resume("rootfs.ext4", Some("\"e1\"")).await?;
+        assert_eq!(writer.size(), 4);
+        writer.write(b"fs").await?;
+        let entry = writer.commit("rootfs.ext4", Some("\"e1\"")).await?;
+        assert_eq!(entry.sha256, hex::encode(Sha256::digest(b"rootfs")));
+
+        let mut writer = cache.resume("rootfs.ext4", Some("\"e2\"")).await?;
+        writer.write(b"stale").await?;
+        writer.truncate().await?;
+        writer.write(b"fresh").await?;
+        let entry = writer.commit("rootfs.ext4", Some("\"e2\"")).await?;
+        assert_eq!(fs::read(entry.path()).await?, b"fresh");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sparse_test() -> Result<()> {
+        let dir = tempdir()?;
+        let cache = ArtifactCache::new(dir.path());
+        let mut writer = cache.writer().await?;
+        writer.write_sparse(&[0; 1 << 20]).await?;
+        writer.write_sparse(b"data").await?;
+        writer.write_sparse(&[0; 1 << 20]).await?;
+        let entry = writer.commit("disk.img", None).await?;
+
+        let mut content = vec![0; 2 << 20];
+        content.splice(1 << 20..1 << 20, *b"data");
+        assert_eq!(entry.size, content.len() as u64);
+        assert_eq!(entry.sha256, hex::encode(Sha256::digest(&content)));
+        assert_eq!(fs::read(entry.path()).await?, content);
+        let meta = fs::metadata(entry.path()).await?;
+        assert!(std::os::unix::fs::MetadataExt::blocks(&meta) * 512 < meta.len());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn lock_test() -> Result<()> {
+        let dir = tempdir()?;
+        let cache = ArtifactCache::new(dir.path());
+        let lock = cache.lock("rootfs").await?;
+
+        let waiting = tokio::spawn({
+            let cache = cache.clone();
+            async move { cache.lock("rootfs").await.map(|_| ()) }
+        });
+        let _other = cache.lock("kernel").await?;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+
+        drop(lock);
+        waiting.await??;
+        Ok(())
+    }
+}
@@ -0,0 +1,405 @@
+use std::{path::Path, sync::Arc, time::Duration};
+
+use anyhow::{Result, bail};
+use http::Method;
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::to_string;
+use tokio::sync::Mutex;
+
+use crate::{
+    domain::{
+        http::Http,
+        models::{
+            ApiError, FirecrackerVersion, FullVmConfiguration, InstanceInfo, MachineConfiguration,
+        },
+    },
+    infrastructure::{connection::Transport, supervisor::Backoff},
+};
+
+/// How long a request waits for its response by default
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Timeouts and retries of the requests sent by an [`ApiClient`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ClientOptions {
+    /// How long a request waits for its response, `None` to wait forever
+    pub timeout: Option<Duration>,
+    /// How many times a GET or PUT request is resent after the connection was lost
+    ///
+    /// `PUT /actions` and `PUT /snapshot/*` are never resent, as they would repeat the action
+    /// or write the snapshot twice.
+    pub max_retries: u32,
+    /// Delay before every resend
+    pub backoff: Backoff,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Some(DEFAULT_TIMEOUT),
+            max_retries: 3,
+            backoff: Backoff {
+                initial: Duration::from_millis(50),
+                max: Duration::from_secs(1),
+            },
+        }
+    }
+}
+
+/// A handle to the API of a Firecracker process, cheap to clone and safe to share between tasks
+///
+/// Requests of every clone are serialized over a single connection to the API socket,
+/// which is re-established once lost. Each clone has its own [`ClientOptions`].
+/// The handle does not own the process: once it is stopped, requests fail.
+///
+/// Exemple:
+/// ```no_compile
+/// let client = process.client();
+/// tokio::spawn(async move {
+///     let info = client.instance_info().await?;
+/// });
+/// let slow = process.client().timeout(Some(Duration::from_secs(300)));
+/// ```
+#[derive(Clone)]
+pub struct ApiClient {
+    transport: Arc<Mutex<Transport>>,
+    options: ClientOptions,
+}
+
+impl ApiClient {
+    pub(crate) fn new(transport: Transport) -> Self {
+        Self {
+            transport: Arc::new(Mutex::new(transport)),
+            options: ClientOptions::default(),
+        }
+    }
+
+    /// Set the timeouts and retries of the requests sent by this client
+    pub fn options(mut self, options: ClientOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn get_options(&self) -> ClientOptions {
+        self.options
+    }
+
+    /// Set how long the requests of this client wait for their response, `None` to wait forever
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.options.timeout = timeout;
+        self
+    }
+
+    /// Returns general information about the instance, `GET /`
+    pub async fn instance_info(&self) -> Result<InstanceInfo> {
+        self.request(Method::GET, "/", ()).await
+    }
+
+    /// Returns the version of the running Firecracker, `GET /version`
+    pub async fn version(&self) -> Result<FirecrackerVersion> {
+        self.request(Method::GET, "/version", ()).await
+    }
+
+    /// Returns the vCPU and memory configuration, `GET /machine-config`
+    pub async fn machine_config(&self) -> Result<MachineConfiguration> {
+        self.request(Method::GET, "/machine-config", ()).await
+    }
+
+    /// Returns the configuration Firecracker has actually applied, `GET /vm/config`
+    pub async fn vm_config(&self) -> Result<FullVmConfiguration> {
+        self.request(Method::GET, "/vm/config", ()).await
+    }
+
+    /// Sends a request to any endpoint of the API and deserializes the JSON response,
+    /// failing if Firecracker responds with an error status
+    ///
+    /// A `()` body sends no body, and a `()` response accepts an empty one.
+    ///
+    /// Exemple:
+    /// ```no_compile
+    /// let info: InstanceInfo = client.request(Method::GET, "/", ()).await?;
+    /// client.request::<_, ()>(Method::PATCH, "/vm", json!({"state": "Paused"})).await?;
+    /// ```
+    pub async fn request<B: Serialize, R: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: B,
+    ) -> Result<R> {
+        let body = serde_json::to_value(body)?;
+        let req = Self::new_request(method, path, (!body.is_null()).then(|| body.to_string()));
+        let Http::Response { body, .. } = self.send(req).await? else {
+            bail!("{path} did not return a response");
+        };
+        let body = match body.is_empty() {
+            true => b"null",
+            false => &body[..],
+        };
+        Ok(serde_json::from_slice(body)?)
+    }
+
+    /// Sends a request to any endpoint of the API and returns the response as is,
+    /// whatever its status
+    pub async fn request_raw(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Vec<u8>>,
+    ) -> Result<http::Response<Vec<u8>>> {
+        let Http::Response {
+            code,
+            header_map,
+            body,
+        } = self.exchange(Self::new_request(method, path, body)).await?
+        else {
+            bail!("{path} did not return a response");
+        };
+        let mut res = http::Response::builder().status(code);
+        for (k, v) in &header_map {
+            res = res.header(k, v);
+        }
+        Ok(res.body(body)?)
+    }
+
+    /// Sends a request with a JSON body, failing if Firecracker responds with an error status
+    pub(crate) async fn send_json<T: Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        body: &T,
+    ) -> Result<Http> {
+        self.send(Self::new_request(method, path, Some(to_string(body)?)))
+            .await
+    }
+
+    fn new_request(method: Method, path: &str, body: Option<impl Into<Vec<u8>>>) -> Http {
+        let req = Http::new_request(path, method)
+            .add_header("Host", "localhost")
+            .add_header("Accept", "application/json");
+        match body {
+            Some(body) => req
+                .add_header("Content-Type", "application/json")
+                .body(body),
+            None => req,
+        }
+    }
+
+    /// Sends a request, failing if Firecracker responds with an error status
+    async fn send(&self, req: Http) -> Result<Http> {
+        let (method, path) = match &req {
+            Http::Request { method, path, .. } => (method.clone(), path.display().to_string()),
+            Http::Response { .. } => bail!("only requests can be sent to Firecracker"),
+        };
+        let res = self.exchange(req).await?;
+        if let Http::Response { code, body, .. } = &res
+            && !code.is_success()
+        {
+            match serde_json::from_slice::<ApiError>(body) {
+                Ok(error) => bail!(
+                    "{method} {path} failed with {code}: {}",
+                    error.fault_message
+                ),
+                Err(_) => bail!(
+                    "{method} {path} failed with {code}: {}",
+                    String::from_utf8_lossy(body)
+                ),
+            }
+        }
+        Ok(res)
+    }
+
+    /// Sends a request over the shared connection and reads the response,
+    /// resending idempotent requests once the connection was lost
+    ///
+    /// The exchange runs in its own task, so that a caller giving up on it
+    /// does not leave the response on the connection for the next one.
+    async fn exchange(&self, req: Http) -> Result<Http> {
+        let retries = match Self::is_idempotent(&req) {
+            true => self.options.max_retries,
+            false => 0,
+        };
+        let mut attempt = 0;
+        loop {
+            let mut transport = self.transport.clone().lock_owned().await;
+            let (sent, timeout) = (req.clone(), self.options.timeout);
+            match tokio::spawn(async move { transport.exchange(sent, timeout).await }).await? {
+                Err(e) if attempt < retries && Transport::is_connection_lost(&e) => {
+                    attempt += 1;
+                    tokio::time::sleep(self.options.backoff.delay(attempt)).await;
+                }
+                exchanged => return exchanged,
+            }
+        }
+    }
+
+    fn is_idempotent(req: &Http) -> bool {
+        match req {
+            Http::Request { method, path, .. } => {
+                *method == Method::GET
+                    || (*method == Method::PUT
+                        && path != Path::new("/actions")
+                        && !path.starts_with("/snapshot"))
+            }
+            Http::Response { .. } => false,
+        }
+    }
+
+    /// Closes the connection and removes the API socket
+    pub(crate) async fn close(&self) -> Result<()> {
+        self.transport.lock().await.close().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use http::StatusCode;
+    use tempfile::tempdir;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::UnixListener,
+    };
+
+    use super::*;
+    use crate::{domain::http::HttpDecoder, infrastructure::connection::TransportKind};
+
+    /// Answers every request on a single connection with its path, `/slow` after a delay
+    async fn client() -> Result<(ApiClient, tempfile::TempDir)> {
+        let dir = tempdir()?;
+        let socket = dir.path().join("api.socket");
+        let listener = UnixListener::bind(&socket)?;
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await?;
+            let mut decoder = HttpDecoder::new();
+            loop {
+                let Some(Http::Request { path, .. }) = decoder.decode()? else {
+                    if socket.read_buf(decoder.buffer()).await? == 0 {
+                        return Ok::<_, anyhow::Error>(());
+                    }
+                    continue;
+                };
+                if path.to_str() == Some("/slow") {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+                let body = serde_json::to_string(&path)?;
+                let res = Http::new_response(StatusCode::OK).body(body);
+                socket.write_all(&res.build()).await?;
+            }
+        });
+        let transport = Transport::connect(TransportKind::Stream, &socket).await?;
+        Ok((ApiClient::new(transport), dir))
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_test() -> Result<()> {
+        let (client, _dir) = client().await?;
+        let tasks: Vec<_> = (0..16)
+            .map(|i| {
+                let client = client.clone();
+                tokio::spawn(async move {
+                    let path = format!("/task/{i}");
+                    let echoed: String = client.request(Method::GET, &path, ()).await?;
+                    assert_eq!(echoed, path);
+                    Ok::<_, anyhow::Error>(())
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await??;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cancelled_request_test() -> Result<()> {
+        let (client, _dir) = client().await?;
+        let slow = client.request::<_, String>(Method::GET, "/slow", ());
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), slow)
+                .await
+                .is_err()
+        );
+        // The response to the abandoned request is not mistaken for this one
+        let echoed: String = client.request(Method::GET, "/next", ()).await?;
+        assert_eq!(echoed, "/next");
+        Ok(())
+    }
+
+    /// Drops the connection instead of answering the first `resets` requests, answers the others
+    async fn flaky(resets: usize) -> Result<(ApiClient, Arc<AtomicUsize>, tempfile::TempDir)> {
+        let dir = tempdir()?;
+        let socket = dir.path().join("api.socket");
+        let listener = UnixListener::bind(&socket)?;
+        let received = Arc::new(AtomicUsize::new(0));
+        let counter = received.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await?;
+                let mut decoder = HttpDecoder::new();
+                loop {
+                    if decoder.decode()?.is_none() {
+                        if socket.read_buf(decoder.buffer()).await? == 0 {
+                            break;
+                        }
+                        continue;
+                    }
+                    if counter.fetch_add(1, Ordering::SeqCst) < resets {
+                        break;
+                    }
+                    let res = Http::new_response(StatusCode::NO_CONTENT);
+                    socket.write_all(&res.build()).await?;
+                }
+            }
+            #[allow(unreachable_code)]
+            Ok::<_, anyhow::Error>(())
+        });
+        let transport = Transport::connect(TransportKind::Stream, &socket).await?;
+        Ok((ApiClient::new(transport), received, dir))
+    }
+
+    #[test]
+    fn idempotent_test() {
+        let idempotent = |method, path| ApiClient::is_idempotent(&Http::new_request(path, method));
+
+        assert!(idempotent(Method::GET, "/"));
+        assert!(idempotent(Method::PUT, "/boot-source"));
+        assert!(idempotent(Method::PUT, "/drives/rootfs"));
+        assert!(!idempotent(Method::PUT, "/actions"));
+        assert!(!idempotent(Method::PUT, "/snapshot/create"));
+        assert!(!idempotent(Method::PUT, "/snapshot/load"));
+        assert!(!idempotent(Method::PATCH, "/vm"));
+    }
+
+    #[tokio::test]
+    async fn retry_test() -> Result<()> {
+        let (client, received, _dir) = flaky(2).await?;
+        client.request::<_, ()>(Method::GET, "/", ()).await?;
+        assert_eq!(received.load(Ordering::SeqCst), 3);
+
+        let (client, received, _dir) = flaky(1).await?;
+        let body = serde_json::json!({"action_type": "InstanceStart"});
+        assert!(
+            client
+                .request::<_, ()>(Method::PUT, "/actions", &body)
+                .await
+                .is_err()
+        );
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+        // The lost connection is re-established for the next request
+        client.request::<_, ()>(Method::PATCH, "/vm", ()).await?;
+
+        let (client, received, _dir) = flaky(4).await?;
+        let error = client
+            .options(ClientOptions {
+                max_retries: 2,
+                ..Default::default()
+            })
+            .request::<_, ()>(Method::PUT, "/mmds", ())
+            .await
+            .unwrap_err();
+        assert!(Transport::is_connection_lost(&error));
+        assert_eq!(received.load(Ordering::SeqCst), 3);
+        Ok(())
+    }
+}
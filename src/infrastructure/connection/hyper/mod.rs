@@ -0,0 +1,186 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, anyhow, bail};
+use bytes::Bytes;
+use http::Request;
+use http_body_util::{BodyExt, Full};
+use hyper::client::conn::http1::{SendRequest, handshake};
+use hyper_util::rt::TokioIo;
+use tokio::{fs, net::UnixStream, task::JoinHandle};
+
+use crate::domain::http::Http;
+
+/// A keep-alive HTTP/1 connection to the API socket, driven by `hyper`
+///
+/// The connection is re-established before the next request once it is closed or fails.
+pub(crate) struct HyperConnection {
+    path: PathBuf,
+    sender: Option<SendRequest<Full<Bytes>>>,
+    driver: Option<JoinHandle<()>>,
+}
+
+impl HyperConnection {
+    pub(crate) async fn connect<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut connection = Self {
+            path: path.as_ref().to_path_buf(),
+            sender: None,
+            driver: None,
+        };
+        connection.reconnect().await?;
+        Ok(connection)
+    }
+
+    /// Sends a request and reads the response
+    pub(crate) async fn exchange(&mut self, req: Http) -> Result<Http> {
+        let Http::Request {
+            path,
+            method,
+            header_map,
+            body,
+        } = req
+        else {
+            bail!("only requests can be sent to Firecracker");
+        };
+        let mut builder = Request::builder()
+            .method(method)
+            .uri(path.to_string_lossy().as_ref());
+        for (k, v) in &header_map {
+            builder = builder.header(k, v);
+        }
+        let request = builder.body(Full::new(Bytes::from(body)))?;
+
+        let alive = match &mut self.sender {
+            Some(sender) => sender.ready().await.is_ok(),
+            None => false,
+        };
+        if !alive {
+            self.reconnect().await?;
+        }
+        let sender = self
+            .sender
+            .as_mut()
+            .ok_or_else(|| anyhow!("not connected to {}", self.path.display()))?;
+
+        let exchanged = async {
+            let (parts, body) = sender.send_request(request).await?.into_parts();
+            Ok::<_, anyhow::Error>((parts, body.collect().await?.to_bytes()))
+        };
+        let (parts, body) = match exchanged.await {
+            Ok(response) => response,
+            Err(e) => {
+                self.disconnect();
+                return Err(e);
+            }
+        };
+
+        let header_map = parts
+            .headers
+            .iter()
+            .map(|(k, v)| Ok((k.to_string(), v.to_str()?.to_string())))
+            .collect::<Result<_>>()?;
+        Ok(Http::Response {
+            code: parts.status,
+            header_map,
+            body: body.to_vec(),
+        })
+    }
+
+    /// Closes the connection and removes the socket
+    pub(crate) async fn close(&mut self) -> Result<()> {
+        self.disconnect();
+        fs::remove_file(&self.path).await?;
+        Ok(())
+    }
+
+    async fn reconnect(&mut self) -> Result<()> {
+        self.disconnect();
+        let stream = UnixStream::connect(&self.path).await?;
+        let (sender, connection) = handshake(TokioIo::new(stream)).await?;
+        self.driver = Some(tokio::spawn(async move {
+            let _ = connection.await;
+        }));
+        self.sender = Some(sender);
+        Ok(())
+    }
+
+    fn disconnect(&mut self) {
+        self.sender = None;
+        if let Some(driver) = self.driver.take() {
+            driver.abort();
+        }
+    }
+}
+
+impl Drop for HyperConnection {
+    fn drop(&mut self) {
+        self.disconnect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use http::{Method, StatusCode};
+    use tempfile::tempdir;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::UnixListener,
+    };
+
+    use super::*;
+    use crate::domain::http::HttpDecoder;
+
+    /// Answers every request with its path, closing each connection after `per_connection` requests
+    fn serve(listener: UnixListener, per_connection: usize) -> JoinHandle<Result<usize>> {
+        tokio::spawn(async move {
+            let mut connections = 0;
+            loop {
+                let (mut socket, _) = listener.accept().await?;
+                connections += 1;
+                let mut decoder = HttpDecoder::new();
+                let mut served = 0;
+                while served < per_connection {
+                    let Some(Http::Request { path, .. }) = decoder.decode()? else {
+                        if socket.read_buf(decoder.buffer()).await? == 0 {
+                            break;
+                        }
+                        continue;
+                    };
+                    if path.to_str() == Some("/wedged") {
+                        return Ok(connections);
+                    }
+                    let res = Http::new_response(StatusCode::OK)
+                        .body(path.to_string_lossy().into_owned());
+                    socket.write_all(&res.build()).await?;
+                    served += 1;
+                }
+            }
+        })
+    }
+
+    fn get(path: &str) -> Http {
+        Http::new_request(path, Method::GET).add_header("Host", "localhost")
+    }
+
+    #[tokio::test]
+    async fn keep_alive_and_reconnect_test() -> Result<()> {
+        let dir = tempdir()?;
+        let socket = dir.path().join("api.socket");
+        let server = serve(UnixListener::bind(&socket)?, 2);
+
+        let mut connection = HyperConnection::connect(&socket).await?;
+        for path in ["/a", "/b", "/c"] {
+            let res = connection.exchange(get(path)).await?;
+            assert_eq!(res.get_body(), path.as_bytes());
+            // Lets the connection notice that the server closed it
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        connection.exchange(get("/wedged")).await.ok();
+
+        // Two requests went over the first connection, the third needed a new one
+        assert_eq!(server.await??, 2);
+        Ok(())
+    }
+}
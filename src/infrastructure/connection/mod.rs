@@ -1,2 +1,7 @@
-pub mod socket;
-pub mod stream;
+#[cfg(feature = "hyper")]
+pub(crate) mod hyper;
+pub(crate) mod socket;
+pub(crate) mod stream;
+mod transport;
+
+pub use transport::{RequestTimeout, Transport, TransportKind};
@@ -1,3 +1,5 @@
+use std::io;
+
 use anyhow::Result;
 use tokio::{
     fs,
@@ -5,17 +7,21 @@ use tokio::{
     net::UnixStream,
 };
 
-use crate::domain::http::Http;
+use crate::domain::http::{Http, HttpDecoder};
 
 /// A structure that allows you to work safely with VMs
 pub(crate) struct Stream {
     stream: UnixStream,
+    decoder: HttpDecoder,
 }
 
 #[allow(unused)]
 impl Stream {
     pub(crate) fn new(stream: UnixStream) -> Self {
-        Self { stream }
+        Self {
+            stream,
+            decoder: HttpDecoder::new(),
+        }
     }
 
     pub async fn send_user_request(&mut self, req: Http) -> Result<()> {
@@ -23,10 +29,22 @@ impl Stream {
         Ok(())
     }
 
+    /// Reads the next message, bytes received past its end are kept for the following one
     pub async fn read_req(&mut self) -> Result<Http> {
-        let mut buf = vec![];
-        self.read_raw(&mut buf).await?;
-        Ok(Http::from(buf))
+        loop {
+            if let Some(message) = self.decoder.decode()? {
+                return Ok(message);
+            }
+            if self.stream.read_buf(self.decoder.buffer()).await? == 0 {
+                return self.decoder.decode_eof()?.ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed before a complete message was received",
+                    )
+                    .into()
+                });
+            }
+        }
     }
 
     pub(crate) async fn send_raw(&mut self, raw: &[u8]) -> Result<()> {
@@ -39,7 +57,7 @@ impl Stream {
     }
 
     /// Safely closes the unix stream
-    pub async fn close(mut self) -> Result<()> {
+    pub async fn close(&mut self) -> Result<()> {
         self.stream.shutdown().await?;
         fs::remove_file(self.stream.peer_addr()?.as_pathname().unwrap()).await?;
         Ok(())
@@ -0,0 +1,261 @@
+use std::{
+    fmt::{self, Display},
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Result, bail};
+use http::Method;
+use serde::Serialize;
+use tokio::fs;
+
+#[cfg(feature = "hyper")]
+use crate::infrastructure::connection::hyper::HyperConnection;
+use crate::{
+    domain::http::Http,
+    infrastructure::connection::{socket::Socket, stream::Stream},
+};
+
+/// Implementations of the connection to the API socket
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum TransportKind {
+    /// The SDK's own HTTP/1.1 codec over a Unix stream
+    #[default]
+    Stream,
+    /// `hyper`'s HTTP/1 client, with keep-alive
+    #[cfg(feature = "hyper")]
+    Hyper,
+}
+
+/// A request got no response in time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestTimeout {
+    pub method: Method,
+    pub path: String,
+    pub after: Duration,
+}
+
+impl Display for RequestTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} got no response within {:?}",
+            self.method, self.path, self.after
+        )
+    }
+}
+
+impl std::error::Error for RequestTimeout {}
+
+/// A connection to the API socket of a Firecracker process
+///
+/// A connection that failed or timed out is dropped and re-established by the next exchange.
+pub struct Transport {
+    kind: TransportKind,
+    path: PathBuf,
+    inner: Option<Inner>,
+}
+
+enum Inner {
+    Stream(Stream),
+    #[cfg(feature = "hyper")]
+    Hyper(HyperConnection),
+}
+
+impl Inner {
+    async fn connect(kind: TransportKind, path: &Path) -> Result<Self> {
+        Ok(match kind {
+            TransportKind::Stream => Self::Stream(Socket::new()?.connect(path).await?),
+            #[cfg(feature = "hyper")]
+            TransportKind::Hyper => Self::Hyper(HyperConnection::connect(path).await?),
+        })
+    }
+
+    async fn exchange(&mut self, req: Http) -> Result<Http> {
+        match self {
+            Self::Stream(stream) => {
+                stream.send_user_request(req).await?;
+                stream.read_req().await
+            }
+            #[cfg(feature = "hyper")]
+            Self::Hyper(connection) => connection.exchange(req).await,
+        }
+    }
+}
+
+impl Transport {
+    /// Connects to the API socket at the specified path
+    pub async fn connect<P: AsRef<Path>>(kind: TransportKind, path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let inner = Inner::connect(kind, &path).await?;
+        Ok(Self {
+            kind,
+            path,
+            inner: Some(inner),
+        })
+    }
+
+    /// Sends a request and reads its response, failing with [`RequestTimeout`]
+    /// if it takes longer than `timeout`
+    pub async fn exchange(&mut self, req: Http, timeout: Option<Duration>) -> Result<Http> {
+        let (method, path) = match &req {
+            Http::Request { method, path, .. } => (method.clone(), path.display().to_string()),
+            Http::Response { .. } => bail!("only requests can be sent to Firecracker"),
+        };
+        let mut inner = match self.inner.take() {
+            Some(inner) => inner,
+            None => Inner::connect(self.kind, &self.path).await?,
+        };
+        let exchanged = match timeout {
+            Some(after) => tokio::time::timeout(after, inner.exchange(req))
+                .await
+                .unwrap_or_else(|_| {
+                    Err(RequestTimeout {
+                        method,
+                        path,
+                        after,
+                    }
+                    .into())
+                }),
+            None => inner.exchange(req).await,
+        };
+        // A late or partial response would be taken for the next one
+        if exchanged.is_ok() {
+            self.inner = Some(inner);
+        }
+        exchanged
+    }
+
+    /// Closes the connection and removes the API socket
+    pub async fn close(&mut self) -> Result<()> {
+        match self.inner.take() {
+            Some(Inner::Stream(mut stream)) => stream.close().await,
+            #[cfg(feature = "hyper")]
+            Some(Inner::Hyper(mut connection)) => connection.close().await,
+            None => Ok(fs::remove_file(&self.path).await?),
+        }
+    }
+
+    /// Returns whether the error means the connection was lost before a response was read
+    pub(crate) fn is_connection_lost(error: &anyhow::Error) -> bool {
+        error.chain().any(|cause| {
+            #[cfg(feature = "hyper")]
+            if let Some(error) = cause.downcast_ref::<hyper::Error>() {
+                return error.is_closed() || error.is_incomplete_message() || error.is_canceled();
+            }
+            cause.downcast_ref::<io::Error>().is_some_and(|error| {
+                matches!(
+                    error.kind(),
+                    io::ErrorKind::BrokenPipe
+                        | io::ErrorKind::ConnectionAborted
+                        | io::ErrorKind::ConnectionReset
+                        | io::ErrorKind::NotConnected
+                        | io::ErrorKind::UnexpectedEof
+                )
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+    use tempfile::tempdir;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::UnixListener,
+    };
+
+    use super::*;
+    use crate::domain::http::HttpDecoder;
+
+    /// Answers with the path of every request, drops the connection on `/reset`
+    /// and never answers `/wedged`
+    fn serve(listener: UnixListener) {
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await?;
+                tokio::spawn(async move {
+                    let mut decoder = HttpDecoder::new();
+                    loop {
+                        let Some(Http::Request { path, .. }) = decoder.decode()? else {
+                            if socket.read_buf(decoder.buffer()).await? == 0 {
+                                return Ok::<_, anyhow::Error>(());
+                            }
+                            continue;
+                        };
+                        match path.to_str() {
+                            Some("/reset") => return Ok(()),
+                            Some("/wedged") => std::future::pending().await,
+                            _ => {}
+                        }
+                        let res = Http::new_response(StatusCode::OK)
+                            .body(path.to_string_lossy().into_owned());
+                        socket.write_all(&res.build()).await?;
+                    }
+                });
+            }
+            #[allow(unreachable_code)]
+            Ok::<_, anyhow::Error>(())
+        });
+    }
+
+    fn get(path: &str) -> Http {
+        Http::new_request(path, Method::GET).add_header("Host", "localhost")
+    }
+
+    fn kinds() -> Vec<TransportKind> {
+        vec![
+            TransportKind::Stream,
+            #[cfg(feature = "hyper")]
+            TransportKind::Hyper,
+        ]
+    }
+
+    #[tokio::test]
+    async fn timeout_test() -> Result<()> {
+        let dir = tempdir()?;
+        let socket = dir.path().join("api.socket");
+        serve(UnixListener::bind(&socket)?);
+
+        for kind in kinds() {
+            let mut transport = Transport::connect(kind, &socket).await?;
+            let error = transport
+                .exchange(get("/wedged"), Some(Duration::from_millis(50)))
+                .await
+                .unwrap_err();
+            assert_eq!(
+                error.downcast_ref::<RequestTimeout>(),
+                Some(&RequestTimeout {
+                    method: Method::GET,
+                    path: "/wedged".into(),
+                    after: Duration::from_millis(50),
+                })
+            );
+            assert!(!Transport::is_connection_lost(&error));
+
+            // The wedged connection was replaced
+            let res = transport.exchange(get("/next"), None).await?;
+            assert_eq!(res.get_body(), b"/next");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reconnect_test() -> Result<()> {
+        let dir = tempdir()?;
+        let socket = dir.path().join("api.socket");
+        serve(UnixListener::bind(&socket)?);
+
+        for kind in kinds() {
+            let mut transport = Transport::connect(kind, &socket).await?;
+            let error = transport.exchange(get("/reset"), None).await.unwrap_err();
+            assert!(Transport::is_connection_lost(&error), "{kind:?}: {error:#}");
+
+            let res = transport.exchange(get("/next"), None).await?;
+            assert_eq!(res.get_body(), b"/next");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,611 @@
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Result, bail};
+use reqwest::{
+    Client, RequestBuilder, Response, StatusCode,
+    header::{CONTENT_RANGE, ETAG, IF_RANGE, RANGE},
+};
+use tokio::io::{self, AsyncWriteExt};
+
+use crate::infrastructure::{
+    cache::{ArtifactCache, CacheEntry, CacheWriter, Unpacked},
+    lockfile::LockedArtifact,
+    s3::Signer,
+    supervisor::Backoff,
+    unpack::{Compression, MAGIC_LEN, unpack},
+};
+
+/// How many times an interrupted download is resumed
+const MAX_RESUMES: u32 = 3;
+
+/// Delay before resuming an interrupted download
+const RESUME_BACKOFF: Backoff = Backoff {
+    initial: Duration::from_millis(500),
+    max: Duration::from_secs(5),
+};
+
+/// Progress of an artifact download
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadProgress {
+    /// Key of the artifact
+    pub key: String,
+    /// Bytes received so far, including those of a resumed partial download
+    pub downloaded: u64,
+    /// Size of the artifact, if the server reports it
+    pub total: Option<u64>,
+}
+
+/// Receives the progress of every download
+pub type ProgressCallback = Arc<dyn Fn(&DownloadProgress) + Send + Sync>;
+
+/// The connection closed before the announced size was received
+#[derive(Debug)]
+struct Incomplete {
+    received: u64,
+    total: u64,
+}
+
+impl fmt::Display for Incomplete {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "received {} of {} bytes", self.received, self.total)
+    }
+}
+
+impl std::error::Error for Incomplete {}
+
+/// The first response of [`fetch_unpacked`], classified by its leading bytes
+enum Opened {
+    /// The download was decoded into the writer
+    Unpacked(Unpacked),
+    /// The artifact is stored as is, `head` was read from `res` to detect its format
+    Plain {
+        res: Response,
+        head: Vec<u8>,
+        total: Option<u64>,
+    },
+}
+
+/// Returns the artifact at the URL from the cache, downloading it unless it is cached
+///
+/// The cache entry of the key is found with the ETag the server reports for the URL.
+/// Requests are signed by `signer` if the URL is in a private bucket.
+pub(crate) async fn fetch(
+    cache: &ArtifactCache,
+    url: &str,
+    key: &str,
+    signer: Option<&Signer>,
+    progress: Option<&ProgressCallback>,
+) -> Result<CacheEntry> {
+    let client = Client::new();
+    let _lock = cache.lock(key).await?;
+    let etag = etag(&client, url, signer).await?;
+    if let Some(entry) = cache.get(key, etag.as_deref()).await? {
+        return Ok(entry);
+    }
+    let mut writer = cache.resume(key, etag.as_deref()).await?;
+    download(
+        &client,
+        url,
+        key,
+        etag.as_deref(),
+        signer,
+        &mut writer,
+        progress,
+    )
+    .await?;
+    writer.commit(key, etag.as_deref()).await
+}
+
+/// Returns the artifact at the URL decompressed, or the member of the tar archive it holds,
+/// downloading it unless it is cached
+///
+/// Downloads compressed with gzip, zstd or xz are decoded while they stream into the cache,
+/// so only the result is stored. A decoder cannot be resumed, so an interrupted download
+/// of an archive or a compressed artifact starts over, plain artifacts are resumed as by [`fetch`].
+pub(crate) async fn fetch_unpacked(
+    cache: &ArtifactCache,
+    url: &str,
+    key: &str,
+    member: Option<&str>,
+    signer: Option<&Signer>,
+    progress: Option<&ProgressCallback>,
+) -> Result<CacheEntry> {
+    let client = Client::new();
+    let _lock = cache.lock(key).await?;
+    let etag = etag(&client, url, signer).await?;
+    let etag = etag.as_deref();
+    if let Some(entry) = cache.get_member(key, etag, member).await? {
+        return Ok(entry);
+    }
+    // Only plain artifacts leave a partial download behind
+    let mut partial = match member {
+        Some(_) => None,
+        None => Some(cache.resume(key, etag).await?),
+    };
+    let mut received = false;
+    if partial.as_ref().is_none_or(|partial| partial.size() == 0) {
+        let mut writer = cache.writer().await?;
+        let mut restarts = 0;
+        let (res, head, total) = loop {
+            match download_unpacked(&client, url, key, member, signer, &mut writer, progress).await
+            {
+                Err(e) if restarts < MAX_RESUMES && is_interrupted(&e) => {
+                    restarts += 1;
+                    writer.truncate().await?;
+                    tokio::time::sleep(RESUME_BACKOFF.delay(restarts)).await;
+                }
+                Err(e) => return Err(e),
+                Ok(Opened::Unpacked(unpacked)) => {
+                    return writer.commit_unpacked(key, etag, unpacked).await;
+                }
+                Ok(Opened::Plain { res, head, total }) => break (res, head, total),
+            }
+        };
+        // Store the response read to detect the format, it is resumed below if interrupted
+        let partial = partial
+            .as_mut()
+            .expect("plain artifacts are fetched without a member");
+        partial.write(&head).await?;
+        match receive(key, res, total, partial, progress).await {
+            Ok(()) => received = true,
+            Err(e) if !is_interrupted(&e) => return Err(e),
+            Err(_) => {}
+        }
+    }
+    let mut partial = partial.expect("plain artifacts are fetched without a member");
+    if !received {
+        download(&client, url, key, etag, signer, &mut partial, progress).await?;
+    }
+    partial.commit(key, etag).await
+}
+
+/// Streams the artifact at the URL through [`unpack`] into the writer
+///
+/// Returns the response without reading further if the artifact is neither compressed nor an archive to extract.
+async fn download_unpacked(
+    client: &Client,
+    url: &str,
+    key: &str,
+    member: Option<&str>,
+    signer: Option<&Signer>,
+    writer: &mut CacheWriter,
+    progress: Option<&ProgressCallback>,
+) -> Result<Opened> {
+    let mut res = send(client, client.get(url), signer)
+        .await?
+        .error_for_status()?;
+    let total = res.content_length();
+    let mut head = vec![];
+    while head.len() < MAGIC_LEN {
+        match res.chunk().await? {
+            Some(chunk) => head.extend_from_slice(&chunk),
+            None => break,
+        }
+    }
+    let compression = Compression::detect(&head);
+    if compression.is_none() && member.is_none() {
+        return Ok(Opened::Plain { res, head, total });
+    }
+
+    let (mut sender, receiver) = io::duplex(1 << 16);
+    let receive = async move {
+        let report = |downloaded| {
+            if let Some(progress) = progress {
+                progress(&DownloadProgress {
+                    key: key.into(),
+                    downloaded,
+                    total,
+                });
+            }
+        };
+        let mut downloaded = head.len() as u64;
+        report(downloaded);
+        sender.write_all(&head).await?;
+        while let Some(chunk) = res.chunk().await? {
+            sender.write_all(&chunk).await?;
+            downloaded += chunk.len() as u64;
+            report(downloaded);
+        }
+        // `sender` is dropped on return, which ends the stream of the decoder
+        match total {
+            Some(total) if downloaded < total => Err(Incomplete {
+                received: downloaded,
+                total,
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    };
+    tokio::try_join!(receive, unpack(receiver, compression, member, writer))?;
+    Ok(Opened::Unpacked(Unpacked {
+        compression: compression.map(|c| c.name().into()),
+        member: member.map(Into::into),
+    }))
+}
+
+/// Returns the artifact recorded in the lockfile, downloading it from the URL unless it is cached
+///
+/// Recorded artifacts are found in the cache by digest, so offline mode needs no prior fetch.
+/// The record is the digest of the unpacked download, see [`fetch_unpacked`].
+/// Fails with [`ArtifactMismatch`](crate::infrastructure::lockfile::ArtifactMismatch) if the download differs from the record.
+pub(crate) async fn fetch_locked(
+    cache: &ArtifactCache,
+    url: &str,
+    locked: &LockedArtifact,
+    member: Option<&str>,
+    signer: Option<&Signer>,
+    progress: Option<&ProgressCallback>,
+) -> Result<CacheEntry> {
+    if let Some(entry) = cache.find(&locked.key, &locked.sha256, locked.size).await? {
+        return Ok(entry);
+    }
+    if cache.is_offline() {
+        bail!(
+            "{} is not in the artifact cache at {} and offline mode is enabled",
+            locked.key,
+            cache.root().display()
+        );
+    }
+    let entry = fetch_unpacked(cache, url, &locked.key, member, signer, progress).await?;
+    locked.verify(&entry)?;
+    Ok(entry)
+}
+
+/// Returns the ETag of the URL, if the server reports one
+async fn etag(client: &Client, url: &str, signer: Option<&Signer>) -> Result<Option<String>> {
+    let res = send(client, client.head(url), signer)
+        .await?
+        .error_for_status()?;
+    Ok(res
+        .headers()
+        .get(ETAG)
+        .and_then(|v| v.to_str().ok())
+        .map(Into::into))
+}
+
+/// Sends the request, signed by `signer` if there is one
+pub(crate) async fn send(
+    client: &Client,
+    req: RequestBuilder,
+    signer: Option<&Signer>,
+) -> Result<Response> {
+    let mut req = req.build()?;
+    if let Some(signer) = signer {
+        signer.sign(&mut req, SystemTime::now())?;
+    }
+    Ok(client.execute(req).await?)
+}
+
+/// Streams the artifact at the URL into the writer, resuming where a previous attempt stopped
+///
+/// Partial content is only appended when the server confirms it still has the same `etag`,
+/// without an ETag every attempt starts from zero.
+pub(crate) async fn download(
+    client: &Client,
+    url: &str,
+    key: &str,
+    etag: Option<&str>,
+    signer: Option<&Signer>,
+    writer: &mut CacheWriter,
+    progress: Option<&ProgressCallback>,
+) -> Result<()> {
+    let mut resumes = 0;
+    loop {
+        match attempt(client, url, key, etag, signer, writer, progress).await {
+            Err(e) if resumes < MAX_RESUMES && is_interrupted(&e) => {
+                resumes += 1;
+                tokio::time::sleep(RESUME_BACKOFF.delay(resumes)).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn attempt(
+    client: &Client,
+    url: &str,
+    key: &str,
+    etag: Option<&str>,
+    signer: Option<&Signer>,
+    writer: &mut CacheWriter,
+    progress: Option<&ProgressCallback>,
+) -> Result<()> {
+    if etag.is_none() {
+        // Nothing proves the partial file is a prefix of the current artifact
+        writer.truncate().await?;
+    }
+    let offset = writer.size();
+    let mut req = client.get(url);
+    if let (1.., Some(etag)) = (offset, etag) {
+        req = req
+            .header(RANGE, format!("bytes={offset}-"))
+            .header(IF_RANGE, etag);
+    }
+    let mut res = send(client, req, signer).await?;
+    if res.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        // The partial file is not a prefix of the artifact
+        writer.truncate().await?;
+        res = send(client, client.get(url), signer).await?;
+    }
+    let res = res.error_for_status()?;
+    let total = match content_range(&res) {
+        Some((start, total)) if res.status() == StatusCode::PARTIAL_CONTENT && start == offset => {
+            total
+        }
+        Some(_) => bail!("{key}: unexpected Content-Range in the response to bytes={offset}-"),
+        None => {
+            // The whole artifact is sent again
+            writer.truncate().await?;
+            res.content_length()
+        }
+    };
+    receive(key, res, total, writer, progress).await
+}
+
+/// Appends the body of the response to the writer, which must hold everything sent before it
+async fn receive(
+    key: &str,
+    mut res: Response,
+    total: Option<u64>,
+    writer: &mut CacheWriter,
+    progress: Option<&ProgressCallback>,
+) -> Result<()> {
+    let report = |downloaded| {
+        if let Some(progress) = progress {
+            progress(&DownloadProgress {
+                key: key.into(),
+                downloaded,
+                total,
+            });
+        }
+    };
+    report(writer.size());
+    while let Some(chunk) = res.chunk().await? {
+        writer.write(&chunk).await?;
+        report(writer.size());
+    }
+
+    match total {
+        Some(total) if writer.size() < total => Err(Incomplete {
+            received: writer.size(),
+            total,
+        }
+        .into()),
+        Some(total) if writer.size() > total => {
+            let received = writer.size();
+            writer.truncate().await?;
+            bail!("{key}: received {received} bytes, but the server announced {total}")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Parses the first byte and the total size of `Content-Range: bytes <first>-<last>/<total>`
+fn content_range(res: &Response) -> Option<(u64, Option<u64>)> {
+    let range = res.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let (span, total) = range.strip_prefix("bytes ")?.split_once('/')?;
+    let (first, _) = span.split_once('-')?;
+    Some((first.parse().ok()?, total.parse().ok()))
+}
+
+/// Returns whether the connection was lost, in which case the download can be resumed
+///
+/// Reading a body that ends early is reported by `reqwest` as a decoding error.
+fn is_interrupted(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause.is::<Incomplete>()
+            || cause.downcast_ref::<reqwest::Error>().is_some_and(|e| {
+                e.is_body() || e.is_decode() || e.is_connect() || e.is_timeout() || e.is_request()
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+
+    use async_compression::tokio::write::GzipEncoder;
+    use tempfile::tempdir;
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    use super::*;
+    use crate::domain::http::{Http, HttpDecoder};
+
+    /// Serves `artifact` and records the `Range` header of every GET request
+    ///
+    /// The first response to a GET is cut after `cut` bytes, and `Range` is ignored unless `ranges` is set.
+    async fn serve(
+        artifact: Vec<u8>,
+        cut: usize,
+        ranges: bool,
+    ) -> Result<(SocketAddr, Arc<Mutex<Vec<Option<String>>>>)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let requests = Arc::new(Mutex::new(vec![]));
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await?;
+                let mut decoder = HttpDecoder::new();
+                let req = loop {
+                    if let Some(req) = decoder.decode()? {
+                        break req;
+                    }
+                    socket.read_buf(decoder.buffer()).await?;
+                };
+                let range = req.header("range").map(String::from);
+                if matches!(&req, Http::Request { method, .. } if method == http::Method::HEAD) {
+                    let head = Http::new_response(http::StatusCode::OK)
+                        .add_header("Content-Length", &artifact.len().to_string());
+                    socket.write_all(&head.build()).await?;
+                    continue;
+                }
+                let first = {
+                    let mut requests = recorded.lock().unwrap();
+                    requests.push(range.clone());
+                    requests.len() == 1
+                };
+                let start = match range
+                    .and_then(|r| r.strip_prefix("bytes=")?.strip_suffix('-')?.parse().ok())
+                {
+                    Some(start) if ranges => start,
+                    _ => 0,
+                };
+                let body = &artifact[start..];
+                let head = match start {
+                    0 => Http::new_response(http::StatusCode::OK),
+                    _ => Http::new_response(http::StatusCode::PARTIAL_CONTENT).add_header(
+                        "Content-Range",
+                        &format!("bytes {start}-{}/{}", artifact.len() - 1, artifact.len()),
+                    ),
+                }
+                .add_header("Content-Length", &body.len().to_string());
+                let mut raw = head.build().into_vec();
+                raw.extend_from_slice(match first {
+                    true => &body[..cut.min(body.len())],
+                    false => body,
+                });
+                socket.write_all(&raw).await?;
+            }
+            #[allow(unreachable_code)]
+            Ok::<_, anyhow::Error>(())
+        });
+        Ok((addr, requests))
+    }
+
+    fn artifact() -> Vec<u8> {
+        (0..100_000u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn resume_test() -> Result<()> {
+        let (addr, requests) = serve(artifact(), 30_000, true).await?;
+        let dir = tempdir()?;
+        let cache = ArtifactCache::new(dir.path());
+        let reported = Arc::new(Mutex::new(vec![]));
+        let progress: ProgressCallback = {
+            let reported = reported.clone();
+            Arc::new(move |p: &DownloadProgress| reported.lock().unwrap().push(p.clone()))
+        };
+
+        let mut writer = cache.resume("rootfs.ext4", Some("\"e1\"")).await?;
+        let url = format!("http://{addr}/rootfs.ext4");
+        download(
+            &Client::new(),
+            &url,
+            "rootfs.ext4",
+            Some("\"e1\""),
+            None,
+            &mut writer,
+            Some(&progress),
+        )
+        .await?;
+        let entry = writer.commit("rootfs.ext4", Some("\"e1\"")).await?;
+
+        assert_eq!(tokio::fs::read(entry.path()).await?, artifact());
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [None, Some("bytes=30000-".into())]
+        );
+        let reported = reported.lock().unwrap();
+        assert!(
+            reported
+                .windows(2)
+                .all(|p| p[0].downloaded <= p[1].downloaded)
+        );
+        assert_eq!(
+            reported.last(),
+            Some(&DownloadProgress {
+                key: "rootfs.ext4".into(),
+                downloaded: 100_000,
+                total: Some(100_000),
+            })
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn restart_test() -> Result<()> {
+        let (addr, requests) = serve(artifact(), 30_000, true).await?;
+        let dir = tempdir()?;
+        let cache = ArtifactCache::new(dir.path());
+
+        let mut writer = cache.resume("vmlinux", None).await?;
+        let url = format!("http://{addr}/vmlinux");
+        download(
+            &Client::new(),
+            &url,
+            "vmlinux",
+            None,
+            None,
+            &mut writer,
+            None,
+        )
+        .await?;
+        assert_eq!(writer.size(), 100_000);
+        let entry = writer.commit("vmlinux", None).await?;
+        assert_eq!(tokio::fs::read(entry.path()).await?, artifact());
+        // Without an ETag the interrupted download is never resumed with a Range
+        assert_eq!(*requests.lock().unwrap(), [None, None]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unpacked_restart_test() -> Result<()> {
+        let mut compressed = vec![];
+        let mut encoder = GzipEncoder::new(&mut compressed);
+        encoder.write_all(&artifact()).await?;
+        encoder.shutdown().await?;
+        let (addr, requests) = serve(compressed.clone(), compressed.len() / 2, true).await?;
+        let dir = tempdir()?;
+        let cache = ArtifactCache::new(dir.path());
+
+        let url = format!("http://{addr}/vmlinux.gz");
+        let entry = fetch_unpacked(&cache, &url, "vmlinux.gz", None, None, None).await?;
+        assert_eq!(tokio::fs::read(entry.path()).await?, artifact());
+        assert_eq!(
+            entry.unpacked.and_then(|u| u.compression).as_deref(),
+            Some("gzip")
+        );
+        // A decoder cannot resume, so the download starts over
+        assert_eq!(*requests.lock().unwrap(), [None, None]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unpacked_plain_test() -> Result<()> {
+        let (addr, requests) = serve(artifact(), usize::MAX, true).await?;
+        let dir = tempdir()?;
+        let cache = ArtifactCache::new(dir.path());
+
+        let url = format!("http://{addr}/rootfs.ext4");
+        let entry = fetch_unpacked(&cache, &url, "rootfs.ext4", None, None, None).await?;
+        assert_eq!(tokio::fs::read(entry.path()).await?, artifact());
+        assert_eq!(entry.unpacked, None);
+        assert_eq!(cache.get("rootfs.ext4", None).await?, Some(entry));
+        // The response read to detect the format is stored, not requested again
+        assert_eq!(*requests.lock().unwrap(), [None]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unpacked_plain_interrupted_test() -> Result<()> {
+        let (addr, requests) = serve(artifact(), 30_000, true).await?;
+        let dir = tempdir()?;
+        let cache = ArtifactCache::new(dir.path());
+
+        let url = format!("http://{addr}/rootfs.ext4");
+        let entry = fetch_unpacked(&cache, &url, "rootfs.ext4", None, None, None).await?;
+        assert_eq!(tokio::fs::read(entry.path()).await?, artifact());
+        // Without an ETag the plain download restarts from zero
+        assert_eq!(*requests.lock().unwrap(), [None, None]);
+        Ok(())
+    }
+}
@@ -0,0 +1,389 @@
+use std::{
+    env,
+    fs::Permissions,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use reqwest::Client;
+use serde::Serialize;
+use tokio::{fs, process::Command};
+
+use crate::{
+    domain::compat::Version,
+    infrastructure::{
+        cache::ArtifactCache,
+        download::{ProgressCallback, fetch},
+        s3::Arch,
+    },
+};
+
+/// Installs the Firecracker and jailer binaries of a release
+///
+/// Every release is unpacked into its own directory, so several versions can be installed side by side.
+///
+/// Layout:
+/// ```text
+/// <root>/
+/// ├── v1.13.1-x86_64/
+/// │   ├── firecracker
+/// │   └── jailer
+/// └── tmp/               releases being unpacked
+/// ```
+///
+/// Exemple:
+/// ```no_compile
+/// let installation = FirecrackerInstaller::default()
+///     .install("1.13.1".parse()?, None)
+///     .await?;
+/// let process = FirecrackerStartup::new()
+///     .firecracker_release(installation.version())
+///     .start().await?;
+/// ```
+#[derive(Clone, Serialize)]
+pub struct FirecrackerInstaller {
+    root: PathBuf,
+    release_url: String,
+    arch: Arch,
+    cache: ArtifactCache,
+    #[serde(skip)]
+    progress: Option<ProgressCallback>,
+}
+
+/// An installed release
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Installation {
+    version: Version,
+    dir: PathBuf,
+}
+
+impl Installation {
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn firecracker(&self) -> PathBuf {
+        self.dir.join("firecracker")
+    }
+
+    pub fn jailer(&self) -> PathBuf {
+        self.dir.join("jailer")
+    }
+}
+
+impl FirecrackerInstaller {
+    /// Creates an installer unpacking releases into the specified directory
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            release_url: "https://github.com/firecracker-microvm/firecracker/releases/download"
+                .into(),
+            arch: Arch::host(),
+            cache: ArtifactCache::default(),
+            progress: None,
+        }
+    }
+
+    /// Set the URL the releases are downloaded from, laid out like the GitHub releases
+    ///
+    /// The archive of a release is `<url>/v<version>/firecracker-v<version>-<arch>.tgz`.
+    pub fn release_url(mut self, url: impl Into<String>) -> Self {
+        self.release_url = url.into().trim_end_matches('/').into();
+        self
+    }
+
+    /// Set the architecture of the binaries, the one of the host by default
+    pub fn arch(mut self, arch: Arch) -> Self {
+        self.arch = arch;
+        self
+    }
+
+    /// Set the cache holding the downloaded archives
+    pub fn artifact_cache(mut self, cache: ArtifactCache) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Set the callback receiving the progress of downloads
+    pub fn progress(mut self, callback: Option<ProgressCallback>) -> Self {
+        self.progress = callback;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the directory of the release, whether it is installed or not
+    pub fn path(&self, version: Version) -> PathBuf {
+        self.root.join(self.dir_name(version))
+    }
+
+    /// Returns the installation of the release, if it is installed
+    pub async fn installation(&self, version: Version) -> Option<Installation> {
+        let installation = Installation {
+            version,
+            dir: self.path(version),
+        };
+        let installed = fs::try_exists(installation.firecracker()).await.ok()?
+            && fs::try_exists(installation.jailer()).await.ok()?;
+        installed.then_some(installation)
+    }
+
+    /// Returns the installed releases, from the oldest to the latest
+    pub async fn installed(&self) -> Result<Vec<Installation>> {
+        let mut dir = match fs::read_dir(&self.root).await {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let suffix = format!("-{}", self.arch.as_str());
+        let mut installed = vec![];
+        while let Some(entry) = dir.next_entry().await? {
+            let name = entry.file_name();
+            let Some(version) = name
+                .to_str()
+                .and_then(|name| name.strip_suffix(&suffix)?.strip_prefix('v')?.parse().ok())
+            else {
+                continue;
+            };
+            installed.extend(self.installation(version).await);
+        }
+        installed.sort_by_key(Installation::version);
+        Ok(installed)
+    }
+
+    /// Installs the release unless it is already installed
+    ///
+    /// The archive must have the SHA-256 digest `sha256` if it is specified,
+    /// the one published next to it in `<archive>.sha256.txt` otherwise.
+    pub async fn install(&self, version: Version, sha256: Option<&str>) -> Result<Installation> {
+        if let Some(installation) = self.installation(version).await {
+            return Ok(installation);
+        }
+        let name = format!("firecracker-v{version}-{}", self.arch.as_str());
+        let url = format!("{}/v{version}/{name}.tgz", self.release_url);
+        let expected = match sha256 {
+            Some(sha256) => sha256.to_ascii_lowercase(),
+            None => self.published_sha256(&url).await?,
+        };
+
+        let key = format!("firecracker/v{version}/{name}.tgz");
+        let archive = fetch(&self.cache, &url, &key, None, self.progress.as_ref()).await?;
+        if archive.sha256 != expected {
+            bail!(
+                "the archive of Firecracker {version} has SHA-256 {}, but {expected} was expected",
+                archive.sha256
+            );
+        }
+        self.unpack(version, archive.path()).await?;
+        self.installation(version)
+            .await
+            .ok_or_else(|| anyhow!("Firecracker {version} was not installed"))
+    }
+
+    /// Returns the digest in `<archive>.sha256.txt`, formatted like the output of `sha256sum`
+    async fn published_sha256(&self, url: &str) -> Result<String> {
+        let checksum = Client::new()
+            .get(format!("{url}.sha256.txt"))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        match checksum.split_whitespace().next() {
+            Some(sha256) if sha256.len() == 64 => Ok(sha256.to_ascii_lowercase()),
+            _ => bail!("{url}.sha256.txt has no SHA-256 digest"),
+        }
+    }
+
+    /// Unpacks the binaries of the archive into the directory of the release
+    ///
+    /// They are moved into place at once, so a concurrent installation never sees half of them.
+    async fn unpack(&self, version: Version, archive: &Path) -> Result<()> {
+        let tmp = self.root.join("tmp");
+        fs::create_dir_all(&tmp).await?;
+        let temp = tempfile::tempdir_in(&tmp)?;
+        let temp = temp.path();
+        let status = Command::new("tar")
+            .arg("-xzf")
+            .arg(archive)
+            .arg("-C")
+            .arg(temp)
+            .status()
+            .await
+            .context("failed to run tar")?;
+        if !status.success() {
+            bail!("tar failed to unpack {}: {status}", archive.display());
+        }
+        let arch = self.arch.as_str();
+        let release = temp.join(format!("release-v{version}-{arch}"));
+        let dir = temp.join(self.dir_name(version));
+        fs::create_dir(&dir).await?;
+        for binary in ["firecracker", "jailer"] {
+            let path = dir.join(binary);
+            fs::rename(release.join(format!("{binary}-v{version}-{arch}")), &path)
+                .await
+                .with_context(|| format!("the archive has no {binary} binary"))?;
+            fs::set_permissions(&path, Permissions::from_mode(0o755)).await?;
+        }
+        match fs::rename(&dir, self.path(version)).await {
+            Ok(()) => Ok(()),
+            // Installed concurrently
+            Err(_) if self.installation(version).await.is_some() => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn dir_name(&self, version: Version) -> String {
+        format!("v{version}-{}", self.arch.as_str())
+    }
+}
+
+impl Default for FirecrackerInstaller {
+    /// Installs into `FIRECRACKER_RELEASES`, `~/.firecracker/releases` by default
+    fn default() -> Self {
+        Self::new(
+            env::var_os("FIRECRACKER_RELEASES")
+                .map(PathBuf::from)
+                .unwrap_or(
+                    env::home_dir()
+                        .unwrap_or(env::temp_dir())
+                        .join(".firecracker/releases"),
+                ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, net::SocketAddr};
+
+    use http::{Method, StatusCode};
+    use sha2::{Digest, Sha256};
+    use tempfile::tempdir;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+    use crate::domain::http::{Http, HttpDecoder};
+
+    /// Serves the files like a release server
+    async fn serve(files: HashMap<String, Vec<u8>>) -> Result<SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await?;
+                let mut decoder = HttpDecoder::new();
+                let (method, path) = loop {
+                    if let Some(Http::Request { method, path, .. }) = decoder.decode()? {
+                        break (method, path);
+                    }
+                    socket.read_buf(decoder.buffer()).await?;
+                };
+                let res = match files.get(path.to_str().unwrap()) {
+                    Some(file) if method == Method::HEAD => Http::new_response(StatusCode::OK)
+                        .add_header("Content-Length", &file.len().to_string()),
+                    Some(file) => Http::new_response(StatusCode::OK).body(file.clone()),
+                    None => Http::new_response(StatusCode::NOT_FOUND),
+                };
+                socket.write_all(&res.build()).await?;
+            }
+            #[allow(unreachable_code)]
+            Ok::<_, anyhow::Error>(())
+        });
+        Ok(addr)
+    }
+
+    /// Packs a release archive laid out like the published ones
+    async fn archive(dir: &Path, version: &str) -> Result<Vec<u8>> {
+        let release = dir.join(format!("release-v{version}-x86_64"));
+        fs::create_dir_all(&release).await?;
+        for binary in ["firecracker", "jailer"] {
+            let path = release.join(format!("{binary}-v{version}-x86_64"));
+            fs::write(path, format!("#!/bin/sh\necho {binary} v{version}\n")).await?;
+        }
+        let archive = dir.join("release.tgz");
+        let status = Command::new("tar")
+            .arg("-czf")
+            .arg(&archive)
+            .arg("-C")
+            .arg(dir)
+            .arg(format!("release-v{version}-x86_64"))
+            .status()
+            .await?;
+        assert!(status.success());
+        Ok(fs::read(archive).await?)
+    }
+
+    #[tokio::test]
+    async fn install_test() -> Result<()> {
+        let dir = tempdir()?;
+        let archive = archive(dir.path(), "1.13.1").await?;
+        let sha256 = hex::encode(Sha256::digest(&archive));
+        let name = "/v1.13.1/firecracker-v1.13.1-x86_64.tgz";
+        let checksum = format!("{sha256}  firecracker-v1.13.1-x86_64.tgz\n");
+        let files = HashMap::from([
+            (name.to_string(), archive),
+            (format!("{name}.sha256.txt"), checksum.into_bytes()),
+        ]);
+        let addr = serve(files).await?;
+        let installer = FirecrackerInstaller::new(dir.path().join("releases"))
+            .release_url(format!("http://{addr}/"))
+            .arch(Arch::X86_64)
+            .artifact_cache(ArtifactCache::new(dir.path().join("cache")));
+        let version = Version::new(1, 13, 1);
+        assert_eq!(installer.installation(version).await, None);
+
+        let installation = installer.install(version, None).await?;
+        assert_eq!(installation.dir(), installer.path(version));
+        let output = Command::new(installation.jailer()).output().await?;
+        assert_eq!(output.stdout, b"jailer v1.13.1\n");
+        assert_eq!(
+            installer.installed().await?.as_slice(),
+            std::slice::from_ref(&installation)
+        );
+        assert_eq!(
+            installer.install(version, Some(&sha256)).await?,
+            installation
+        );
+
+        let error = installer
+            .install(Version::new(1, 12, 0), Some(&sha256))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("404"), "{error:#}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn checksum_mismatch_test() -> Result<()> {
+        let dir = tempdir()?;
+        let archive = archive(dir.path(), "1.13.1").await?;
+        let name = "/v1.13.1/firecracker-v1.13.1-x86_64.tgz";
+        let addr = serve(HashMap::from([(name.to_string(), archive)])).await?;
+        let installer = FirecrackerInstaller::new(dir.path().join("releases"))
+            .release_url(format!("http://{addr}"))
+            .arch(Arch::X86_64)
+            .artifact_cache(ArtifactCache::new(dir.path().join("cache")));
+        let version = Version::new(1, 13, 1);
+
+        let error = installer
+            .install(version, Some(&"0".repeat(64)))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("was expected"), "{error:#}");
+        assert_eq!(installer.installation(version).await, None);
+        // No checksum is published
+        assert!(installer.install(version, None).await.is_err());
+        Ok(())
+    }
+}
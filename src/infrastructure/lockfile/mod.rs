@@ -0,0 +1,182 @@
+use std::{
+    fmt::{self, Display},
+    path::Path,
+};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::infrastructure::{cache::CacheEntry, source::ArtifactKind};
+
+/// Version of the lockfile format
+const LOCKFILE_VERSION: u32 = 1;
+
+/// The downloaded kernel, initrd and rootfs a VM boots from, pinned for reproducible images
+///
+/// Exemple:
+/// ```json
+/// {
+///   "version": 1,
+///   "kernel": {
+///     "key": "firecracker-ci/v1.10/x86_64/vmlinux-5.10.239",
+///     "size": 40271136,
+///     "sha256": "9f2c…"
+///   }
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lockfile {
+    #[serde(default)]
+    version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kernel: Option<LockedArtifact>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initrd: Option<LockedArtifact>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rootfs: Option<LockedArtifact>,
+}
+
+/// An artifact recorded in a [`Lockfile`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockedArtifact {
+    pub key: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl LockedArtifact {
+    /// Fails with [`ArtifactMismatch`] unless the entry has the recorded size and digest
+    pub fn verify(&self, entry: &CacheEntry) -> Result<(), ArtifactMismatch> {
+        match entry.size == self.size && entry.sha256.eq_ignore_ascii_case(&self.sha256) {
+            true => Ok(()),
+            false => Err(ArtifactMismatch {
+                expected: self.clone(),
+                size: entry.size,
+                sha256: entry.sha256.clone(),
+            }),
+        }
+    }
+}
+
+impl From<&CacheEntry> for LockedArtifact {
+    fn from(entry: &CacheEntry) -> Self {
+        Self {
+            key: entry.key.clone(),
+            size: entry.size,
+            sha256: entry.sha256.clone(),
+        }
+    }
+}
+
+/// A downloaded artifact differs from the one recorded in the lockfile
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtifactMismatch {
+    pub expected: LockedArtifact,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl Display for ArtifactMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} has {} bytes with SHA-256 {}, but the lockfile records {} bytes with SHA-256 {}",
+            self.expected.key, self.size, self.sha256, self.expected.size, self.expected.sha256
+        )
+    }
+}
+
+impl std::error::Error for ArtifactMismatch {}
+
+impl Lockfile {
+    /// Reads the lockfile, returning `None` if it does not exist
+    pub async fn read<P: AsRef<Path>>(path: P) -> Result<Option<Self>> {
+        let path = path.as_ref();
+        let raw = match fs::read(path).await {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let lockfile: Self = serde_json::from_slice(&raw)
+            .with_context(|| format!("failed to parse the lockfile {}", path.display()))?;
+        if lockfile.version != LOCKFILE_VERSION {
+            bail!(
+                "{} has version {} of the lockfile format, only {LOCKFILE_VERSION} is supported",
+                path.display(),
+                lockfile.version
+            );
+        }
+        Ok(Some(lockfile))
+    }
+
+    /// Replaces the lockfile atomically
+    pub async fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let lockfile = Self {
+            version: LOCKFILE_VERSION,
+            ..self.clone()
+        };
+        let mut raw = serde_json::to_vec_pretty(&lockfile)?;
+        raw.push(b'\n');
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        fs::write(&temp, raw).await?;
+        fs::rename(&temp, path).await?;
+        Ok(())
+    }
+
+    /// Returns the recorded artifact of the kind
+    pub fn get(&self, kind: ArtifactKind) -> Option<&LockedArtifact> {
+        match kind {
+            ArtifactKind::Kernel => self.kernel.as_ref(),
+            ArtifactKind::Initrd => self.initrd.as_ref(),
+            ArtifactKind::Rootfs => self.rootfs.as_ref(),
+        }
+    }
+
+    /// Records the artifact of the kind, or removes the record
+    pub fn set(&mut self, kind: ArtifactKind, artifact: Option<LockedArtifact>) {
+        match kind {
+            ArtifactKind::Kernel => self.kernel = artifact,
+            ArtifactKind::Initrd => self.initrd = artifact,
+            ArtifactKind::Rootfs => self.rootfs = artifact,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn write_and_read_test() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("firecracker.lock");
+        assert_eq!(Lockfile::read(&path).await?, None);
+
+        let mut lockfile = Lockfile::default();
+        lockfile.set(
+            ArtifactKind::Kernel,
+            Some(LockedArtifact {
+                key: "firecracker-ci/v1.10/x86_64/vmlinux-5.10.239".into(),
+                size: 12,
+                sha256: "ab".repeat(32),
+            }),
+        );
+        lockfile.write(&path).await?;
+        let read = Lockfile::read(&path).await?.unwrap();
+        assert_eq!(
+            read.get(ArtifactKind::Kernel),
+            lockfile.get(ArtifactKind::Kernel)
+        );
+        assert_eq!(read.get(ArtifactKind::Rootfs), None);
+        assert!(fs::read_to_string(&path).await?.contains("\"version\": 1"));
+
+        fs::write(&path, r#"{"version": 2}"#).await?;
+        assert!(Lockfile::read(&path).await.is_err());
+        Ok(())
+    }
+}
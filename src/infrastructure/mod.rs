@@ -1,4 +1,12 @@
-pub(crate) mod connection;
-pub(crate) mod fs;
+pub mod cache;
+pub mod client;
+pub mod connection;
+pub mod download;
+pub mod installer;
+pub mod lockfile;
 pub mod process;
-pub(crate) mod s3;
+pub mod s3;
+pub mod source;
+pub mod supervisor;
+pub(crate) mod unpack;
+pub mod workspace;
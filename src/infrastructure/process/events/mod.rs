@@ -0,0 +1,222 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use tokio::{sync::broadcast, task::JoinHandle};
+
+use crate::{
+    domain::models::InstanceState,
+    infrastructure::{client::ApiClient, process::monitor::ProcessExit},
+};
+
+/// Number of events a slow subscriber may lag behind before losing them
+const EVENTS_CAPACITY: usize = 64;
+
+/// A change in the lifecycle of a VM
+#[derive(Debug, Clone)]
+pub enum VmEventKind {
+    /// The Firecracker process was spawned
+    Spawned { pid: Option<u32> },
+    /// The API socket accepts connections
+    ApiReady,
+    /// Boot source, drives and network interfaces were applied
+    Configured,
+    /// The guest is running
+    Running,
+    /// The guest was paused
+    Paused,
+    /// The guest was resumed after a pause
+    Resumed,
+    /// A snapshot of the paused guest was written
+    SnapshotCreated {
+        snapshot_path: PathBuf,
+        mem_file_path: PathBuf,
+    },
+    /// The guest kernel reported a panic on the console
+    GuestPanicked,
+    /// The Firecracker process exited
+    Exited(ProcessExit),
+}
+
+/// A lifecycle event with the time it was observed
+#[derive(Debug, Clone)]
+pub struct VmEvent {
+    pub timestamp: SystemTime,
+    pub kind: VmEventKind,
+}
+
+/// Guest state as last known by the SDK
+struct Observed {
+    state: InstanceState,
+    /// Bumped on every change made by the SDK, so that polls sent before it are discarded
+    generation: u64,
+}
+
+/// Event stream of the VMs started from one `FirecrackerStartup`
+///
+/// Note: Clones share the channel, so the processes relaunched by a supervisor publish to the same subscribers.
+/// The state of every process is tracked by its own [`EventEmitter`].
+#[derive(Clone)]
+pub(crate) struct EventChannel(broadcast::Sender<VmEvent>);
+
+impl EventChannel {
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<VmEvent> {
+        self.0.subscribe()
+    }
+}
+
+impl Default for EventChannel {
+    fn default() -> Self {
+        Self(broadcast::channel(EVENTS_CAPACITY).0)
+    }
+}
+
+/// Sender side of the event stream of one process, shared by the SDK calls and the background tasks
+#[derive(Clone)]
+pub(crate) struct EventEmitter {
+    sender: broadcast::Sender<VmEvent>,
+    observed: Arc<Mutex<Observed>>,
+}
+
+impl EventEmitter {
+    /// Creates the emitter of a new process, publishing into `channel`
+    pub(crate) fn new(channel: &EventChannel) -> Self {
+        Self {
+            sender: channel.0.clone(),
+            observed: Arc::new(Mutex::new(Observed {
+                state: InstanceState::NotStarted,
+                generation: 0,
+            })),
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<VmEvent> {
+        self.sender.subscribe()
+    }
+
+    pub(crate) fn emit(&self, kind: VmEventKind) {
+        if matches!(kind, VmEventKind::Spawned { .. }) {
+            let mut observed = self.observed.lock().unwrap();
+            observed.state = InstanceState::NotStarted;
+            observed.generation += 1;
+        }
+        let _ = self.sender.send(VmEvent {
+            timestamp: SystemTime::now(),
+            kind,
+        });
+    }
+
+    /// Emits `Running`, `Paused` or `Resumed` if the guest state has changed
+    ///
+    /// A polled state is only applied if the SDK has not changed the state since `polled` was read.
+    fn transition(&self, to: InstanceState, polled: Option<u64>) {
+        let from = {
+            let mut observed = self.observed.lock().unwrap();
+            match polled {
+                Some(generation) if generation != observed.generation => return,
+                Some(_) => {}
+                None => observed.generation += 1,
+            }
+            std::mem::replace(&mut observed.state, to)
+        };
+        match (from, to) {
+            (InstanceState::NotStarted, InstanceState::Running) => self.emit(VmEventKind::Running),
+            (InstanceState::Paused, InstanceState::Running) => self.emit(VmEventKind::Resumed),
+            (InstanceState::Running, InstanceState::Paused) => self.emit(VmEventKind::Paused),
+            _ => {}
+        }
+    }
+
+    pub(crate) fn running(&self) {
+        self.transition(InstanceState::Running, None)
+    }
+
+    pub(crate) fn paused(&self) {
+        self.transition(InstanceState::Paused, None)
+    }
+
+    fn generation(&self) -> u64 {
+        self.observed.lock().unwrap().generation
+    }
+
+    /// Emits `GuestPanicked` if the console line reports a kernel panic
+    pub(crate) fn console_line(&self, line: &str) {
+        if line.contains("Kernel panic") {
+            self.emit(VmEventKind::GuestPanicked);
+        }
+    }
+
+    /// Polls `GET /` through `client` and emits the observed state changes
+    ///
+    /// Note: `client` should own a separate connection, so that polls never wait behind the SDK calls.
+    pub(crate) fn spawn_poller(&self, client: ApiClient, interval: Duration) -> JoinHandle<()> {
+        let emitter = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let generation = emitter.generation();
+                if let Ok(info) = client.instance_info().await {
+                    emitter.transition(info.state, Some(generation));
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transitions_test() {
+        let channel = EventChannel::default();
+        let emitter = EventEmitter::new(&channel);
+        let mut events = channel.subscribe();
+
+        emitter.running();
+        emitter.running();
+        emitter.paused();
+        let stale = emitter.generation() - 1;
+        emitter.transition(InstanceState::Running, Some(stale));
+        emitter.running();
+
+        let kinds: Vec<_> = std::iter::from_fn(|| events.try_recv().ok())
+            .map(|e| e.kind)
+            .collect();
+        assert!(matches!(
+            kinds.as_slice(),
+            [
+                VmEventKind::Running,
+                VmEventKind::Paused,
+                VmEventKind::Resumed
+            ]
+        ));
+    }
+
+    #[test]
+    fn channel_clone_test() {
+        let channel = EventChannel::default();
+        let cloned = channel.clone();
+        let mut events = channel.subscribe();
+        let mut other = cloned.subscribe();
+
+        let first = EventEmitter::new(&cloned);
+        first.running();
+        // Every process starts from its own observed state
+        EventEmitter::new(&channel).running();
+        first.running();
+
+        for events in [&mut events, &mut other] {
+            let kinds: Vec<_> = std::iter::from_fn(|| events.try_recv().ok())
+                .map(|e| e.kind)
+                .collect();
+            assert!(matches!(
+                kinds.as_slice(),
+                [VmEventKind::Running, VmEventKind::Running]
+            ));
+        }
+    }
+}
@@ -1,120 +1,610 @@
-use std::{env, process::Stdio, time::Duration};
+use std::{
+    fs::OpenOptions,
+    path::Path,
+    process::Stdio,
+    sync::{Arc, OnceLock, mpsc},
+    time::{Duration, Instant},
+};
 
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 use http::Method;
-use serde_json::to_string;
+use serde::{Serialize, de::DeserializeOwned};
 use tokio::{
-    io::AsyncReadExt,
     process::{Child, Command},
+    runtime::Handle,
+    sync::{broadcast, oneshot, watch},
+    task::JoinHandle,
 };
 
 use crate::{
     domain::{
-        config::{Action, ActionType, FirecrackerConfiguration},
+        compat::{MIN_SUPPORTED_VERSION, Version},
+        config::{
+            ConfigDiff, FirecrackerConfiguration, LiveFix, ReconcileReport, SnapshotLoadOptions,
+        },
         http::Http,
+        models::{
+            ActionType, BalloonUpdate, FirecrackerVersion, FullVmConfiguration, InstanceActionInfo,
+            InstanceInfo, MachineConfiguration, PartialDrive, PartialNetworkInterface, RateLimiter,
+            SnapshotCreateParams, SnapshotType, Vm, VmState,
+        },
+    },
+    infrastructure::{
+        client::ApiClient,
+        connection::{Transport, TransportKind},
+        process::{
+            events::{EventEmitter, VmEvent, VmEventKind},
+            monitor::{ExitWatcher, OutputCapture},
+        },
     },
-    infrastructure::connection::{socket::Socket, stream::Stream},
 };
 
+pub mod events;
+pub mod monitor;
+
+pub use monitor::{ExitReason, ProcessExit};
+
+/// Work run on the thread returned by [`spawner`]
+type SpawnJob = Box<dyn FnOnce() + Send>;
+
+/// Returns the thread forking attached Firecracker processes, which lives as long as the program
+fn spawner() -> &'static mpsc::Sender<SpawnJob> {
+    static SPAWNER: OnceLock<mpsc::Sender<SpawnJob>> = OnceLock::new();
+    SPAWNER.get_or_init(|| {
+        let (tx, rx) = mpsc::channel::<SpawnJob>();
+        std::thread::Builder::new()
+            .name("firecracker-spawner".into())
+            .spawn(move || rx.into_iter().for_each(|job| job()))
+            .expect("failed to start the spawner thread");
+        tx
+    })
+}
+
+/// How long Firecracker may take to create its API socket
+const API_READY_TIMEOUT: Duration = Duration::from_secs(2);
+
 /// Structure for managing the Firecracker process created using `FirecrackerStartup`
+///
+/// Dropping it kills the process and removes the files created for it,
+/// unless the VM was started with `FirecrackerStartup::detached`.
 pub struct FirecrackerProcess {
-    process: Child,
-    stream: Stream,
+    watcher: ExitWatcher,
+    console: Arc<OutputCapture>,
+    client: ApiClient,
+    poller: JoinHandle<()>,
+    events: EventEmitter,
+    version: Version,
     configuration: FirecrackerConfiguration,
+    cleanup_on_drop: bool,
 }
 
 impl FirecrackerProcess {
     pub(crate) async fn new(configuration: FirecrackerConfiguration) -> Result<Self> {
-        Ok(Self {
-            process: {
-                let child = Command::new(env::var("FIRECRACKER").unwrap_or("firecracker".into()))
-                    .args([
-                        "--api-sock",
-                        configuration
-                            .startup_config
-                            .get_api_socket()
-                            .to_str()
-                            .unwrap(),
-                    ])
-                    .stdout(match configuration.startup_config.current_stdout() {
-                        true => Stdio::piped(),
-                        false => Stdio::null(),
-                    })
-                    .spawn()?;
-                tokio::time::sleep(Duration::from_millis(2)).await;
-                child
-            },
-            stream: Socket::new()?
-                .connect(configuration.startup_config.get_api_socket())
-                .await?,
-            configuration,
-        })
+        let startup = &configuration.startup_config;
+        let events = EventEmitter::new(startup.events());
+        let connected = async {
+            let mut child = Self::spawn(&configuration).await?;
+            let console = OutputCapture::new(startup.current_stdout(), Some(events.clone()));
+            let stderr = OutputCapture::new(false, None);
+            let mut readers = Vec::new();
+            if let Some(stdout) = child.stdout.take() {
+                readers.push(
+                    console.spawn_reader(stdout, Some(configuration.workspace().console_log())),
+                );
+            }
+            if let Some(pipe) = child.stderr.take() {
+                readers.push(stderr.spawn_reader(pipe, None));
+            }
+            let watcher = ExitWatcher::spawn(
+                child,
+                readers,
+                stderr,
+                console.clone(),
+                events.clone(),
+                startup.is_detached(),
+            );
+            events.emit(VmEventKind::Spawned { pid: watcher.pid() });
+            let transport = Self::connect(
+                startup.current_transport(),
+                configuration.api_socket(),
+                &watcher,
+            )
+            .await?;
+            let client = ApiClient::new(transport).options(startup.current_client_options());
+            let version = Self::detect_version(&client).await?;
+            events.emit(VmEventKind::ApiReady);
+            let polling = Self::connect(
+                startup.current_transport(),
+                configuration.api_socket(),
+                &watcher,
+            )
+            .await?;
+            let poller = events.spawn_poller(
+                ApiClient::new(polling).options(startup.current_client_options()),
+                startup.current_state_poll_interval(),
+            );
+            Ok::<_, anyhow::Error>((watcher, console, client, poller, version))
+        }
+        .await;
+        match connected {
+            Ok((watcher, console, client, poller, version)) => Ok(Self {
+                watcher,
+                console,
+                client,
+                poller,
+                events,
+                version,
+                configuration,
+                cleanup_on_drop: true,
+            }),
+            Err(e) => {
+                // The child, if any, is killed once its watcher is dropped; leftovers on disk are not.
+                if !startup.is_detached() {
+                    configuration.cleanup();
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Leaves the workspace in place when the process ends, for a supervisor to reuse
+    pub(crate) fn keep_files(mut self) -> Self {
+        self.cleanup_on_drop = false;
+        self
+    }
+
+    /// Connects to the API socket once Firecracker has created it
+    async fn connect(
+        kind: TransportKind,
+        api_socket: &Path,
+        watcher: &ExitWatcher,
+    ) -> Result<Transport> {
+        let deadline = Instant::now() + API_READY_TIMEOUT;
+        loop {
+            match Transport::connect(kind, api_socket).await {
+                Ok(transport) => return Ok(transport),
+                Err(e) if Instant::now() >= deadline || watcher.try_wait().is_some() => {
+                    return Err(e);
+                }
+                Err(_) => tokio::time::sleep(Duration::from_millis(5)).await,
+            }
+        }
+    }
+
+    /// Reads the release of the running Firecracker via `GET /version` and rejects unsupported ones
+    async fn detect_version(client: &ApiClient) -> Result<Version> {
+        let version: Version = client.version().await?.firecracker_version.parse()?;
+        if version < MIN_SUPPORTED_VERSION {
+            bail!(
+                "Firecracker {version} is not supported, {MIN_SUPPORTED_VERSION} or newer is required"
+            );
+        }
+        Ok(version)
+    }
+
+    /// Returns the release of the specified Firecracker binary, as reported by `firecracker --version`
+    pub async fn binary_version<P: AsRef<Path>>(binary: P) -> Result<Version> {
+        let binary = binary.as_ref();
+        let output = Command::new(binary)
+            .arg("--version")
+            .output()
+            .await
+            .with_context(|| format!("failed to run {} --version", binary.display()))?;
+        String::from_utf8_lossy(&output.stdout).parse()
+    }
+
+    /// Spawns Firecracker, which is killed if this program dies unless it is detached
+    ///
+    /// A detached process outlives the pipes of this program, so its console is written
+    /// straight to the console log of the workspace and its stderr is discarded.
+    ///
+    /// `PR_SET_PDEATHSIG` fires once the thread that forked the child exits, not the process,
+    /// and the threads of tokio exit when idle, so attached processes are forked by [`spawner`].
+    async fn spawn(configuration: &FirecrackerConfiguration) -> Result<Child> {
+        let startup = &configuration.startup_config;
+        let detached = startup.is_detached();
+        let workspace = configuration.workspace();
+        let mut command = Command::new(startup.current_binary());
+        command
+            .arg("--api-sock")
+            .arg(configuration.api_socket())
+            .arg("--log-path")
+            .arg(workspace.log_file())
+            .arg("--metrics-path")
+            .arg(workspace.metrics_file())
+            .kill_on_drop(!detached);
+        if detached {
+            let console = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(workspace.console_log())
+                .context("failed to open the console log")?;
+            command.stdout(console).stderr(Stdio::null());
+        } else {
+            command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        }
+        if detached || !cfg!(target_os = "linux") {
+            return Ok(command.spawn()?);
+        }
+        #[cfg(target_os = "linux")]
+        {
+            let parent = libc::pid_t::try_from(std::process::id())?;
+            // SAFETY: `prctl`, `getppid` and `_exit` are async-signal-safe and only affect the forked child.
+            unsafe {
+                command.pre_exec(move || {
+                    if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    // The parent died before the signal was armed
+                    if libc::getppid() != parent {
+                        libc::_exit(1);
+                    }
+                    Ok(())
+                });
+            }
+        }
+        let handle = Handle::current();
+        let (tx, rx) = oneshot::channel();
+        let job: SpawnJob = Box::new(move || {
+            let _runtime = handle.enter();
+            let _ = tx.send(command.spawn());
+        });
+        if spawner().send(job).is_err() {
+            bail!("the spawner thread is gone");
+        }
+        Ok(rx.await.context("the spawner thread is gone")??)
     }
 
-    pub async fn start_vm(&mut self) -> Result<Http> {
-        self.stream
-            .send_user_request(
-                Http::new_request("/boot-source", Method::PUT)
-                    .add_header("Host", "localhost")
-                    .add_header("Content-Type", "application/json")
-                    .body(to_string(&self.configuration.boot_source)?),
+    /// Configures and boots the VM
+    pub async fn start_vm(&self) -> Result<Http> {
+        let configuration = self.configuration.clone();
+        self.client
+            .send_json(Method::PUT, "/boot-source", &configuration.boot_source)
+            .await?;
+        self.client
+            .send_json(
+                Method::PUT,
+                "/machine-config",
+                &configuration.machine_config,
             )
             .await?;
-        self.stream
-            .send_user_request(
-                Http::new_request("/drives/rootfs", Method::PUT)
-                    .add_header("Host", "localhost")
-                    .add_header("Content-Type", "application/json")
-                    .body(to_string(&self.configuration.drives)?),
+        self.client
+            .send_json(
+                Method::PUT,
+                &format!("/drives/{}", configuration.drives.drive_id),
+                &configuration.drives,
             )
             .await?;
-        for inet in &self.configuration.network_interfaces {
-            self.stream
-                .send_user_request(
-                    Http::new_request(
-                        format!("/network-interfaces/{}", inet.iface_id),
-                        Method::PUT,
-                    )
-                    .add_header("Host", "localhost")
-                    .add_header("Content-Type", "application/json")
-                    .body(to_string(&inet)?),
+        for inet in &configuration.network_interfaces {
+            self.client
+                .send_json(
+                    Method::PUT,
+                    &format!("/network-interfaces/{}", inet.iface_id),
+                    inet,
                 )
                 .await?;
         }
-        tokio::time::sleep(Duration::from_millis(15)).await;
+        self.client
+            .send_json(Method::PUT, "/vsock", &configuration.vsock)
+            .await?;
+        if let Some(balloon) = &configuration.balloon {
+            self.client
+                .send_json(Method::PUT, "/balloon", balloon)
+                .await?;
+        }
+        if let Some(mmds) = &configuration.mmds {
+            self.client.send_json(Method::PUT, "/mmds", mmds).await?;
+        }
+        self.events.emit(VmEventKind::Configured);
 
-        self.stream
-            .send_user_request(
-                Http::new_request("/actions", Method::PUT)
-                    .add_header("Host", "localhost")
-                    .add_header("Content-Type", "application/json")
-                    .body(to_string(&Action {
-                        action_type: ActionType::InstanceStart,
-                    })?),
+        let res = self
+            .client
+            .send_json(
+                Method::PUT,
+                "/actions",
+                &InstanceActionInfo {
+                    action_type: ActionType::InstanceStart,
+                },
             )
             .await?;
+        self.events.running();
+        Ok(res)
+    }
 
-        self.stream.read_req().await
+    /// Pauses the running VM
+    pub async fn pause(&self) -> Result<()> {
+        self.client
+            .send_json(
+                Method::PATCH,
+                "/vm",
+                &Vm {
+                    state: VmState::Paused,
+                },
+            )
+            .await?;
+        self.events.paused();
+        Ok(())
     }
 
-    pub async fn stdout(&mut self) -> Result<String> {
-        let mut out = String::new();
-        if let Some(mut stdout) = self.process.stdout.take() {
-            let mut buf = vec![];
-            stdout.read_buf(&mut buf).await?;
-            out = String::from_utf8(buf)?.to_string();
+    /// Resumes the paused VM
+    pub async fn resume(&self) -> Result<()> {
+        self.client
+            .send_json(
+                Method::PATCH,
+                "/vm",
+                &Vm {
+                    state: VmState::Resumed,
+                },
+            )
+            .await?;
+        self.events.running();
+        Ok(())
+    }
+
+    /// Creates a snapshot of the paused VM
+    ///
+    /// Note: For the best documentation, please refer to [here](https://github.com/firecracker-microvm/firecracker/blob/main/docs/snapshotting/snapshot-support.md).
+    pub async fn create_snapshot<P: AsRef<Path>>(
+        &self,
+        snapshot_type: SnapshotType,
+        snapshot_path: P,
+        mem_file_path: P,
+    ) -> Result<()> {
+        let snapshot_path = snapshot_path.as_ref().to_path_buf();
+        let mem_file_path = mem_file_path.as_ref().to_path_buf();
+        self.client
+            .send_json(
+                Method::PUT,
+                "/snapshot/create",
+                &SnapshotCreateParams {
+                    snapshot_type: Some(snapshot_type),
+                    snapshot_path: snapshot_path.clone(),
+                    mem_file_path: mem_file_path.clone(),
+                },
+            )
+            .await?;
+        self.events.emit(VmEventKind::SnapshotCreated {
+            snapshot_path,
+            mem_file_path,
+        });
+        Ok(())
+    }
+
+    /// Loads a snapshot into the freshly started process instead of configuring and booting the VM
+    ///
+    /// The request is shaped after the running Firecracker release, options it does not support are rejected.
+    pub async fn load_snapshot<P: AsRef<Path>>(
+        &self,
+        snapshot_path: P,
+        mem_file_path: P,
+        options: SnapshotLoadOptions,
+    ) -> Result<()> {
+        let resume_vm = options.resume_vm;
+        let load = options.params(
+            self.version,
+            snapshot_path.as_ref().to_path_buf(),
+            mem_file_path.as_ref().to_path_buf(),
+        )?;
+        self.client
+            .send_json(Method::PUT, "/snapshot/load", &load)
+            .await?;
+        match resume_vm {
+            true => self.events.running(),
+            false => self.events.paused(),
+        }
+        Ok(())
+    }
+
+    /// Returns the release of the running Firecracker, detected once the API socket is ready
+    pub fn detected_version(&self) -> Version {
+        self.version
+    }
+
+    /// Subscribes to the lifecycle events of the VM
+    pub fn subscribe(&self) -> broadcast::Receiver<VmEvent> {
+        self.events.subscribe()
+    }
+
+    /// Returns general information about the instance, `GET /`
+    pub async fn instance_info(&self) -> Result<InstanceInfo> {
+        self.client.instance_info().await
+    }
+
+    /// Returns the version of the running Firecracker, `GET /version`
+    pub async fn version(&self) -> Result<FirecrackerVersion> {
+        self.client.version().await
+    }
+
+    /// Returns the vCPU and memory configuration, `GET /machine-config`
+    pub async fn machine_config(&self) -> Result<MachineConfiguration> {
+        self.client.machine_config().await
+    }
+
+    /// Returns the configuration Firecracker has actually applied, `GET /vm/config`
+    pub async fn vm_config(&self) -> Result<FullVmConfiguration> {
+        self.client.vm_config().await
+    }
+
+    /// Compares the desired configuration with the one applied by Firecracker
+    pub async fn diff(&self, desired: &FirecrackerConfiguration) -> Result<ConfigDiff> {
+        let actual = self.vm_config().await?;
+        let mmds = match desired.mmds {
+            Some(_) => Some(self.client.request(Method::GET, "/mmds", ()).await?),
+            None => None,
+        };
+        Ok(desired.diff(&actual, mmds.as_ref()))
+    }
+
+    /// Applies the drive paths, rate limiters, balloon size and MMDS data of the desired configuration
+    /// to the running VM and reports the drifts that need a restart
+    ///
+    /// The configuration of the process takes over every applied change.
+    pub async fn reconcile(
+        &mut self,
+        desired: &FirecrackerConfiguration,
+    ) -> Result<ReconcileReport> {
+        let diff = self.diff(desired).await?;
+        for fix in diff.fixes() {
+            match &fix {
+                LiveFix::Drive(drive_id) => {
+                    let drive = &desired.drives;
+                    let drifted = |field| diff.drifted(&format!("drives.{drive_id}.{field}"));
+                    self.client
+                        .send_json(
+                            Method::PATCH,
+                            &format!("/drives/{drive_id}"),
+                            &PartialDrive {
+                                drive_id: drive_id.clone(),
+                                path_on_host: drive
+                                    .path_on_host
+                                    .clone()
+                                    .filter(|_| drifted("path_on_host")),
+                                rate_limiter: drifted("rate_limiter")
+                                    .then(|| RateLimiter::update(drive.rate_limiter.as_ref())),
+                            },
+                        )
+                        .await?;
+                }
+                LiveFix::NetInterface(iface_id) => {
+                    let Some(inet) = desired
+                        .network_interfaces
+                        .iter()
+                        .find(|i| &i.iface_id == iface_id)
+                    else {
+                        continue;
+                    };
+                    let drifted =
+                        |field| diff.drifted(&format!("network_interfaces.{iface_id}.{field}"));
+                    self.client
+                        .send_json(
+                            Method::PATCH,
+                            &format!("/network-interfaces/{iface_id}"),
+                            &PartialNetworkInterface {
+                                iface_id: iface_id.clone(),
+                                rx_rate_limiter: drifted("rx_rate_limiter")
+                                    .then(|| RateLimiter::update(inet.rx_rate_limiter.as_ref())),
+                                tx_rate_limiter: drifted("tx_rate_limiter")
+                                    .then(|| RateLimiter::update(inet.tx_rate_limiter.as_ref())),
+                            },
+                        )
+                        .await?;
+                }
+                LiveFix::Balloon => {
+                    let Some(balloon) = &desired.balloon else {
+                        continue;
+                    };
+                    self.client
+                        .send_json(
+                            Method::PATCH,
+                            "/balloon",
+                            &BalloonUpdate {
+                                amount_mib: balloon.amount_mib,
+                            },
+                        )
+                        .await?;
+                }
+                LiveFix::Mmds => {
+                    if let Some(mmds) = &desired.mmds {
+                        self.client.send_json(Method::PUT, "/mmds", mmds).await?;
+                    }
+                }
+            }
+            self.configuration.adopt(desired, &fix);
         }
-        Ok(out)
+        let (applied, requires_restart) = diff.drifts().iter().cloned().partition(|d| d.is_live());
+        Ok(ReconcileReport {
+            applied,
+            requires_restart,
+        })
+    }
+
+    /// Returns a handle to the API that can be cloned and shared between tasks
+    pub fn client(&self) -> ApiClient {
+        self.client.clone()
+    }
+
+    /// Sends a request to any endpoint of the API and deserializes the JSON response,
+    /// failing if Firecracker responds with an error status
+    ///
+    /// See [`ApiClient::request`].
+    pub async fn request<B: Serialize, R: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: B,
+    ) -> Result<R> {
+        self.client.request(method, path, body).await
+    }
+
+    /// Sends a request to any endpoint of the API and returns the response as is,
+    /// whatever its status
+    pub async fn request_raw(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Vec<u8>>,
+    ) -> Result<http::Response<Vec<u8>>> {
+        self.client.request_raw(method, path, body).await
+    }
+
+    /// Waits for the console output written since the previous call and returns it
+    ///
+    /// Returns an empty string if stdout is disabled or the process has exited.
+    pub async fn stdout(&mut self) -> Result<String> {
+        Ok(String::from_utf8(self.console.take_unread().await)?)
+    }
+
+    /// Returns the OS identifier of the Firecracker process
+    pub fn pid(&self) -> Option<u32> {
+        self.watcher.pid()
+    }
+
+    /// Waits for the process to exit
+    pub async fn wait(&self) -> Result<ProcessExit> {
+        self.watcher.wait().await
+    }
+
+    /// Returns the exit of the process if it has already exited
+    pub fn try_wait(&self) -> Option<ProcessExit> {
+        self.watcher.try_wait()
+    }
+
+    /// Subscribes to the exit of the process, published by the background watcher
+    pub fn exit_events(&self) -> watch::Receiver<Option<ProcessExit>> {
+        self.watcher.subscribe()
     }
 
     pub fn config(&self) -> &FirecrackerConfiguration {
         &self.configuration
     }
 
+    pub(crate) fn config_mut(&mut self) -> &mut FirecrackerConfiguration {
+        &mut self.configuration
+    }
+
     /// Correctly starts the process stop and waits for it to complete
     pub async fn stop(mut self) -> Result<()> {
-        self.stream.close().await?;
-        self.process.kill().await?;
+        let exited = self.watcher.try_wait().is_some();
+        let closed = self.client.close().await;
+        self.watcher.kill();
+        self.watcher.wait().await?;
+        // The connection of an exited process may already be gone
+        if !exited {
+            closed?;
+        }
         Ok(())
     }
 }
+
+impl Drop for FirecrackerProcess {
+    fn drop(&mut self) {
+        self.poller.abort();
+        if self.configuration.startup_config.is_detached() {
+            return;
+        }
+        self.watcher.kill();
+        if self.cleanup_on_drop {
+            self.configuration.cleanup();
+        }
+    }
+}
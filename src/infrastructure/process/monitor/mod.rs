@@ -0,0 +1,415 @@
+use std::{
+    collections::VecDeque,
+    future::pending,
+    path::PathBuf,
+    process::ExitStatus,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Result;
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    process::Child,
+    sync::{Notify, oneshot, watch},
+    task::JoinHandle,
+};
+
+use crate::infrastructure::process::events::{EventEmitter, VmEventKind};
+
+/// Number of output lines kept for diagnostics
+const TAIL_LINES: usize = 64;
+/// Upper bound of console output buffered for `FirecrackerProcess::stdout`
+const MAX_UNREAD: usize = 1 << 20;
+/// How long the watcher waits for the output readers to drain after exit
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+/// Messages of Firecracker's own KVM errors, as written to stderr
+const KVM_ERRORS: [&str; 5] = [
+    "Error creating the Kvm object",
+    "/dev/kvm",
+    "KVM capability",
+    "KvmVcpu",
+    "Kvm(",
+];
+
+/// Why the Firecracker process has exited
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// Firecracker exited with code 0, or was stopped by the SDK
+    CleanShutdown,
+    /// Firecracker exited with code 0 after the guest requested a reboot
+    GuestReboot,
+    /// Firecracker was terminated for a syscall outside of its seccomp filter
+    SeccompViolation,
+    /// Firecracker rejected its configuration
+    BadConfiguration,
+    /// Firecracker failed to parse its command line arguments
+    ArgParsing,
+    /// Firecracker failed because of a KVM error
+    KvmError,
+    /// The process was killed by the specified signal
+    ///
+    /// Note: Signals caught by Firecracker itself are reported through exit codes 149-151 and 154-157.
+    Signal(i32),
+    /// Firecracker exited with any other non-zero code
+    Error(i32),
+}
+
+impl ExitReason {
+    /// Classifies an exit, `stopped` is set when the SDK itself killed the process
+    pub(crate) fn classify(
+        status: ExitStatus,
+        stopped: bool,
+        stderr: &[String],
+        console: &[String],
+    ) -> Self {
+        if stopped {
+            return Self::CleanShutdown;
+        }
+        #[cfg(unix)]
+        if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+            return Self::Signal(signal);
+        }
+        let rebooted = console.iter().chain(stderr).any(|line| {
+            line.to_ascii_lowercase()
+                .contains("reboot: restarting system")
+        });
+        let kvm = stderr
+            .iter()
+            .any(|line| KVM_ERRORS.iter().any(|error| line.contains(error)));
+        match status.code().unwrap_or(-1) {
+            0 if rebooted => Self::GuestReboot,
+            0 => Self::CleanShutdown,
+            148 => Self::SeccompViolation,
+            149 => Self::Signal(libc::SIGBUS),
+            150 => Self::Signal(libc::SIGSEGV),
+            151 => Self::Signal(libc::SIGXFSZ),
+            152 => Self::BadConfiguration,
+            153 => Self::ArgParsing,
+            154 => Self::Signal(libc::SIGXCPU),
+            155 => Self::Signal(libc::SIGPIPE),
+            156 => Self::Signal(libc::SIGHUP),
+            157 => Self::Signal(libc::SIGILL),
+            _ if kvm => Self::KvmError,
+            code => Self::Error(code),
+        }
+    }
+
+    /// Returns `true` unless the VM was shut down cleanly
+    pub fn is_failure(&self) -> bool {
+        *self != Self::CleanShutdown
+    }
+}
+
+/// The result of a finished Firecracker process together with its last output
+#[derive(Debug, Clone)]
+pub struct ProcessExit {
+    status: ExitStatus,
+    reason: ExitReason,
+    stderr: Vec<String>,
+    console: Vec<String>,
+}
+
+impl ProcessExit {
+    pub fn status(&self) -> ExitStatus {
+        self.status
+    }
+
+    pub fn reason(&self) -> ExitReason {
+        self.reason
+    }
+
+    /// Returns the last lines written by Firecracker to stderr
+    pub fn stderr_tail(&self) -> &[String] {
+        &self.stderr
+    }
+
+    /// Returns the last lines of the console output, including Firecracker's own log
+    pub fn console_tail(&self) -> &[String] {
+        &self.console
+    }
+}
+
+#[derive(Default)]
+struct CaptureState {
+    lines: VecDeque<String>,
+    partial: Vec<u8>,
+    unread: Vec<u8>,
+    eof: bool,
+}
+
+impl CaptureState {
+    fn push_line(&mut self, line: &[u8]) {
+        if self.lines.len() == TAIL_LINES {
+            self.lines.pop_front();
+        }
+        self.lines
+            .push_back(String::from_utf8_lossy(line).trim_end().to_string());
+    }
+}
+
+/// Output of a child pipe, kept as the last lines and, optionally, as unread bytes
+#[derive(Default)]
+pub(crate) struct OutputCapture {
+    state: Mutex<CaptureState>,
+    notify: Notify,
+    keep_unread: bool,
+    events: Option<EventEmitter>,
+}
+
+impl OutputCapture {
+    pub(crate) fn new(keep_unread: bool, events: Option<EventEmitter>) -> Arc<Self> {
+        Arc::new(Self {
+            keep_unread,
+            events,
+            ..Default::default()
+        })
+    }
+
+    fn push(&self, chunk: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        if self.keep_unread {
+            state.unread.extend_from_slice(chunk);
+            let overflow = state.unread.len().saturating_sub(MAX_UNREAD);
+            state.unread.drain(..overflow);
+        }
+        state.partial.extend_from_slice(chunk);
+        while let Some(pos) = state.partial.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = state.partial.drain(..=pos).collect();
+            state.push_line(&line);
+            if let Some(events) = &self.events {
+                events.console_line(state.lines.back().unwrap());
+            }
+        }
+        drop(state);
+        self.notify.notify_waiters();
+    }
+
+    fn finish(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !state.partial.is_empty() {
+            let line = std::mem::take(&mut state.partial);
+            state.push_line(&line);
+        }
+        state.eof = true;
+        drop(state);
+        self.notify.notify_waiters();
+    }
+
+    /// Returns the last captured lines
+    pub(crate) fn tail(&self) -> Vec<String> {
+        self.state.lock().unwrap().lines.iter().cloned().collect()
+    }
+
+    /// Waits for unread output and takes it, returns an empty buffer once the pipe is closed
+    pub(crate) async fn take_unread(&self) -> Vec<u8> {
+        loop {
+            let notified = self.notify.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if !state.unread.is_empty() || state.eof || !self.keep_unread {
+                    return std::mem::take(&mut state.unread);
+                }
+            }
+            notified.await;
+        }
+    }
+
+    /// Reads the pipe until EOF, mirroring the output into `mirror` if specified
+    pub(crate) fn spawn_reader<R>(
+        self: &Arc<Self>,
+        mut pipe: R,
+        mirror: Option<PathBuf>,
+    ) -> JoinHandle<()>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+    {
+        let capture = self.clone();
+        tokio::spawn(async move {
+            let mut file = match mirror {
+                Some(path) => File::create(path).await.ok(),
+                None => None,
+            };
+            let mut buf = vec![0u8; 8192];
+            while let Ok(n @ 1..) = pipe.read(&mut buf).await {
+                capture.push(&buf[..n]);
+                if let Some(file) = file.as_mut() {
+                    let _ = file.write_all(&buf[..n]).await;
+                }
+            }
+            capture.finish();
+        })
+    }
+}
+
+/// Handle of the background task that owns the child and publishes its exit
+pub(crate) struct ExitWatcher {
+    pid: Option<u32>,
+    kill: Option<oneshot::Sender<()>>,
+    exit: watch::Receiver<Option<ProcessExit>>,
+}
+
+impl ExitWatcher {
+    /// Spawns the watcher. Dropping the handle kills the child, unless it is `detached`.
+    pub(crate) fn spawn(
+        mut child: Child,
+        readers: Vec<JoinHandle<()>>,
+        stderr: Arc<OutputCapture>,
+        console: Arc<OutputCapture>,
+        events: EventEmitter,
+        detached: bool,
+    ) -> Self {
+        let pid = child.id();
+        let (kill_tx, kill_rx) = oneshot::channel::<()>();
+        let (exit_tx, exit_rx) = watch::channel(None);
+        tokio::spawn(async move {
+            let killed = async {
+                if kill_rx.await.is_err() && detached {
+                    pending::<()>().await;
+                }
+            };
+            let (status, stopped) = tokio::select! {
+                status = child.wait() => (status, false),
+                _ = killed => {
+                    let _ = child.start_kill();
+                    (child.wait().await, true)
+                }
+            };
+            let _ = tokio::time::timeout(DRAIN_TIMEOUT, async {
+                for reader in readers {
+                    let _ = reader.await;
+                }
+            })
+            .await;
+            if let Ok(status) = status {
+                let stderr = stderr.tail();
+                let console = console.tail();
+                let exit = ProcessExit {
+                    status,
+                    reason: ExitReason::classify(status, stopped, &stderr, &console),
+                    stderr,
+                    console,
+                };
+                events.emit(VmEventKind::Exited(exit.clone()));
+                let _ = exit_tx.send(Some(exit));
+            }
+        });
+        Self {
+            pid,
+            kill: Some(kill_tx),
+            exit: exit_rx,
+        }
+    }
+
+    pub(crate) fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+
+    /// Asks the watcher to kill the child
+    pub(crate) fn kill(&mut self) {
+        if let Some(kill) = self.kill.take() {
+            let _ = kill.send(());
+        }
+    }
+
+    pub(crate) fn try_wait(&self) -> Option<ProcessExit> {
+        self.exit.borrow().clone()
+    }
+
+    pub(crate) async fn wait(&self) -> Result<ProcessExit> {
+        let mut exit = self.exit.clone();
+        let exit = exit
+            .wait_for(Option::is_some)
+            .await
+            .map_err(|_| anyhow::anyhow!("Firecracker exit watcher has stopped"))?;
+        Ok(exit.clone().unwrap())
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<Option<ProcessExit>> {
+        self.exit.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::ExitStatusExt;
+
+    use super::*;
+
+    fn code(c: i32) -> ExitStatus {
+        ExitStatus::from_raw(c << 8)
+    }
+
+    #[test]
+    fn classify_exit_test() {
+        let console = vec!["reboot: Restarting system".to_string()];
+        let classify = |c| ExitReason::classify(code(c), false, &[], &[]);
+
+        assert_eq!(classify(0), ExitReason::CleanShutdown);
+        assert_eq!(
+            ExitReason::classify(code(0), false, &[], &console),
+            ExitReason::GuestReboot
+        );
+        assert_eq!(classify(1), ExitReason::Error(1));
+        assert_eq!(
+            ExitReason::classify(ExitStatus::from_raw(libc::SIGKILL), false, &[], &[]),
+            ExitReason::Signal(libc::SIGKILL)
+        );
+    }
+
+    #[test]
+    fn classify_exit_code_test() {
+        let classify = |c| ExitReason::classify(code(c), false, &[], &[]);
+
+        assert_eq!(classify(148), ExitReason::SeccompViolation);
+        assert_eq!(classify(149), ExitReason::Signal(libc::SIGBUS));
+        assert_eq!(classify(150), ExitReason::Signal(libc::SIGSEGV));
+        assert_eq!(classify(151), ExitReason::Signal(libc::SIGXFSZ));
+        assert_eq!(classify(152), ExitReason::BadConfiguration);
+        assert_eq!(classify(153), ExitReason::ArgParsing);
+        assert_eq!(classify(154), ExitReason::Signal(libc::SIGXCPU));
+        assert_eq!(classify(155), ExitReason::Signal(libc::SIGPIPE));
+        assert_eq!(classify(156), ExitReason::Signal(libc::SIGHUP));
+        assert_eq!(classify(157), ExitReason::Signal(libc::SIGILL));
+    }
+
+    #[test]
+    fn classify_kvm_test() {
+        let kvm = vec!["Error creating the Kvm object: No such file or directory".to_string()];
+        let clock = vec!["clocksource: Switched to clocksource kvm-clock".to_string()];
+
+        assert_eq!(
+            ExitReason::classify(code(1), false, &kvm, &[]),
+            ExitReason::KvmError
+        );
+        // Guest output never counts as a KVM error
+        assert_eq!(
+            ExitReason::classify(code(1), false, &[], &kvm),
+            ExitReason::Error(1)
+        );
+        assert_eq!(
+            ExitReason::classify(code(1), false, &clock, &clock),
+            ExitReason::Error(1)
+        );
+    }
+
+    #[test]
+    fn classify_stopped_test() {
+        let reason = ExitReason::classify(ExitStatus::from_raw(libc::SIGKILL), true, &[], &[]);
+        assert_eq!(reason, ExitReason::CleanShutdown);
+        assert!(!reason.is_failure());
+    }
+
+    #[tokio::test]
+    async fn output_capture_test() {
+        let capture = OutputCapture::new(true, None);
+        capture.push(b"first\nsec");
+        capture.push(b"ond\nthird");
+        capture.finish();
+
+        assert_eq!(capture.tail(), vec!["first", "second", "third"]);
+        assert_eq!(capture.take_unread().await, b"first\nsecond\nthird");
+        assert!(capture.take_unread().await.is_empty());
+    }
+}
@@ -0,0 +1,188 @@
+//! Parsing of the [ListObjectsV2](https://docs.aws.amazon.com/AmazonS3/latest/API/API_ListObjectsV2.html) response
+
+use anyhow::{Result, anyhow, bail};
+
+/// An object of a bucket listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct S3Object {
+    pub key: String,
+    pub size: u64,
+    pub etag: Option<String>,
+}
+
+/// A page of a bucket listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ListBucketResult {
+    pub contents: Vec<S3Object>,
+    /// Token requesting the next page, if the listing is truncated
+    pub next_continuation_token: Option<String>,
+}
+
+impl ListBucketResult {
+    pub fn parse(xml: &str) -> Result<Self> {
+        let root = elements(xml, "ListBucketResult")
+            .next()
+            .ok_or_else(|| anyhow!("the response is not a ListBucketResult"))?;
+        let contents = elements(root, "Contents")
+            .map(|object| {
+                let key = elements(object, "Key")
+                    .next()
+                    .ok_or_else(|| anyhow!("an object of the listing has no Key"))?;
+                let size = match elements(object, "Size").next() {
+                    Some(size) => size.trim().parse()?,
+                    None => 0,
+                };
+                Ok(S3Object {
+                    key: unescape(key)?,
+                    size,
+                    etag: elements(object, "ETag").next().map(unescape).transpose()?,
+                })
+            })
+            .collect::<Result<_>>()?;
+        let truncated = elements(root, "IsTruncated").next().map(str::trim) == Some("true");
+        let next_continuation_token = match elements(root, "NextContinuationToken").next() {
+            Some(token) if truncated => Some(unescape(token)?),
+            None if truncated => bail!("the listing is truncated without a NextContinuationToken"),
+            _ => None,
+        };
+        Ok(Self {
+            contents,
+            next_continuation_token,
+        })
+    }
+}
+
+/// Returns the content of the `tag` elements directly found in `xml`
+///
+/// The elements of a listing never nest in an element of the same name, so the first closing tag ends it.
+fn elements<'a>(xml: &'a str, tag: &'a str) -> impl Iterator<Item = &'a str> {
+    let open = format!("<{tag}");
+    let close = format!("</{tag}>");
+    let mut rest = xml;
+    std::iter::from_fn(move || {
+        loop {
+            let start = rest.find(&open)?;
+            let after = &rest[start + open.len()..];
+            let end = after.find('>')?;
+            let attributes = &after[..end];
+            if !attributes.is_empty() && !attributes.starts_with([' ', '\t', '\r', '\n', '/']) {
+                // Another tag starting with the same name
+                rest = after;
+                continue;
+            }
+            if attributes.ends_with('/') {
+                // <Tag/> is empty
+                rest = &after[end + 1..];
+                return Some("");
+            }
+            let content = &after[end + 1..];
+            let len = content.find(&close)?;
+            rest = &content[len + close.len()..];
+            return Some(&content[..len]);
+        }
+    })
+}
+
+/// Replaces the predefined entities and character references of XML text
+fn unescape(text: &str) -> Result<String> {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find(';')
+            .ok_or_else(|| anyhow!("unterminated entity in {text:?}"))?;
+        let entity = &rest[start + 1..start + end];
+        let c = match entity {
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = match entity.strip_prefix("#x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => entity.strip_prefix('#').and_then(|n| n.parse().ok()),
+                };
+                code.and_then(char::from_u32)
+                    .ok_or_else(|| anyhow!("unknown entity &{entity}; in {text:?}"))?
+            }
+        };
+        unescaped.push(c);
+        rest = &rest[start + end + 1..];
+    }
+    unescaped.push_str(rest);
+    Ok(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_test() -> Result<()> {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+    <Name>spec.ccfc.min</Name>
+    <Prefix>firecracker-ci/v1.10/x86_64/vmlinux-5.10</Prefix>
+    <KeyCount>2</KeyCount>
+    <MaxKeys>1000</MaxKeys>
+    <IsTruncated>true</IsTruncated>
+    <NextContinuationToken>1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM=</NextContinuationToken>
+    <Contents>
+        <Key>firecracker-ci/v1.10/x86_64/vmlinux-5.10.239</Key>
+        <LastModified>2025-07-01T10:00:00.000Z</LastModified>
+        <ETag>&quot;0b7a8a7d6c2a1b3e&quot;</ETag>
+        <Size>40271136</Size>
+        <StorageClass>STANDARD</StorageClass>
+    </Contents>
+    <Contents>
+        <Key>firecracker-ci/v1.10/x86_64/vmlinux-5.10.239.config &amp; notes</Key>
+        <Size>0</Size>
+        <KeySuffix/>
+    </Contents>
+</ListBucketResult>"#;
+        assert_eq!(
+            ListBucketResult::parse(xml)?,
+            ListBucketResult {
+                contents: vec![
+                    S3Object {
+                        key: "firecracker-ci/v1.10/x86_64/vmlinux-5.10.239".into(),
+                        size: 40271136,
+                        etag: Some("\"0b7a8a7d6c2a1b3e\"".into()),
+                    },
+                    S3Object {
+                        key: "firecracker-ci/v1.10/x86_64/vmlinux-5.10.239.config & notes".into(),
+                        size: 0,
+                        etag: None,
+                    },
+                ],
+                next_continuation_token: Some(
+                    "1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM=".into()
+                ),
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn last_page_test() -> Result<()> {
+        let xml = "<ListBucketResult><IsTruncated>false</IsTruncated></ListBucketResult>";
+        let page = ListBucketResult::parse(xml)?;
+        assert!(page.contents.is_empty());
+        assert_eq!(page.next_continuation_token, None);
+
+        assert!(ListBucketResult::parse("<Error><Code>AccessDenied</Code></Error>").is_err());
+        let truncated = "<ListBucketResult><IsTruncated>true</IsTruncated></ListBucketResult>";
+        assert!(ListBucketResult::parse(truncated).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn unescape_test() -> Result<()> {
+        assert_eq!(unescape("a&lt;b&gt;&apos;&#38;&#x41;")?, "a<b>'&A");
+        assert!(unescape("a&b").is_err());
+        assert!(unescape("&nbsp;").is_err());
+        Ok(())
+    }
+}
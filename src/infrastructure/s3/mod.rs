@@ -1,82 +1,856 @@
-use anyhow::Result;
-use regex::Regex;
-use reqwest::Client;
+mod listing;
+mod sigv4;
 
+use std::env;
+
+use anyhow::{Result, anyhow, bail};
+use reqwest::{Client, Url};
+use serde::Serialize;
+
+use crate::{
+    domain::compat::Version,
+    infrastructure::{
+        cache::{ArtifactCache, CacheEntry},
+        download::{ProgressCallback, fetch_locked, fetch_unpacked, send},
+        lockfile::LockedArtifact,
+        s3::listing::{ListBucketResult, S3Object},
+        source::{ArtifactContext, ArtifactFuture, ArtifactKind, ArtifactSource},
+    },
+};
+
+pub use sigv4::Credentials;
+pub(crate) use sigv4::Signer;
+
+/// Extensions of the compressed copies of a rootfs, listed after it so that they are preferred
+const COMPRESSED_SUFFIXES: [&str; 3] = [".gz", ".xz", ".zst"];
+
+/// Artifacts downloaded from the bucket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum S3Item {
     Rootfs,
     Kernel,
 }
 
+/// Architecture of the downloaded kernel and rootfs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Arch {
+    X86_64,
+    Aarch64,
+}
+
+impl Arch {
+    /// Returns the architecture of the host, x86_64 if it has no Firecracker build
+    pub fn host() -> Self {
+        match std::env::consts::ARCH {
+            "aarch64" => Self::Aarch64,
+            _ => Self::X86_64,
+        }
+    }
+
+    /// Returns the name of the architecture in the keys of the bucket
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::X86_64 => "x86_64",
+            Self::Aarch64 => "aarch64",
+        }
+    }
+}
+
+impl Default for Arch {
+    fn default() -> Self {
+        Self::host()
+    }
+}
+
+/// A version of an artifact available in the bucket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtifactVersion {
+    pub key: String,
+    /// Version of a kernel, a rootfs has none
+    pub version: Option<Version>,
+    pub size: u64,
+    pub etag: Option<String>,
+}
+
+/// A bucket of an S3-compatible store
+#[derive(Debug, Clone, Serialize)]
+struct Bucket {
+    endpoint: String,
+    name: String,
+}
+
+impl Bucket {
+    /// Returns the URL of the bucket, as a subdomain of the endpoint unless `path_style` is set
+    fn url(&self, path_style: bool) -> Result<String> {
+        if path_style {
+            return Ok(format!("{}/{}", self.endpoint, self.name));
+        }
+        let mut url = Url::parse(&self.endpoint)?;
+        let host = url
+            .host_str()
+            .ok_or_else(|| anyhow!("the endpoint {} has no host", self.endpoint))?;
+        url.set_host(Some(&format!("{}.{host}", self.name)))?;
+        Ok(url.as_str().trim_end_matches('/').into())
+    }
+}
+
+/// Downloads the kernel and rootfs built by the Firecracker CI
+///
+/// Both are looked up under `firecracker-ci/<ci_version>/<arch>/` of the bucket, so a mirror must keep that layout.
+/// Requests are anonymous unless credentials are set, to read a private bucket.
+#[derive(Clone, Serialize)]
 pub struct S3Downloader {
     xml_path: String,
     download_path: String,
-    kernel_prefix: (String, Regex),
-    rootfs_prefix: (String, Regex),
+    bucket: Option<Bucket>,
+    path_style: bool,
+    region: String,
+    #[serde(skip)]
+    credentials: Option<Credentials>,
+    ci_version: String,
+    arch: Arch,
+    kernel_series: String,
+    distro: String,
+    #[serde(skip)]
+    progress: Option<ProgressCallback>,
 }
 
 impl Default for S3Downloader {
     fn default() -> Self {
         Self {
-            kernel_prefix: (
-                "firecracker-ci/v1.10/x86_64/vmlinux-5.10".into(),
-                Regex::new(r"<Key>(firecracker-ci/v1\.10/x86_64/vmlinux-5\.10\.\d{3})</Key>")
-                    .unwrap(),
-            ),
-            rootfs_prefix: (
-                "firecracker-ci/v1.10/x86_64/ubuntu-22.04.ext4".into(),
-                Regex::new(r"<Key>(firecracker-ci/v1\.10/x86_64/ubuntu-22\.04\.ext4)</Key>")
-                    .unwrap(),
-            ),
             xml_path: "http://spec.ccfc.min.s3.amazonaws.com".into(),
             download_path: "https://s3.amazonaws.com/spec.ccfc.min".into(),
+            bucket: None,
+            path_style: false,
+            region: env::var("AWS_REGION")
+                .or_else(|_| env::var("AWS_DEFAULT_REGION"))
+                .unwrap_or("us-east-1".into()),
+            credentials: None,
+            ci_version: "v1.10".into(),
+            arch: Arch::host(),
+            kernel_series: "5.10".into(),
+            distro: "ubuntu-22.04".into(),
+            progress: None,
         }
     }
 }
 
 impl S3Downloader {
-    async fn download_item(&self, item: &str) -> Result<Vec<u8>> {
-        Ok(Client::new()
-            .get(format!("{}/{}", self.download_path, item))
-            .send()
-            .await?
-            .bytes()
-            .await?
-            .into())
+    /// Set the URL listing the bucket and the URL objects are downloaded from
+    ///
+    /// Exemple:
+    /// ```no_compile
+    /// let s3 = S3Downloader::default()
+    ///     .urls("http://mirror.local/spec.ccfc.min", "http://mirror.local/spec.ccfc.min");
+    /// ```
+    pub fn urls(mut self, list_url: impl Into<String>, download_url: impl Into<String>) -> Self {
+        self.xml_path = list_url.into().trim_end_matches('/').into();
+        self.download_path = download_url.into().trim_end_matches('/').into();
+        self
+    }
+
+    /// Set an S3-compatible store and the bucket holding the artifacts, in place of the URLs of [`S3Downloader::urls`]
+    ///
+    /// Exemple:
+    /// ```no_compile
+    /// let s3 = S3Downloader::default()
+    ///     .endpoint("https://minio.internal:9000", "hardened-images")
+    ///     .path_style(true)
+    ///     .credentials(Credentials::load().await?);
+    /// ```
+    pub fn endpoint(mut self, endpoint: impl Into<String>, bucket: impl Into<String>) -> Self {
+        self.bucket = Some(Bucket {
+            endpoint: endpoint.into().trim_end_matches('/').into(),
+            name: bucket.into(),
+        });
+        self
+    }
+
+    /// Flag to address the bucket of the endpoint as `<endpoint>/<bucket>/<key>`
+    /// instead of `<bucket>.<endpoint>/<key>`, as most S3-compatible stores expect
+    pub fn path_style(mut self, flag: bool) -> Self {
+        self.path_style = flag;
+        self
+    }
+
+    /// Set the region requests are signed for, `AWS_REGION` or `us-east-1` by default
+    pub fn region(mut self, region: impl Into<String>) -> Self {
+        self.region = region.into();
+        self
+    }
+
+    /// Set the credentials signing the requests, which are anonymous without them
+    pub fn credentials(mut self, credentials: Option<Credentials>) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// Set the version of the Firecracker CI artifacts, `v1.10` by default
+    pub fn ci_version(mut self, version: impl Into<String>) -> Self {
+        self.ci_version = version.into();
+        self
+    }
+
+    /// Set the architecture of the artifacts, the one of the host by default
+    pub fn arch(mut self, arch: Arch) -> Self {
+        self.arch = arch;
+        self
+    }
+
+    /// Set the kernel series, whose latest patch version is downloaded, `5.10` by default
+    ///
+    /// A complete version such as `5.10.239` pins the kernel, see [`S3Downloader::list_versions`].
+    pub fn kernel_series(mut self, series: impl Into<String>) -> Self {
+        self.kernel_series = series.into();
+        self
+    }
+
+    /// Set the distro of the rootfs, `ubuntu-22.04` by default
+    pub fn distro(mut self, distro: impl Into<String>) -> Self {
+        self.distro = distro.into();
+        self
+    }
+
+    /// Set the callback receiving the progress of downloads
+    pub fn progress(mut self, callback: Option<ProgressCallback>) -> Self {
+        self.progress = callback;
+        self
+    }
+
+    fn signer(&self) -> Option<Signer> {
+        let credentials = self.credentials.clone()?;
+        Some(Signer::new(credentials, &self.region))
     }
 
-    async fn xml(&self, prefix: &str) -> Result<String> {
-        Ok(Client::new()
-            .get(format!("{}/?prefix={}&list-type=2", self.xml_path, prefix))
-            .send()
+    /// Returns the URL listing the bucket
+    fn list_url(&self) -> Result<String> {
+        match &self.bucket {
+            Some(bucket) => bucket.url(self.path_style),
+            None => Ok(self.xml_path.clone()),
+        }
+    }
+
+    /// Lists every object whose key starts with the prefix, following the continuation tokens
+    async fn list(&self, prefix: &str) -> Result<Vec<S3Object>> {
+        let client = Client::new();
+        let url = format!("{}/", self.list_url()?);
+        let signer = self.signer();
+        let mut objects = vec![];
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+            if let Some(token) = &token {
+                query.push(("continuation-token", token.as_str()));
+            }
+            let xml = send(&client, client.get(&url).query(&query), signer.as_ref())
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+            let page = ListBucketResult::parse(&xml)?;
+            objects.extend(page.contents);
+            match page.next_continuation_token {
+                Some(next) => token = Some(next),
+                None => return Ok(objects),
+            }
+        }
+    }
+
+    /// Returns the URL the object is downloaded from
+    fn url(&self, key: &str) -> Result<String> {
+        let base = match &self.bucket {
+            Some(bucket) => bucket.url(self.path_style)?,
+            None => self.download_path.clone(),
+        };
+        Ok(format!("{base}/{key}"))
+    }
+
+    /// Returns the prefix of the item's keys
+    ///
+    /// A kernel series is a prefix of versions, so a complete version such as `5.10.239` pins it.
+    fn prefix(&self, s3_item: &S3Item) -> String {
+        let dir = format!("firecracker-ci/{}/{}", self.ci_version, self.arch.as_str());
+        match s3_item {
+            S3Item::Rootfs => format!("{dir}/{}.ext4", self.distro),
+            S3Item::Kernel => format!("{dir}/vmlinux-{}", self.kernel_series),
+        }
+    }
+
+    /// Returns the available versions of the item, from the oldest to the latest
+    ///
+    /// Exemple:
+    /// ```no_compile
+    /// let kernels = S3Downloader::default().kernel_series("6.1").list_versions(S3Item::Kernel).await?;
+    /// let latest = kernels.last().and_then(|kernel| kernel.version);
+    /// ```
+    pub async fn list_versions(&self, s3_item: S3Item) -> Result<Vec<ArtifactVersion>> {
+        let prefix = self.prefix(&s3_item);
+        let mut versions: Vec<_> = self
+            .list(&prefix)
             .await?
-            .text()
-            .await?)
-    }
-
-    pub async fn download(&self, s3_item: S3Item) -> Result<Vec<u8>> {
-        let mut versions: Vec<_> = match s3_item {
-            S3Item::Rootfs => {
-                let xml = self.xml(&self.rootfs_prefix.0).await?;
-                self.rootfs_prefix
-                    .1
-                    .captures_iter(&xml)
-                    .map(|m| m[1].to_string())
-                    .collect()
+            .into_iter()
+            .filter_map(|object| {
+                let version = match s3_item {
+                    S3Item::Rootfs
+                        if object.key == prefix
+                            || COMPRESSED_SUFFIXES
+                                .iter()
+                                .any(|suffix| object.key == format!("{prefix}{suffix}")) =>
+                    {
+                        None
+                    }
+                    S3Item::Rootfs => return None,
+                    S3Item::Kernel => {
+                        let (_, version) = object.key.rsplit_once("/vmlinux-")?;
+                        // Skips `vmlinux-5.10.239.config` or `vmlinux-5.10.2` for the series `5.10.23`
+                        let rest = &object.key[prefix.len()..];
+                        if !(rest.is_empty() || rest.starts_with('.'))
+                            || !version.chars().all(|c| c.is_ascii_digit() || c == '.')
+                        {
+                            return None;
+                        }
+                        Some(version.parse().ok()?)
+                    }
+                };
+                Some(ArtifactVersion {
+                    key: object.key,
+                    version,
+                    size: object.size,
+                    etag: object.etag,
+                })
+            })
+            .collect();
+        versions.sort_by_key(|v| v.version);
+        Ok(versions)
+    }
+
+    /// Returns the latest version of the item from the cache, downloading it unless it is cached
+    ///
+    /// A rootfs compressed with gzip, zstd or xz, `<distro>.ext4.zst` for instance, is decompressed.
+    /// In offline mode, the version resolved by the last online fetch is returned.
+    pub async fn fetch(&self, s3_item: S3Item, cache: &ArtifactCache) -> Result<CacheEntry> {
+        let prefix = self.prefix(&s3_item);
+        if cache.is_offline() {
+            return cache.resolve(&prefix).await?.ok_or_else(|| {
+                anyhow!(
+                    "{prefix} is not in the artifact cache at {} and offline mode is enabled",
+                    cache.root().display()
+                )
+            });
+        }
+        let key = match self.list_versions(s3_item).await?.pop() {
+            Some(latest) => latest.key,
+            None => bail!("no version of {prefix} found in {}", self.list_url()?),
+        };
+        let signer = self.signer();
+        let url = self.url(&key)?;
+        let progress = self.progress.as_ref();
+        let entry = fetch_unpacked(cache, &url, &key, None, signer.as_ref(), progress).await?;
+        cache.set_ref(&prefix, &entry).await?;
+        Ok(entry)
+    }
+
+    /// Returns the version of the item recorded in the lockfile, or the latest one without a record
+    ///
+    /// Fails with [`ArtifactMismatch`](crate::infrastructure::lockfile::ArtifactMismatch) if the downloaded file differs from the recorded one.
+    pub async fn fetch_locked(
+        &self,
+        s3_item: S3Item,
+        cache: &ArtifactCache,
+        locked: Option<&LockedArtifact>,
+    ) -> Result<CacheEntry> {
+        match locked {
+            Some(locked) => {
+                let url = self.url(&locked.key)?;
+                let signer = self.signer();
+                let progress = self.progress.as_ref();
+                fetch_locked(cache, &url, locked, None, signer.as_ref(), progress).await
             }
-            S3Item::Kernel => {
-                let xml = self.xml(&self.kernel_prefix.0).await?;
-                self.kernel_prefix
-                    .1
-                    .captures_iter(&xml)
-                    .map(|m| m[1].to_string())
-                    .collect()
+            None => self.fetch(s3_item, cache).await,
+        }
+    }
+}
+
+impl ArtifactSource for S3Downloader {
+    /// Resolves the kernel and rootfs, the bucket has no initrd
+    fn resolve<'a>(
+        &'a self,
+        kind: ArtifactKind,
+        context: ArtifactContext<'a>,
+    ) -> ArtifactFuture<'a> {
+        Box::pin(async move {
+            let s3_item = match kind {
+                ArtifactKind::Kernel => S3Item::Kernel,
+                ArtifactKind::Rootfs => S3Item::Rootfs,
+                ArtifactKind::Initrd => return Ok(None),
+            };
+            let entry = self
+                .fetch_locked(s3_item, context.cache, context.locked)
+                .await?;
+            Ok(Some((&entry).into()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, net::SocketAddr};
+
+    use async_compression::tokio::write::GzipEncoder;
+    use http::{Method, StatusCode};
+    use tempfile::tempdir;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+    use crate::{
+        domain::http::{Http, HttpDecoder},
+        infrastructure::lockfile::ArtifactMismatch,
+    };
+
+    /// Serves the objects like the bucket, listing the keys starting with `prefix`
+    async fn serve(objects: HashMap<String, Vec<u8>>) -> Result<SocketAddr> {
+        serve_private(objects, None).await
+    }
+
+    /// Serves the objects like a private bucket addressed by path, `/<bucket>/<key>`,
+    /// refusing the requests whose signature differs from the one of `signer`
+    async fn serve_private(
+        objects: HashMap<String, Vec<u8>>,
+        private: Option<(&'static str, Signer)>,
+    ) -> Result<SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await?;
+                let mut decoder = HttpDecoder::new();
+                let req = loop {
+                    if let Some(req) = decoder.decode()? {
+                        break req;
+                    }
+                    socket.read_buf(decoder.buffer()).await?;
+                };
+                let Http::Request { method, path, .. } = &req else {
+                    unreachable!()
+                };
+                let method = method.clone();
+                let mut path = path.to_string_lossy().into_owned();
+                if let Some((bucket, signer)) = &private {
+                    let signed = verify(&req, signer);
+                    match path.strip_prefix(&format!("/{bucket}")) {
+                        Some(key) if signed => path = key.into(),
+                        _ => {
+                            let res = Http::new_response(StatusCode::FORBIDDEN);
+                            socket.write_all(&res.build()).await?;
+                            continue;
+                        }
+                    }
+                }
+                let res = match path.strip_prefix("/?") {
+                    Some(query) => {
+                        let query: HashMap<_, _> = query
+                            .split('&')
+                            .filter_map(|param| param.split_once('='))
+                            .map(|(name, value)| (name, percent_decode(value)))
+                            .collect();
+                        let mut keys: Vec<_> = objects
+                            .keys()
+                            .filter(|key| key.starts_with(&query["prefix"]))
+                            .collect();
+                        keys.sort();
+                        // Pages of two keys, the token being the index of the next one
+                        let start = query
+                            .get("continuation-token")
+                            .map_or(0, |token| token["after/".len()..].parse().unwrap());
+                        let page: String = keys
+                            .iter()
+                            .skip(start)
+                            .take(2)
+                            .map(|key| {
+                                format!(
+                                    "<Contents><Key>{key}</Key><Size>{}</Size></Contents>",
+                                    objects[*key].len()
+                                )
+                            })
+                            .collect();
+                        let next = match start + 2 < keys.len() {
+                            true => format!(
+                                "<IsTruncated>true</IsTruncated>\
+                                 <NextContinuationToken>after/{}</NextContinuationToken>",
+                                start + 2
+                            ),
+                            false => "<IsTruncated>false</IsTruncated>".into(),
+                        };
+                        Http::new_response(StatusCode::OK)
+                            .body(format!("<ListBucketResult>{next}{page}</ListBucketResult>"))
+                    }
+                    None => match objects.get(&path[1..]) {
+                        Some(object) if method == Method::HEAD => {
+                            Http::new_response(StatusCode::OK)
+                                .add_header("ETag", &format!("\"{}\"", object.len()))
+                                .add_header("Content-Length", &object.len().to_string())
+                        }
+                        Some(object) => Http::new_response(StatusCode::OK).body(object.clone()),
+                        None => Http::new_response(StatusCode::NOT_FOUND),
+                    },
+                };
+                socket.write_all(&res.build()).await?;
             }
+            #[allow(unreachable_code)]
+            Ok::<_, anyhow::Error>(())
+        });
+        Ok(addr)
+    }
+
+    /// Returns whether the request is signed by `signer`
+    fn verify(req: &Http, signer: &Signer) -> bool {
+        let Http::Request { method, path, .. } = req else {
+            return false;
+        };
+        let (Some(authorization), Some(host), Some(datetime)) = (
+            req.header("authorization"),
+            req.header("host"),
+            req.header("x-amz-date"),
+        ) else {
+            return false;
         };
-        if versions.is_empty() {
-            anyhow::bail!("Could not find any version");
+        let Some((_, signed_headers)) = authorization.split_once("SignedHeaders=") else {
+            return false;
+        };
+        let mut headers = reqwest::header::HeaderMap::new();
+        for name in signed_headers.split(',').next().unwrap().split(';') {
+            if name == "host" {
+                continue;
+            }
+            let value = req.header(name).unwrap_or_default();
+            headers.insert(
+                reqwest::header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                value.parse().unwrap(),
+            );
+        }
+        let url = Url::parse(&format!("http://{host}{}", path.display())).unwrap();
+        signer.authorization(method, &url, &headers, datetime) == authorization
+    }
+
+    fn percent_decode(value: &str) -> String {
+        let mut decoded = vec![];
+        let mut bytes = value.bytes();
+        while let Some(b) = bytes.next() {
+            match b {
+                b'%' => {
+                    let hex = [bytes.next().unwrap(), bytes.next().unwrap()];
+                    decoded
+                        .push(u8::from_str_radix(std::str::from_utf8(&hex).unwrap(), 16).unwrap());
+                }
+                b => decoded.push(b),
+            }
         }
-        versions.sort();
-        let latest = versions.last().unwrap();
-        self.download_item(latest).await
+        String::from_utf8(decoded).unwrap()
+    }
+
+    #[tokio::test]
+    async fn list_versions_test() -> Result<()> {
+        let objects: HashMap<String, Vec<u8>> = [
+            "vmlinux-6.1.141",
+            "vmlinux-6.1.141.config",
+            "vmlinux-6.1.9",
+            "vmlinux-6.1.128",
+            "vmlinux-6.1.10",
+            "vmlinux-6.10.2",
+            "ubuntu-24.04.ext4",
+            "ubuntu-24.04.squashfs",
+        ]
+        .into_iter()
+        .map(|name| (format!("firecracker-ci/v1.12/x86_64/{name}"), name.into()))
+        .collect();
+        let addr = serve(objects).await?;
+        let s3 = S3Downloader::default()
+            .urls(format!("http://{addr}"), format!("http://{addr}"))
+            .ci_version("v1.12")
+            .arch(Arch::X86_64)
+            .kernel_series("6.1")
+            .distro("ubuntu-24.04");
+
+        let kernels = s3.list_versions(S3Item::Kernel).await?;
+        let versions: Vec<_> = kernels.iter().map(|k| k.version.unwrap()).collect();
+        assert_eq!(
+            versions,
+            [
+                Version::new(6, 1, 9),
+                Version::new(6, 1, 10),
+                Version::new(6, 1, 128),
+                Version::new(6, 1, 141),
+            ]
+        );
+        assert_eq!(kernels[0].key, "firecracker-ci/v1.12/x86_64/vmlinux-6.1.9");
+        assert_eq!(kernels[0].size, "vmlinux-6.1.9".len() as u64);
+
+        let pinned = s3.clone().kernel_series("6.1.10");
+        let kernels = pinned.list_versions(S3Item::Kernel).await?;
+        assert_eq!(kernels.len(), 1);
+        assert_eq!(kernels[0].version, Some(Version::new(6, 1, 10)));
+
+        let rootfs = s3.list_versions(S3Item::Rootfs).await?;
+        assert_eq!(rootfs.len(), 1);
+        assert_eq!(
+            rootfs[0].key,
+            "firecracker-ci/v1.12/x86_64/ubuntu-24.04.ext4"
+        );
+        assert_eq!(rootfs[0].version, None);
+        Ok(())
+    }
+
+    fn kernels(versions: &[(&str, &str)]) -> HashMap<String, Vec<u8>> {
+        versions
+            .iter()
+            .map(|(version, body)| {
+                let key = format!("firecracker-ci/v1.10/x86_64/vmlinux-{version}");
+                (key, body.as_bytes().to_vec())
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn locked_resolve_test() -> Result<()> {
+        let dir = tempdir()?;
+        let cache = ArtifactCache::new(dir.path().join("cache"));
+        let s3 = |addr: SocketAddr| {
+            S3Downloader::default()
+                .urls(format!("http://{addr}"), format!("http://{addr}"))
+                .arch(Arch::X86_64)
+                .kernel_series("6.1")
+        };
+        let addr = serve(kernels(&[("6.1.128", "old"), ("6.1.141", "kernel")])).await?;
+
+        // The latest version is recorded
+        let context = ArtifactContext {
+            cache: &cache,
+            locked: None,
+        };
+        let kernel = s3(addr)
+            .resolve(ArtifactKind::Kernel, context)
+            .await?
+            .unwrap();
+        let locked = kernel.locked.unwrap();
+        assert_eq!(locked.key, "firecracker-ci/v1.10/x86_64/vmlinux-6.1.141");
+        assert_eq!(tokio::fs::read(&kernel.path).await?, b"kernel");
+        assert_eq!(s3(addr).resolve(ArtifactKind::Initrd, context).await?, None);
+
+        // A newer release is ignored, the recorded one is found by digest without a download
+        let addr = serve(kernels(&[("6.1.141", "replaced"), ("6.1.155", "new")])).await?;
+        let context = ArtifactContext {
+            cache: &cache,
+            locked: Some(&locked),
+        };
+        let pinned = s3(addr)
+            .resolve(ArtifactKind::Kernel, context)
+            .await?
+            .unwrap();
+        assert_eq!(pinned.path, kernel.path);
+        let offline = cache.clone().offline(true);
+        let context = ArtifactContext {
+            cache: &offline,
+            locked: Some(&locked),
+        };
+        let pinned = s3(addr)
+            .resolve(ArtifactKind::Kernel, context)
+            .await?
+            .unwrap();
+        assert_eq!(pinned.path, kernel.path);
+
+        // A download that differs from the record is refused
+        let other = ArtifactCache::new(dir.path().join("other"));
+        let error = s3(addr)
+            .fetch_locked(S3Item::Kernel, &other, Some(&locked))
+            .await
+            .unwrap_err();
+        let mismatch = error.downcast_ref::<ArtifactMismatch>().unwrap();
+        assert_eq!(mismatch.expected, locked);
+        assert_eq!(mismatch.size, "replaced".len() as u64);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mirror_fetch_test() -> Result<()> {
+        let objects: HashMap<String, Vec<u8>> = [
+            ("firecracker-ci/v1.12/aarch64/vmlinux-6.1.128", "6.1.128"),
+            ("firecracker-ci/v1.12/aarch64/vmlinux-6.1.141", "6.1.141"),
+            ("firecracker-ci/v1.12/aarch64/vmlinux-5.10.239", "5.10.239"),
+            ("firecracker-ci/v1.12/x86_64/vmlinux-6.1.155", "x86_64"),
+            ("firecracker-ci/v1.10/aarch64/vmlinux-6.1.155", "v1.10"),
+            ("firecracker-ci/v1.12/aarch64/debian-12.ext4", "debian"),
+            ("firecracker-ci/v1.12/aarch64/ubuntu-24.04.ext4", "ubuntu"),
+        ]
+        .into_iter()
+        .map(|(key, body)| (key.into(), body.into()))
+        .collect();
+        let addr = serve(objects).await?;
+        let dir = tempdir()?;
+        let cache = ArtifactCache::new(dir.path());
+        let s3 = S3Downloader::default()
+            .urls(format!("http://{addr}/"), format!("http://{addr}"))
+            .ci_version("v1.12")
+            .arch(Arch::Aarch64)
+            .kernel_series("6.1")
+            .distro("ubuntu-24.04");
+
+        let kernel = s3.fetch(S3Item::Kernel, &cache).await?;
+        assert_eq!(kernel.key, "firecracker-ci/v1.12/aarch64/vmlinux-6.1.141");
+        assert_eq!(kernel.etag.as_deref(), Some("\"7\""));
+        assert_eq!(tokio::fs::read(kernel.path()).await?, b"6.1.141");
+
+        let rootfs = s3.fetch(S3Item::Rootfs, &cache).await?;
+        assert_eq!(tokio::fs::read(rootfs.path()).await?, b"ubuntu");
+
+        // The versions resolved from the mirror are used offline
+        let cache = cache.offline(true);
+        assert_eq!(s3.fetch(S3Item::Kernel, &cache).await?, kernel);
+        let error = s3
+            .clone()
+            .arch(Arch::X86_64)
+            .fetch(S3Item::Kernel, &cache)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("x86_64/vmlinux-6.1"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn private_fetch_test() -> Result<()> {
+        let credentials = Credentials::new("AKIDFIRECRACKER", "secret").session_token("token");
+        let signer = Signer::new(credentials.clone(), "eu-west-3");
+        let objects = kernels(&[
+            ("6.1.128", "old"),
+            ("6.1.141", "kernel"),
+            ("6.1.155", "new"),
+        ]);
+        let addr = serve_private(objects, Some(("images", signer))).await?;
+        let dir = tempdir()?;
+        let cache = ArtifactCache::new(dir.path());
+        let s3 = S3Downloader::default()
+            .endpoint(format!("http://{addr}/"), "images")
+            .path_style(true)
+            .region("eu-west-3")
+            .arch(Arch::X86_64)
+            .kernel_series("6.1");
+
+        let error = s3.fetch(S3Item::Kernel, &cache).await.unwrap_err();
+        assert!(error.to_string().contains("403"));
+        let wrong = Credentials::new("AKIDFIRECRACKER", "wrong");
+        let error = s3
+            .clone()
+            .credentials(Some(wrong))
+            .fetch(S3Item::Kernel, &cache)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("403"));
+
+        // Listed across pages, then downloaded with a HEAD and a GET
+        let s3 = s3.credentials(Some(credentials));
+        let kernel = s3.fetch(S3Item::Kernel, &cache).await?;
+        assert_eq!(kernel.key, "firecracker-ci/v1.10/x86_64/vmlinux-6.1.155");
+        assert_eq!(tokio::fs::read(kernel.path()).await?, b"new");
+        Ok(())
+    }
+
+    #[test]
+    fn endpoint_test() -> Result<()> {
+        let s3 = S3Downloader::default().endpoint("https://minio.internal:9000/", "images");
+        assert_eq!(
+            s3.url("vmlinux")?,
+            "https://images.minio.internal:9000/vmlinux"
+        );
+        assert_eq!(s3.list_url()?, "https://images.minio.internal:9000");
+        let s3 = s3.path_style(true);
+        assert_eq!(
+            s3.url("vmlinux")?,
+            "https://minio.internal:9000/images/vmlinux"
+        );
+        let default = S3Downloader::default();
+        assert_eq!(
+            default.url("vmlinux")?,
+            "https://s3.amazonaws.com/spec.ccfc.min/vmlinux"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn compressed_fetch_test() -> Result<()> {
+        let mut compressed = vec![];
+        let mut encoder = GzipEncoder::new(&mut compressed);
+        encoder.write_all(b"compressed rootfs").await?;
+        encoder.shutdown().await?;
+        let objects: HashMap<String, Vec<u8>> = [
+            (
+                "firecracker-ci/v1.10/x86_64/ubuntu-22.04.ext4",
+                b"rootfs".to_vec(),
+            ),
+            (
+                "firecracker-ci/v1.10/x86_64/ubuntu-22.04.ext4.gz",
+                compressed,
+            ),
+        ]
+        .into_iter()
+        .map(|(key, body)| (key.into(), body))
+        .collect();
+        let addr = serve(objects).await?;
+        let dir = tempdir()?;
+        let cache = ArtifactCache::new(dir.path());
+        let s3 = S3Downloader::default()
+            .urls(format!("http://{addr}"), format!("http://{addr}"))
+            .arch(Arch::X86_64);
+
+        assert_eq!(s3.list_versions(S3Item::Rootfs).await?.len(), 2);
+        let rootfs = s3.fetch(S3Item::Rootfs, &cache).await?;
+        assert_eq!(
+            rootfs.key,
+            "firecracker-ci/v1.10/x86_64/ubuntu-22.04.ext4.gz"
+        );
+        assert_eq!(tokio::fs::read(rootfs.path()).await?, b"compressed rootfs");
+        assert_eq!(
+            rootfs.unpacked.as_ref().unwrap().compression.as_deref(),
+            Some("gzip")
+        );
+        // Only the decompressed rootfs is stored
+        let mut objects = tokio::fs::read_dir(dir.path().join("objects")).await?;
+        assert!(objects.next_entry().await?.is_some());
+        assert!(objects.next_entry().await?.is_none());
+
+        // Pinned to the decompressed rootfs, found by digest offline
+        let offline = cache.clone().offline(true);
+        let locked = (&rootfs).into();
+        let pinned = s3
+            .fetch_locked(S3Item::Rootfs, &offline, Some(&locked))
+            .await?;
+        assert_eq!(pinned.path(), rootfs.path());
+        let other = ArtifactCache::new(dir.path().join("other"));
+        let fetched = s3
+            .fetch_locked(S3Item::Rootfs, &other, Some(&locked))
+            .await?;
+        assert_eq!(fetched.sha256, rootfs.sha256);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn offline_fetch_test() -> Result<()> {
+        let dir = tempdir()?;
+        let cache = ArtifactCache::new(dir.path()).offline(true);
+        let s3 = S3Downloader::default().arch(Arch::X86_64);
+
+        let error = s3.fetch(S3Item::Kernel, &cache).await.unwrap_err();
+        assert!(error.to_string().contains("offline mode is enabled"));
+
+        let key = "firecracker-ci/v1.10/x86_64/vmlinux-5.10.239";
+        let mut writer = cache.writer().await?;
+        writer.write(b"kernel").await?;
+        let entry = writer.commit(key, Some("\"etag\"")).await?;
+        cache
+            .set_ref("firecracker-ci/v1.10/x86_64/vmlinux-5.10", &entry)
+            .await?;
+        assert_eq!(s3.fetch(S3Item::Kernel, &cache).await?, entry);
+        Ok(())
     }
 }
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+
+use crate::infrastructure::{
+    download::{ProgressCallback, fetch_locked, fetch_unpacked},
+    source::{ArtifactContext, ArtifactFuture, ArtifactKind, ArtifactSource},
+};
+
+/// Artifacts downloaded from plain HTTP URLs into the artifact cache
+///
+/// Downloads compressed with gzip, zstd or xz are decompressed.
+///
+/// Exemple:
+/// ```no_compile
+/// let source = HttpSource::new()
+///     .kernel("https://images.internal/vmlinux-6.1")
+///     .rootfs("https://images.internal/debian-12.tar.zst")
+///     .member(ArtifactKind::Rootfs, "debian-12.ext4");
+/// ```
+#[derive(Clone, Default)]
+pub struct HttpSource {
+    urls: HashMap<ArtifactKind, String>,
+    members: HashMap<ArtifactKind, String>,
+    progress: Option<ProgressCallback>,
+}
+
+impl HttpSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the URL of the kernel
+    pub fn kernel(self, url: impl Into<String>) -> Self {
+        self.url(ArtifactKind::Kernel, url)
+    }
+
+    /// Set the URL of the initrd
+    pub fn initrd(self, url: impl Into<String>) -> Self {
+        self.url(ArtifactKind::Initrd, url)
+    }
+
+    /// Set the URL of the rootfs
+    pub fn rootfs(self, url: impl Into<String>) -> Self {
+        self.url(ArtifactKind::Rootfs, url)
+    }
+
+    /// Set the URL of an artifact
+    pub fn url(mut self, kind: ArtifactKind, url: impl Into<String>) -> Self {
+        self.urls.insert(kind, url.into());
+        self
+    }
+
+    /// Set the member of the tar archive at the URL of the artifact to extract it from
+    pub fn member(mut self, kind: ArtifactKind, name: impl Into<String>) -> Self {
+        self.members.insert(kind, name.into());
+        self
+    }
+
+    /// Set the callback receiving the progress of downloads
+    pub fn progress(mut self, callback: Option<ProgressCallback>) -> Self {
+        self.progress = callback;
+        self
+    }
+}
+
+impl ArtifactSource for HttpSource {
+    /// Downloads and unpacks the artifact unless it is cached, in offline mode the last download of the URL is used
+    fn resolve<'a>(
+        &'a self,
+        kind: ArtifactKind,
+        context: ArtifactContext<'a>,
+    ) -> ArtifactFuture<'a> {
+        Box::pin(async move {
+            let Some(url) = self.urls.get(&kind) else {
+                return Ok(None);
+            };
+            let cache = context.cache;
+            let member = self.members.get(&kind).map(String::as_str);
+            // Members of the same archive are resolved independently
+            let name = match member {
+                Some(member) => format!("{url}#{member}"),
+                None => url.clone(),
+            };
+            let progress = self.progress.as_ref();
+            let entry = match context.locked {
+                Some(locked) => fetch_locked(cache, url, locked, member, None, progress).await?,
+                None if cache.is_offline() => cache.resolve(&name).await?.ok_or_else(|| {
+                    anyhow!(
+                        "{name} is not in the artifact cache at {} and offline mode is enabled",
+                        cache.root().display()
+                    )
+                })?,
+                None => {
+                    let entry = fetch_unpacked(cache, url, url, member, None, progress).await?;
+                    cache.set_ref(&name, &entry).await?;
+                    entry
+                }
+            };
+            Ok(Some((&entry).into()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use anyhow::Result;
+    use http::{Method, StatusCode};
+    use tempfile::tempdir;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+    use crate::{
+        domain::http::{Http, HttpDecoder},
+        infrastructure::{cache::ArtifactCache, lockfile::ArtifactMismatch, source::Artifact},
+    };
+
+    /// Serves `body` at every path
+    async fn serve(body: &'static [u8]) -> Result<SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await?;
+                let mut decoder = HttpDecoder::new();
+                let method = loop {
+                    if let Some(Http::Request { method, .. }) = decoder.decode()? {
+                        break method;
+                    }
+                    socket.read_buf(decoder.buffer()).await?;
+                };
+                let res = match method {
+                    Method::HEAD => Http::new_response(StatusCode::OK)
+                        .add_header("Content-Length", &body.len().to_string()),
+                    _ => Http::new_response(StatusCode::OK).body(body),
+                };
+                socket.write_all(&res.build()).await?;
+            }
+            #[allow(unreachable_code)]
+            Ok::<_, anyhow::Error>(())
+        });
+        Ok(addr)
+    }
+
+    #[tokio::test]
+    async fn resolve_test() -> Result<()> {
+        let addr = serve(b"kernel image").await?;
+        let dir = tempdir()?;
+        let cache = ArtifactCache::new(dir.path());
+        let context = ArtifactContext {
+            cache: &cache,
+            locked: None,
+        };
+        let url = format!("http://{addr}/vmlinux");
+        let source = HttpSource::new().kernel(&url);
+
+        let kernel: Artifact = source
+            .resolve(ArtifactKind::Kernel, context)
+            .await?
+            .unwrap();
+        assert_eq!(tokio::fs::read(&kernel.path).await?, b"kernel image");
+        let locked = kernel.locked.clone().unwrap();
+        assert_eq!(locked.key, url);
+        assert_eq!(source.resolve(ArtifactKind::Rootfs, context).await?, None);
+
+        let offline = cache.clone().offline(true);
+        let context = ArtifactContext {
+            cache: &offline,
+            locked: None,
+        };
+        let cached = source.resolve(ArtifactKind::Kernel, context).await?;
+        assert_eq!(cached, Some(kernel));
+
+        // The URL now serves another file
+        let other = ArtifactCache::new(dir.path().join("other"));
+        let context = ArtifactContext {
+            cache: &other,
+            locked: Some(&locked),
+        };
+        let source = HttpSource::new().kernel(format!("http://{}/vmlinux", serve(b"other").await?));
+        let error = source
+            .resolve(ArtifactKind::Kernel, context)
+            .await
+            .unwrap_err();
+        assert!(error.downcast_ref::<ArtifactMismatch>().is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn archive_test() -> Result<()> {
+        let dir = tempdir()?;
+        tokio::fs::create_dir(dir.path().join("images")).await?;
+        tokio::fs::write(dir.path().join("images/vmlinux"), b"kernel").await?;
+        tokio::fs::write(dir.path().join("images/rootfs.ext4"), b"rootfs").await?;
+        let status = tokio::process::Command::new("tar")
+            .args([
+                "-czf",
+                "images.tgz",
+                "-C",
+                "images",
+                "vmlinux",
+                "rootfs.ext4",
+            ])
+            .current_dir(dir.path())
+            .status()
+            .await?;
+        assert!(status.success());
+        let archive = tokio::fs::read(dir.path().join("images.tgz")).await?;
+        let addr = serve(archive.leak()).await?;
+        let cache = ArtifactCache::new(dir.path().join("cache"));
+        let context = ArtifactContext {
+            cache: &cache,
+            locked: None,
+        };
+        let url = format!("http://{addr}/images.tgz");
+        let source = HttpSource::new()
+            .kernel(&url)
+            .rootfs(&url)
+            .member(ArtifactKind::Kernel, "vmlinux")
+            .member(ArtifactKind::Rootfs, "rootfs.ext4");
+
+        let rootfs = source
+            .resolve(ArtifactKind::Rootfs, context)
+            .await?
+            .unwrap();
+        assert_eq!(tokio::fs::read(&rootfs.path).await?, b"rootfs");
+        // Both members of the same download are cached side by side
+        let kernel = source
+            .resolve(ArtifactKind::Kernel, context)
+            .await?
+            .unwrap();
+        assert_eq!(tokio::fs::read(&kernel.path).await?, b"kernel");
+
+        let offline = cache.clone().offline(true);
+        let context = ArtifactContext {
+            cache: &offline,
+            locked: None,
+        };
+        let cached = source.resolve(ArtifactKind::Rootfs, context).await?;
+        assert_eq!(cached, Some(rootfs));
+        Ok(())
+    }
+}
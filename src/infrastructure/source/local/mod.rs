@@ -0,0 +1,117 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+use tokio::fs;
+
+use crate::infrastructure::source::{
+    Artifact, ArtifactContext, ArtifactFuture, ArtifactKind, ArtifactSource,
+};
+
+/// Artifacts stored in a local directory, used in place
+///
+/// The initrd is skipped if it does not exist, a missing kernel or rootfs is reported by the validation of the configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalSource {
+    dir: PathBuf,
+    kernel: PathBuf,
+    initrd: PathBuf,
+    rootfs: PathBuf,
+}
+
+impl LocalSource {
+    /// Reads `vmlinux.bin`, `initrd.img` and `vmrootfs.ext4` from the directory
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            kernel: "vmlinux.bin".into(),
+            initrd: "initrd.img".into(),
+            rootfs: "vmrootfs.ext4".into(),
+        }
+    }
+
+    /// Set the kernel, relative to the directory unless absolute
+    pub fn kernel<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.kernel = path.as_ref().to_path_buf();
+        self
+    }
+
+    /// Set the initrd, relative to the directory unless absolute
+    pub fn initrd<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.initrd = path.as_ref().to_path_buf();
+        self
+    }
+
+    /// Set the rootfs, relative to the directory unless absolute
+    pub fn rootfs<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.rootfs = path.as_ref().to_path_buf();
+        self
+    }
+}
+
+impl Default for LocalSource {
+    /// Reads the kernel and initrd from `FIRECRACKER_KERNEL` and the rootfs from `FIRECRACKER_ROOTFS`,
+    /// `~/.firecracker/kernel/target/` and `~/.firecracker/rootfs/target/` by default
+    fn default() -> Self {
+        let dir = |var: &str, default: &str| {
+            env::var_os(var).map(PathBuf::from).unwrap_or(
+                env::home_dir()
+                    .unwrap_or(env::current_exe().unwrap().parent().unwrap().to_path_buf())
+                    .join(default),
+            )
+        };
+        let rootfs = dir("FIRECRACKER_ROOTFS", ".firecracker/rootfs/target/");
+        Self::new(dir("FIRECRACKER_KERNEL", ".firecracker/kernel/target/"))
+            .rootfs(rootfs.join("vmrootfs.ext4"))
+    }
+}
+
+impl ArtifactSource for LocalSource {
+    fn resolve<'a>(&'a self, kind: ArtifactKind, _: ArtifactContext<'a>) -> ArtifactFuture<'a> {
+        Box::pin(async move {
+            Ok(match kind {
+                ArtifactKind::Kernel => Some(Artifact::local(self.dir.join(&self.kernel))),
+                ArtifactKind::Rootfs => Some(Artifact::local(self.dir.join(&self.rootfs))),
+                ArtifactKind::Initrd => {
+                    let path = self.dir.join(&self.initrd);
+                    fs::try_exists(&path).await?.then(|| Artifact::local(path))
+                }
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::infrastructure::cache::ArtifactCache;
+
+    #[tokio::test]
+    async fn resolve_test() -> Result<()> {
+        let dir = tempdir()?;
+        let cache = ArtifactCache::new(dir.path().join("cache"));
+        let context = ArtifactContext {
+            cache: &cache,
+            locked: None,
+        };
+        let source = LocalSource::new(dir.path()).rootfs("/images/ubuntu.ext4");
+
+        let kernel = source.resolve(ArtifactKind::Kernel, context).await?;
+        assert_eq!(
+            kernel,
+            Some(Artifact::local(dir.path().join("vmlinux.bin")))
+        );
+        let rootfs = source.resolve(ArtifactKind::Rootfs, context).await?;
+        assert_eq!(rootfs, Some(Artifact::local("/images/ubuntu.ext4")));
+        assert_eq!(source.resolve(ArtifactKind::Initrd, context).await?, None);
+
+        fs::write(dir.path().join("initrd.img"), b"initrd").await?;
+        let initrd = source.resolve(ArtifactKind::Initrd, context).await?;
+        assert_eq!(initrd, Some(Artifact::local(dir.path().join("initrd.img"))));
+        Ok(())
+    }
+}
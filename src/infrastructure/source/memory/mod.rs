@@ -0,0 +1,87 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::infrastructure::source::{
+    ArtifactContext, ArtifactFuture, ArtifactKind, ArtifactSource,
+};
+
+/// Artifacts held in memory, written to the artifact cache when resolved
+///
+/// Meant for tests: they are recorded in the lockfile like downloads, under the key `memory/<kind>`.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySource {
+    artifacts: HashMap<ArtifactKind, Arc<[u8]>>,
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the content of an artifact
+    pub fn artifact(mut self, kind: ArtifactKind, content: impl Into<Arc<[u8]>>) -> Self {
+        self.artifacts.insert(kind, content.into());
+        self
+    }
+}
+
+impl ArtifactSource for MemorySource {
+    fn resolve<'a>(
+        &'a self,
+        kind: ArtifactKind,
+        context: ArtifactContext<'a>,
+    ) -> ArtifactFuture<'a> {
+        Box::pin(async move {
+            let Some(content) = self.artifacts.get(&kind) else {
+                return Ok(None);
+            };
+            let mut writer = context.cache.writer().await?;
+            writer.write(content).await?;
+            let entry = writer.commit(&format!("memory/{kind}"), None).await?;
+            if let Some(locked) = context.locked {
+                locked.verify(&entry)?;
+            }
+            Ok(Some((&entry).into()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::infrastructure::{cache::ArtifactCache, lockfile::ArtifactMismatch};
+
+    #[tokio::test]
+    async fn resolve_test() -> Result<()> {
+        let dir = tempdir()?;
+        let cache = ArtifactCache::new(dir.path());
+        let source = MemorySource::new().artifact(ArtifactKind::Rootfs, b"rootfs".as_slice());
+        let context = ArtifactContext {
+            cache: &cache,
+            locked: None,
+        };
+
+        let rootfs = source
+            .resolve(ArtifactKind::Rootfs, context)
+            .await?
+            .unwrap();
+        assert_eq!(tokio::fs::read(&rootfs.path).await?, b"rootfs");
+        assert_eq!(source.resolve(ArtifactKind::Kernel, context).await?, None);
+
+        let locked = rootfs.locked.unwrap();
+        assert_eq!(locked.key, "memory/rootfs");
+        let changed = MemorySource::new().artifact(ArtifactKind::Rootfs, b"changed".as_slice());
+        let context = ArtifactContext {
+            cache: &cache,
+            locked: Some(&locked),
+        };
+        let error = changed
+            .resolve(ArtifactKind::Rootfs, context)
+            .await
+            .unwrap_err();
+        assert!(error.downcast_ref::<ArtifactMismatch>().is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+//! Where the kernel, initrd and rootfs of a VM come from
+//!
+//! Implementations are provided for the public S3 bucket of the Firecracker CI
+//! ([`S3Downloader`](crate::infrastructure::s3::S3Downloader)), plain HTTP URLs, local directories
+//! and in-memory images for tests.
+
+use std::{
+    fmt::{self, Display},
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::infrastructure::{
+    cache::{ArtifactCache, CacheEntry},
+    lockfile::LockedArtifact,
+};
+
+mod http;
+mod local;
+mod memory;
+
+pub use http::HttpSource;
+pub use local::LocalSource;
+pub use memory::MemorySource;
+
+/// Files a VM boots from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum ArtifactKind {
+    Kernel,
+    Initrd,
+    Rootfs,
+}
+
+impl Display for ArtifactKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Kernel => "kernel",
+            Self::Initrd => "initrd",
+            Self::Rootfs => "rootfs",
+        })
+    }
+}
+
+/// A file resolved by an [`ArtifactSource`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Artifact {
+    pub path: PathBuf,
+    /// Record of a downloaded artifact, for the lockfile
+    pub locked: Option<LockedArtifact>,
+}
+
+impl Artifact {
+    /// A local file, which is not recorded in the lockfile
+    pub fn local<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            locked: None,
+        }
+    }
+}
+
+impl From<&CacheEntry> for Artifact {
+    fn from(entry: &CacheEntry) -> Self {
+        Self {
+            path: entry.path().to_path_buf(),
+            locked: Some(entry.into()),
+        }
+    }
+}
+
+/// What an [`ArtifactSource`] resolves with
+#[derive(Debug, Clone, Copy)]
+pub struct ArtifactContext<'a> {
+    /// Cache holding downloaded artifacts, which may be in offline mode
+    pub cache: &'a ArtifactCache,
+    /// Artifact recorded in the lockfile, to be returned instead of the latest one
+    pub locked: Option<&'a LockedArtifact>,
+}
+
+pub type ArtifactFuture<'a> = Pin<Box<dyn Future<Output = Result<Option<Artifact>>> + Send + 'a>>;
+
+/// Provides the files a VM boots from
+///
+/// A source that downloads must return the artifact recorded in the context, failing with
+/// [`ArtifactMismatch`](crate::infrastructure::lockfile::ArtifactMismatch) if it has another digest.
+///
+/// Exemple:
+/// ```no_compile
+/// struct Images(PathBuf);
+///
+/// impl ArtifactSource for Images {
+///     fn resolve<'a>(&'a self, kind: ArtifactKind, _: ArtifactContext<'a>) -> ArtifactFuture<'a> {
+///         Box::pin(async move {
+///             Ok(match kind {
+///                 ArtifactKind::Kernel => Some(Artifact::local(self.0.join("vmlinux"))),
+///                 ArtifactKind::Rootfs => Some(Artifact::local(self.0.join("rootfs.ext4"))),
+///                 ArtifactKind::Initrd => None,
+///             })
+///         })
+///     }
+/// }
+/// ```
+pub trait ArtifactSource: Send + Sync {
+    /// Returns the file of the artifact, or `None` if the source has no such artifact
+    fn resolve<'a>(
+        &'a self,
+        kind: ArtifactKind,
+        context: ArtifactContext<'a>,
+    ) -> ArtifactFuture<'a>;
+}
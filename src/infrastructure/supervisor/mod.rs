@@ -0,0 +1,268 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use serde::Serialize;
+use tokio::{
+    sync::{MappedMutexGuard, Mutex, MutexGuard, broadcast, oneshot},
+    task::JoinHandle,
+};
+
+use crate::{
+    domain::config::FirecrackerConfiguration,
+    infrastructure::process::{FirecrackerProcess, ProcessExit},
+};
+
+/// How long a process must run before its exit starts a new series of restart attempts
+const STABLE_UPTIME: Duration = Duration::from_secs(60);
+
+/// Exponential delay between attempts, doubled after every one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Backoff {
+    /// Returns the delay before the specified attempt, starting from 1
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.initial
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max)
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(30),
+        }
+    }
+}
+
+/// When a supervised VM is relaunched after its process exits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartPolicy {
+    /// Never relaunch
+    Never,
+    /// Relaunch unless Firecracker shut down cleanly, at most `max_retries` times in a row
+    ///
+    /// Note: A guest reboot makes Firecracker exit and counts as a failure.
+    /// The retries and the backoff are reset once a process runs for a minute.
+    OnFailure { max_retries: u32, backoff: Backoff },
+    /// Always relaunch
+    Always { backoff: Backoff },
+}
+
+impl RestartPolicy {
+    fn backoff(&self, exit: &ProcessExit, attempt: u32) -> Option<Backoff> {
+        match *self {
+            Self::Never => None,
+            Self::OnFailure {
+                max_retries,
+                backoff,
+            } => (exit.reason().is_failure() && attempt <= max_retries).then_some(backoff),
+            Self::Always { backoff } => Some(backoff),
+        }
+    }
+}
+
+/// Events emitted by a [`Supervisor`]
+#[derive(Debug, Clone)]
+pub enum SupervisorEvent {
+    /// The process exited and will be relaunched after `delay`
+    Restarting {
+        attempt: u32,
+        delay: Duration,
+        exit: ProcessExit,
+    },
+    /// The VM was relaunched and booted
+    Restarted { attempt: u32 },
+    /// Relaunching the VM failed, the next attempt follows the restart policy
+    RestartFailed { attempt: u32, error: String },
+    /// The process exited and the restart policy does not allow another attempt
+    GaveUp { exit: ProcessExit },
+}
+
+/// Keeps a VM running by relaunching it from the same `FirecrackerConfiguration`
+///
+/// Relaunched processes reuse the socket paths and disks of the first one.
+/// The workspace cleanup policy is applied once the supervisor stops.
+/// Dropping the supervisor stops the VM as well, unless it was started detached.
+pub struct Supervisor {
+    process: Arc<Mutex<Option<FirecrackerProcess>>>,
+    events: broadcast::Sender<SupervisorEvent>,
+    stop: Option<oneshot::Sender<()>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl Supervisor {
+    pub(crate) async fn start(
+        configuration: FirecrackerConfiguration,
+        policy: RestartPolicy,
+    ) -> Result<Self> {
+        let process = match launch(configuration.clone()).await {
+            Ok(process) => Arc::new(Mutex::new(Some(process))),
+            Err(e) => {
+                if !configuration.startup_config.is_detached() {
+                    configuration.cleanup();
+                }
+                return Err(e);
+            }
+        };
+        let (events, _) = broadcast::channel(16);
+        let (stop, stop_rx) = oneshot::channel();
+        let task = tokio::spawn(supervise(
+            configuration,
+            policy,
+            process.clone(),
+            events.clone(),
+            stop_rx,
+        ));
+        Ok(Self {
+            process,
+            events,
+            stop: Some(stop),
+            task: Some(task),
+        })
+    }
+
+    /// Subscribes to the restart events
+    pub fn subscribe(&self) -> broadcast::Receiver<SupervisorEvent> {
+        self.events.subscribe()
+    }
+
+    /// Locks and returns the currently running process
+    ///
+    /// Note: The supervisor cannot relaunch the VM while the guard is held.
+    pub async fn process(&self) -> MappedMutexGuard<'_, FirecrackerProcess> {
+        MutexGuard::map(self.process.lock().await, |process| {
+            process
+                .as_mut()
+                .expect("supervised process is only taken on stop")
+        })
+    }
+
+    /// Stops supervising, stops the current process and applies the workspace cleanup policy
+    pub async fn stop(mut self) -> Result<()> {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(task) = self.task.take() {
+            task.await?;
+        }
+        Ok(())
+    }
+}
+
+/// Spawns the process and boots the VM, leaving the workspace to the supervisor
+async fn launch(configuration: FirecrackerConfiguration) -> Result<FirecrackerProcess> {
+    configuration.remove_sockets();
+    let process = FirecrackerProcess::new(configuration).await?.keep_files();
+    process.start_vm().await?;
+    Ok(process)
+}
+
+async fn supervise(
+    configuration: FirecrackerConfiguration,
+    policy: RestartPolicy,
+    process: Arc<Mutex<Option<FirecrackerProcess>>>,
+    events: broadcast::Sender<SupervisorEvent>,
+    mut stop: oneshot::Receiver<()>,
+) {
+    let mut attempt = 0;
+    let mut launched = Instant::now();
+    let mut requested = false;
+    'supervise: loop {
+        let mut exit = match process.lock().await.as_ref() {
+            Some(process) => process.exit_events(),
+            None => break,
+        };
+        let exit = tokio::select! {
+            res = &mut stop => {
+                requested = res.is_ok();
+                break;
+            }
+            exit = exit.wait_for(Option::is_some) => match exit {
+                Ok(exit) => exit.clone().unwrap(),
+                Err(_) => break,
+            },
+        };
+        attempt = previous_attempts(attempt, launched.elapsed());
+
+        loop {
+            attempt += 1;
+            let Some(backoff) = policy.backoff(&exit, attempt) else {
+                let _ = events.send(SupervisorEvent::GaveUp { exit });
+                break 'supervise;
+            };
+            let delay = backoff.delay(attempt);
+            let _ = events.send(SupervisorEvent::Restarting {
+                attempt,
+                delay,
+                exit: exit.clone(),
+            });
+            tokio::select! {
+                res = &mut stop => {
+                    requested = res.is_ok();
+                    break 'supervise;
+                }
+                _ = tokio::time::sleep(delay) => {}
+            }
+            match launch(configuration.clone()).await {
+                Ok(relaunched) => {
+                    *process.lock().await = Some(relaunched);
+                    launched = Instant::now();
+                    let _ = events.send(SupervisorEvent::Restarted { attempt });
+                    break;
+                }
+                Err(e) => {
+                    let _ = events.send(SupervisorEvent::RestartFailed {
+                        attempt,
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+    }
+
+    if configuration.startup_config.is_detached() && !requested {
+        return;
+    }
+    if let Some(process) = process.lock().await.take() {
+        let _ = process.stop().await;
+    }
+    configuration.cleanup();
+}
+
+/// Returns the attempts counted before the next restart, none once the process ran long enough to be stable
+fn previous_attempts(attempt: u32, uptime: Duration) -> u32 {
+    if uptime >= STABLE_UPTIME { 0 } else { attempt }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_delay_test() {
+        let backoff = Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(1),
+        };
+        assert_eq!(backoff.delay(1), Duration::from_millis(100));
+        assert_eq!(backoff.delay(3), Duration::from_millis(400));
+        assert_eq!(backoff.delay(5), Duration::from_secs(1));
+        assert_eq!(backoff.delay(64), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn stable_uptime_test() {
+        assert_eq!(previous_attempts(3, Duration::from_secs(1)), 3);
+        assert_eq!(previous_attempts(3, STABLE_UPTIME), 0);
+        assert_eq!(previous_attempts(0, Duration::ZERO), 0);
+    }
+}
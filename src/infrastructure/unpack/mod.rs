@@ -0,0 +1,423 @@
+use anyhow::{Context, Result, anyhow, bail};
+use async_compression::tokio::bufread::{GzipDecoder, XzDecoder, ZstdDecoder};
+use tokio::io::{self, AsyncRead, AsyncReadExt, BufReader};
+
+use crate::infrastructure::cache::CacheWriter;
+
+/// Size of a tar header and of the blocks member contents are padded to
+const TAR_BLOCK: usize = 512;
+/// Number of bytes needed to detect a compression
+pub(crate) const MAGIC_LEN: usize = 6;
+
+/// Compression of a downloaded artifact, detected from its first bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    pub(crate) fn detect(magic: &[u8]) -> Option<Self> {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Some(Self::Gzip)
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Self::Zstd)
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Self::Xz)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+            Self::Xz => "xz",
+        }
+    }
+}
+
+/// Decodes the stream and writes it, or the member of the tar archive it holds, to the cache
+///
+/// The stream is decoded to its end even once the member is written, so that a corrupt
+/// or truncated download fails instead of being committed. Blocks of zeros are left as holes.
+pub(crate) async fn unpack<R: AsyncRead + Unpin + Send>(
+    reader: R,
+    compression: Option<Compression>,
+    member: Option<&str>,
+    writer: &mut CacheWriter,
+) -> Result<()> {
+    let reader = BufReader::new(reader);
+    let mut decoded: Box<dyn AsyncRead + Unpin + Send + '_> = match compression {
+        Some(Compression::Gzip) => {
+            let mut decoder = GzipDecoder::new(reader);
+            decoder.multiple_members(true);
+            Box::new(decoder)
+        }
+        Some(Compression::Zstd) => {
+            let mut decoder = ZstdDecoder::new(reader);
+            decoder.multiple_members(true);
+            Box::new(decoder)
+        }
+        Some(Compression::Xz) => {
+            let mut decoder = XzDecoder::new(reader);
+            decoder.multiple_members(true);
+            Box::new(decoder)
+        }
+        None => Box::new(reader),
+    };
+    write(&mut decoded, member, writer).await?;
+    io::copy(&mut decoded, &mut io::sink()).await?;
+    Ok(())
+}
+
+/// Writes the stream, or the member of the tar archive it holds, to the cache
+async fn write<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    member: Option<&str>,
+    writer: &mut CacheWriter,
+) -> Result<()> {
+    let size = match member {
+        Some(member) => Some(find_member(reader, member).await?),
+        None => None,
+    };
+    let mut reader = reader.take(size.unwrap_or(u64::MAX));
+    let mut buf = vec![0; 1 << 20];
+    loop {
+        // Fills the buffer, so that blocks of zeros are aligned
+        let mut filled = 0;
+        while filled < buf.len() {
+            match reader.read(&mut buf[filled..]).await? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        if filled == 0 {
+            break;
+        }
+        writer.write_sparse(&buf[..filled]).await?;
+    }
+    match size {
+        Some(size) if writer.size() < size => {
+            bail!("the archive ends in the middle of its member")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Reads the archive up to the contents of the member and returns their size
+///
+/// Handles the names of ustar, GNU and pax archives, and the sizes of pax archives.
+async fn find_member<R: AsyncRead + Unpin>(reader: &mut R, member: &str) -> Result<u64> {
+    let member = member.trim_start_matches("./");
+    let mut header = [0; TAR_BLOCK];
+    let mut long_name = None;
+    let mut pax = Pax::default();
+    loop {
+        if !read_block(reader, &mut header).await? || header.iter().all(|b| *b == 0) {
+            bail!("the archive has no member {member}");
+        }
+        let size = parse_size(&header[124..136])?;
+        match header[156] {
+            // GNU long name of the next member
+            b'L' => {
+                long_name = Some(c_string(&read_data(reader, size).await?));
+                continue;
+            }
+            // pax extended header of the next member
+            b'x' => {
+                pax = Pax::parse(&read_data(reader, size).await?)?;
+                continue;
+            }
+            _ => {}
+        }
+
+        let name = match (pax.path.take(), long_name.take()) {
+            (Some(path), _) | (None, Some(path)) => path,
+            (None, None) => header_name(&header),
+        };
+        let size = pax.size.take().unwrap_or(size);
+        match header[156] {
+            b'0' | 0 | b'7' if name.trim_start_matches("./") == member => return Ok(size),
+            b'S' if name.trim_start_matches("./") == member => {
+                bail!("{member} is a GNU sparse member, which is not supported")
+            }
+            _ => {
+                let padded = size.next_multiple_of(TAR_BLOCK as u64);
+                io::copy(&mut reader.take(padded), &mut io::sink()).await?;
+            }
+        }
+    }
+}
+
+/// Reads a block, returning `false` at the end of the stream
+async fn read_block<R: AsyncRead + Unpin>(reader: &mut R, block: &mut [u8]) -> Result<bool> {
+    match reader.read_exact(block).await {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Reads the contents of a metadata member
+async fn read_data<R: AsyncRead + Unpin>(reader: &mut R, size: u64) -> Result<Vec<u8>> {
+    if size > 1 << 20 {
+        bail!("the archive has a metadata member of {size} bytes");
+    }
+    let mut data = vec![0; size.next_multiple_of(TAR_BLOCK as u64) as usize];
+    reader.read_exact(&mut data).await?;
+    data.truncate(size as usize);
+    Ok(data)
+}
+
+fn c_string(raw: &[u8]) -> String {
+    String::from_utf8_lossy(raw.split(|b| *b == 0).next().unwrap()).into()
+}
+
+/// Returns the name of a header, prefixed as in POSIX ustar archives, which GNU ones are not
+fn header_name(header: &[u8; TAR_BLOCK]) -> String {
+    let name = c_string(&header[..100]);
+    match &header[257..263] == b"ustar\0" {
+        true => match c_string(&header[345..500]) {
+            prefix if prefix.is_empty() => name,
+            prefix => format!("{prefix}/{name}"),
+        },
+        false => name,
+    }
+}
+
+/// Parses a size, in octal or in the base-256 of GNU archives for members over 8 GiB
+fn parse_size(field: &[u8]) -> Result<u64> {
+    if field[0] & 0x80 != 0 {
+        return Ok(field[1..]
+            .iter()
+            .fold((field[0] & 0x7f) as u64, |size, b| size << 8 | *b as u64));
+    }
+    let octal = String::from_utf8_lossy(field);
+    let octal = octal.trim_matches(|c: char| c == '\0' || c == ' ');
+    match octal {
+        "" => Ok(0),
+        octal => u64::from_str_radix(octal, 8)
+            .with_context(|| format!("{octal:?} is not the size of a tar member")),
+    }
+}
+
+/// Records of a pax extended header overriding the header of the next member
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Pax {
+    path: Option<String>,
+    size: Option<u64>,
+}
+
+impl Pax {
+    /// Parses the `<length> <key>=<value>\n` records, ignoring the keys not needed to find a member
+    fn parse(mut raw: &[u8]) -> Result<Self> {
+        let mut pax = Self::default();
+        while !raw.is_empty() {
+            let invalid = || anyhow!("invalid pax extended header");
+            let space = raw.iter().position(|b| *b == b' ').ok_or_else(invalid)?;
+            let len: usize = std::str::from_utf8(&raw[..space])?
+                .parse()
+                .map_err(|_| invalid())?;
+            let record = raw.get(space + 1..len).ok_or_else(invalid)?;
+            let record = record.strip_suffix(b"\n").ok_or_else(invalid)?;
+            if let Some(path) = record.strip_prefix(b"path=") {
+                pax.path = Some(String::from_utf8_lossy(path).into());
+            } else if let Some(size) = record.strip_prefix(b"size=") {
+                let size = String::from_utf8_lossy(size);
+                pax.size = Some(
+                    size.parse()
+                        .with_context(|| format!("{size:?} is not the size of a tar member"))?,
+                );
+            }
+            raw = &raw[len..];
+        }
+        Ok(pax)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::MetadataExt, path::Path};
+
+    use async_compression::tokio::write::{GzipEncoder, XzEncoder, ZstdEncoder};
+    use tempfile::tempdir;
+    use tokio::{
+        fs,
+        io::{AsyncWrite, AsyncWriteExt},
+        process::Command,
+    };
+
+    use super::*;
+    use crate::infrastructure::cache::{ArtifactCache, CacheEntry};
+
+    /// A sparse disk image: data between two megabytes of zeros
+    fn image() -> Vec<u8> {
+        let mut image = vec![0; 2 << 20];
+        image[1 << 20..(1 << 20) + 6].copy_from_slice(b"rootfs");
+        image
+    }
+
+    async fn compress(compression: Compression, data: &[u8]) -> Result<Vec<u8>> {
+        async fn encode<W: AsyncWrite + Unpin>(mut encoder: W, data: &[u8]) -> Result<()> {
+            encoder.write_all(data).await?;
+            encoder.shutdown().await?;
+            Ok(())
+        }
+        let mut compressed = vec![];
+        match compression {
+            Compression::Gzip => encode(GzipEncoder::new(&mut compressed), data).await?,
+            Compression::Zstd => encode(ZstdEncoder::new(&mut compressed), data).await?,
+            Compression::Xz => encode(XzEncoder::new(&mut compressed), data).await?,
+        }
+        Ok(compressed)
+    }
+
+    async fn store(cache: &ArtifactCache, data: &[u8], member: Option<&str>) -> Result<CacheEntry> {
+        let mut writer = cache.writer().await?;
+        unpack(data, Compression::detect(data), member, &mut writer).await?;
+        writer.commit("artifact", None).await
+    }
+
+    async fn tar(args: &[&str], dir: &Path) -> Result<()> {
+        let status = Command::new("tar")
+            .args(args)
+            .current_dir(dir)
+            .status()
+            .await?;
+        assert!(status.success(), "tar {args:?}: {status}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn decompress_test() -> Result<()> {
+        let dir = tempdir()?;
+        let cache = ArtifactCache::new(dir.path());
+        for compression in [Compression::Gzip, Compression::Zstd, Compression::Xz] {
+            let compressed = compress(compression, &image()).await?;
+            assert_eq!(Compression::detect(&compressed), Some(compression));
+            let entry = store(&cache, &compressed, None).await?;
+            assert_eq!(entry.size, image().len() as u64);
+            assert_eq!(fs::read(entry.path()).await?, image());
+            let meta = fs::metadata(entry.path()).await?;
+            assert!(
+                meta.blocks() * 512 < meta.len(),
+                "{compression:?} is not sparse"
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn corrupt_test() -> Result<()> {
+        let dir = tempdir()?;
+        let cache = ArtifactCache::new(dir.path());
+        let mut archive = vec![];
+        archive.extend(header("vmlinux", b'0', 6));
+        archive.extend(padded(b"kernel"));
+        archive.extend([0; 2 * TAR_BLOCK]);
+
+        for compression in [Compression::Gzip, Compression::Zstd, Compression::Xz] {
+            let compressed = compress(compression, &image()).await?;
+            let truncated = &compressed[..compressed.len() - 8];
+            assert!(store(&cache, truncated, None).await.is_err());
+
+            // The member is complete, the end of the stream is not
+            let compressed = compress(compression, &archive).await?;
+            let truncated = &compressed[..compressed.len() - 4];
+            assert!(store(&cache, truncated, Some("vmlinux")).await.is_err());
+            let entry = store(&cache, &compressed, Some("vmlinux")).await?;
+            assert_eq!(fs::read(entry.path()).await?, b"kernel");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn extract_test() -> Result<()> {
+        let dir = tempdir()?;
+        let cache = ArtifactCache::new(dir.path().join("cache"));
+        let images = dir.path().join("images");
+        let long = "a-directory-name-long-enough-to-need-an-extended-header".repeat(3);
+        fs::create_dir_all(images.join(&long)).await?;
+        fs::write(images.join("vmlinux"), b"kernel").await?;
+        fs::write(images.join(&long).join("rootfs.ext4"), image()).await?;
+        let member = format!("./{long}/rootfs.ext4");
+        tar(&["-cf", "images.tar", "-C", "images", "."], dir.path()).await?;
+        tar(
+            &["--format=pax", "-cf", "pax.tar", "-C", "images", "."],
+            dir.path(),
+        )
+        .await?;
+
+        let archive = fs::read(dir.path().join("images.tar")).await?;
+        let kernel = store(&cache, &archive, Some("vmlinux")).await?;
+        assert_eq!(fs::read(kernel.path()).await?, b"kernel");
+        let rootfs = store(&cache, &archive, Some(&member)).await?;
+        assert_eq!(fs::read(rootfs.path()).await?, image());
+
+        let pax = fs::read(dir.path().join("pax.tar")).await?;
+        let compressed = compress(Compression::Zstd, &pax).await?;
+        let rootfs = store(&cache, &compressed, Some(&member[2..])).await?;
+        assert_eq!(fs::read(rootfs.path()).await?, image());
+        let error = store(&cache, &compressed, Some("initrd.img"))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("no member initrd.img"));
+        Ok(())
+    }
+
+    /// Returns a ustar header, without a checksum as it is not verified
+    fn header(name: &str, kind: u8, size: u64) -> [u8; TAR_BLOCK] {
+        let mut header = [0; TAR_BLOCK];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[124..135].copy_from_slice(format!("{size:011o}").as_bytes());
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header
+    }
+
+    fn padded(data: &[u8]) -> Vec<u8> {
+        let mut padded = data.to_vec();
+        padded.resize(data.len().next_multiple_of(TAR_BLOCK), 0);
+        padded
+    }
+
+    #[tokio::test]
+    async fn pax_size_test() -> Result<()> {
+        let dir = tempdir()?;
+        let cache = ArtifactCache::new(dir.path());
+        // The header size of members over 8 GiB is only in the pax record
+        let records = b"13 size=1000\n20 path=rootfs.ext4\n";
+        let mut archive = vec![];
+        archive.extend(header("PaxHeaders/rootfs.ext4", b'x', records.len() as u64));
+        archive.extend(padded(records));
+        archive.extend(header("rootfs.ext4", b'0', 0));
+        archive.extend(padded(&[7; 1000]));
+        archive.extend(header("vmlinux", b'0', 6));
+        archive.extend(padded(b"kernel"));
+        archive.extend([0; 2 * TAR_BLOCK]);
+
+        let rootfs = store(&cache, &archive, Some("rootfs.ext4")).await?;
+        assert_eq!(fs::read(rootfs.path()).await?, [7; 1000]);
+        let kernel = store(&cache, &archive, Some("vmlinux")).await?;
+        assert_eq!(fs::read(kernel.path()).await?, b"kernel");
+
+        assert!(Pax::parse(b"13 size=1000").is_err());
+        assert!(Pax::parse(b"12 size=big\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_size_test() -> Result<()> {
+        assert_eq!(parse_size(b"00000001750\0")?, 1000);
+        assert_eq!(parse_size(b"       1750 ")?, 1000);
+        let mut base256 = [0u8; 12];
+        base256[0] = 0x80;
+        base256[7] = 0x03;
+        assert_eq!(parse_size(&base256)?, 3 << 32);
+        assert!(parse_size(b"not a size!\0").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,249 @@
+use std::{
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use tempfile::Builder;
+
+/// Maximum length of a Unix socket path, `sun_path` minus the trailing NUL byte.
+pub const MAX_SOCKET_PATH_LEN: usize = 107;
+
+/// What happens to a [`VmWorkspace`] once its VM ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum CleanupPolicy {
+    /// Remove the whole workspace directory, including files that were there before
+    RemoveAll,
+    /// Remove the sockets, keep logs, metrics, console capture and drives
+    KeepArtifacts,
+    /// Leave the workspace untouched
+    Keep,
+}
+
+/// A directory holding every file that belongs to a single VM.
+///
+/// Layout:
+/// ```text
+/// <root>/
+/// ├── firecracker.socket   API socket
+/// ├── vsock.socket         vsock Unix socket
+/// ├── metrics.json         Firecracker metrics, one JSON object per flush
+/// ├── console.log          captured guest console
+/// ├── logs/
+/// │   └── firecracker.log  Firecracker log
+/// ├── drives/              per-VM copies of disks
+/// └── snapshots/
+///     ├── vm.snap          VM state of the latest snapshot
+///     └── vm.mem           guest memory of the latest snapshot
+/// ```
+#[derive(Debug, Clone, Serialize)]
+pub struct VmWorkspace {
+    root: PathBuf,
+    cleanup: CleanupPolicy,
+}
+
+impl VmWorkspace {
+    /// Creates a workspace rooted at the specified directory, cleaned up with [`CleanupPolicy::KeepArtifacts`]
+    ///
+    /// Note: Nothing is created on disk until [`VmWorkspace::create`] is called.
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            cleanup: CleanupPolicy::KeepArtifacts,
+        }
+    }
+
+    /// Creates a fresh directory with a unique name in the system temporary directory,
+    /// removed with [`CleanupPolicy::RemoveAll`]
+    pub fn temporary() -> Result<Self> {
+        let root = Builder::new()
+            .prefix("firecracker-")
+            .tempdir()
+            .context("failed to create the workspace directory")?
+            .keep();
+        Ok(Self::new(root).cleanup_policy(CleanupPolicy::RemoveAll))
+    }
+
+    /// Set the policy applied by [`VmWorkspace::cleanup`]
+    pub fn cleanup_policy(mut self, policy: CleanupPolicy) -> Self {
+        self.cleanup = policy;
+        self
+    }
+
+    /// Returns the current cleanup policy
+    pub fn current_cleanup_policy(&self) -> CleanupPolicy {
+        self.cleanup
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn api_socket(&self) -> PathBuf {
+        self.root.join("firecracker.socket")
+    }
+
+    pub fn vsock_socket(&self) -> PathBuf {
+        self.root.join("vsock.socket")
+    }
+
+    pub fn metrics_file(&self) -> PathBuf {
+        self.root.join("metrics.json")
+    }
+
+    pub fn console_log(&self) -> PathBuf {
+        self.root.join("console.log")
+    }
+
+    pub fn logs_dir(&self) -> PathBuf {
+        self.root.join("logs")
+    }
+
+    pub fn log_file(&self) -> PathBuf {
+        self.logs_dir().join("firecracker.log")
+    }
+
+    pub fn drives_dir(&self) -> PathBuf {
+        self.root.join("drives")
+    }
+
+    /// Returns the path of the per-VM copy of the drive with the specified id,
+    /// with the extension of the source file
+    pub fn drive_path<P: AsRef<Path>>(&self, drive_id: &str, source: P) -> PathBuf {
+        let mut name = OsString::from(drive_id);
+        if let Some(extension) = source.as_ref().extension() {
+            name.push(".");
+            name.push(extension);
+        }
+        self.drives_dir().join(name)
+    }
+
+    pub fn snapshots_dir(&self) -> PathBuf {
+        self.root.join("snapshots")
+    }
+
+    /// Returns the default path of the VM state of a snapshot
+    pub fn snapshot_file(&self) -> PathBuf {
+        self.snapshots_dir().join("vm.snap")
+    }
+
+    /// Returns the default path of the guest memory of a snapshot
+    pub fn memory_file(&self) -> PathBuf {
+        self.snapshots_dir().join("vm.mem")
+    }
+
+    /// Creates the workspace directory tree, including missing parent directories,
+    /// and the log and metrics files, which Firecracker only opens
+    pub fn create(&self) -> Result<()> {
+        for dir in [self.logs_dir(), self.drives_dir(), self.snapshots_dir()] {
+            fs::create_dir_all(dir)?;
+        }
+        for file in [self.log_file(), self.metrics_file()] {
+            fs::File::options().create(true).append(true).open(file)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the cleanup policy to the workspace
+    pub fn cleanup(&self) -> Result<()> {
+        match self.cleanup {
+            CleanupPolicy::RemoveAll => ignore_not_found(fs::remove_dir_all(&self.root)),
+            CleanupPolicy::KeepArtifacts => {
+                for path in [self.api_socket(), self.vsock_socket()] {
+                    ignore_not_found(fs::remove_file(path))?;
+                }
+                Ok(())
+            }
+            CleanupPolicy::Keep => Ok(()),
+        }
+    }
+
+    /// Removes the specified sockets, which may live outside of the workspace, unless everything is kept,
+    /// then applies the cleanup policy
+    pub(crate) fn cleanup_with_sockets(&self, sockets: [&Path; 2]) {
+        if self.cleanup != CleanupPolicy::Keep {
+            for socket in sockets {
+                let _ = fs::remove_file(socket);
+            }
+        }
+        let _ = self.cleanup();
+    }
+}
+
+fn ignore_not_found(res: io::Result<()>) -> Result<()> {
+    match res {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn workspace_layout_test() -> Result<()> {
+        let dir = tempdir()?;
+        let workspace = VmWorkspace::new(dir.path().join("nested/vm0"))
+            .cleanup_policy(CleanupPolicy::RemoveAll);
+        workspace.create()?;
+
+        assert!(workspace.log_file().is_file());
+        assert!(workspace.metrics_file().is_file());
+        assert!(workspace.drives_dir().is_dir());
+        assert!(workspace.snapshots_dir().is_dir());
+        assert_eq!(
+            workspace.drive_path("rootfs", "/images/ubuntu.squashfs"),
+            dir.path().join("nested/vm0/drives/rootfs.squashfs")
+        );
+        assert_eq!(
+            workspace.drive_path("data", "/images/data"),
+            dir.path().join("nested/vm0/drives/data")
+        );
+
+        workspace.cleanup()?;
+        assert!(!workspace.root().exists());
+        Ok(())
+    }
+
+    #[test]
+    fn workspace_keep_artifacts_test() -> Result<()> {
+        let dir = tempdir()?;
+        let workspace = VmWorkspace::new(dir.path()).cleanup_policy(CleanupPolicy::KeepArtifacts);
+        workspace.create()?;
+        fs::write(workspace.api_socket(), b"")?;
+        fs::write(workspace.console_log(), b"console")?;
+
+        workspace.cleanup()?;
+        assert!(!workspace.api_socket().exists());
+        assert!(workspace.console_log().exists());
+        Ok(())
+    }
+
+    #[test]
+    fn workspace_default_cleanup_test() -> Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("data"), b"not ours")?;
+        let workspace = VmWorkspace::new(dir.path());
+        workspace.create()?;
+        workspace.cleanup()?;
+        assert!(dir.path().join("data").exists());
+
+        let temporary = VmWorkspace::temporary()?;
+        assert!(temporary.root().is_dir());
+        assert_eq!(temporary.current_cleanup_policy(), CleanupPolicy::RemoveAll);
+        // Created exclusively, so a second workspace never shares it
+        let other = VmWorkspace::temporary()?;
+        assert_ne!(other.root(), temporary.root());
+        for workspace in [temporary, other] {
+            workspace.cleanup()?;
+            assert!(!workspace.root().exists());
+        }
+        Ok(())
+    }
+}
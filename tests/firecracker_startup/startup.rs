@@ -1,11 +1,25 @@
 use anyhow::Result;
-use firecracker_sdk::api::startup::FirecrackerStartup;
+use firecracker_sdk::{api::startup::FirecrackerStartup, infrastructure::workspace::VmWorkspace};
+use tempfile::tempdir;
 
 #[tokio::test]
 async fn startup() -> Result<()> {
     let startup = FirecrackerStartup::new();
-    println!("{}", startup.get_api_socket().display());
+    assert!(startup.get_api_socket().is_none() && startup.get_workspace().is_none());
     let process = startup.start().await?;
+    println!("{}", process.config().api_socket().display());
+    process.stop().await?;
+
+    // Sockets set explicitly win over the ones of the workspace, whatever the order
+    let dir = tempdir()?;
+    let workspace = VmWorkspace::new(dir.path().join("vm0"));
+    let process = FirecrackerStartup::new()
+        .set_api_socket(dir.path().join("api.socket"))
+        .workspace(workspace.clone())
+        .start()
+        .await?;
+    assert_eq!(process.config().api_socket(), dir.path().join("api.socket"));
+    assert_eq!(process.config().vsock().uds_path, workspace.vsock_socket());
     process.stop().await?;
     Ok(())
 }
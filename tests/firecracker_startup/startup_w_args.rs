@@ -1,5 +1,5 @@
-use anyhow::Result;
-use firecracker_sdk::api::startup::FirecrackerStartup;
+use anyhow::{Context, Result};
+use firecracker_sdk::{api::startup::FirecrackerStartup, domain::config::ValidationErrors};
 use tempfile::tempdir;
 
 #[tokio::test]
@@ -11,3 +11,25 @@ async fn startup_with_args() -> Result<()> {
     dir.close()?;
     Ok(())
 }
+
+#[tokio::test]
+async fn startup_with_invalid_args() -> Result<()> {
+    // Reported together and before the kernel is downloaded
+    let error = FirecrackerStartup::new()
+        .set_api_socket(format!("/tmp/{}.socket", "a".repeat(120)))
+        .vcpu_count(0)
+        .download_kernel(true)
+        .start()
+        .await
+        .err()
+        .context("an invalid configuration must not start")?;
+    let fields: Vec<_> = error
+        .downcast_ref::<ValidationErrors>()
+        .context("expected validation errors")?
+        .violations()
+        .iter()
+        .map(|v| v.field.as_str())
+        .collect();
+    assert_eq!(fields, ["machine_config.vcpu_count", "api_socket"]);
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use anyhow::Result;
+use firecracker_sdk::{api::startup::FirecrackerStartup, domain::models::InstanceState};
+
+#[tokio::test]
+async fn startup_w_client() -> Result<()> {
+    let process = FirecrackerStartup::new().start().await?;
+
+    let pollers: Vec<_> = (0..4)
+        .map(|_| {
+            let client = process.client();
+            tokio::spawn(async move {
+                for _ in 0..10 {
+                    let info = client.instance_info().await?;
+                    assert_eq!(
+                        info.vmm_version,
+                        client.version().await?.firecracker_version
+                    );
+                }
+                Ok::<_, anyhow::Error>(())
+            })
+        })
+        .collect();
+    process.start_vm().await?;
+    for poller in pollers {
+        poller.await??;
+    }
+    assert_eq!(
+        process.client().instance_info().await?.state,
+        InstanceState::Running
+    );
+
+    let client = process.client();
+    process.stop().await?;
+    assert!(client.instance_info().await.is_err());
+    Ok(())
+}
@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use firecracker_sdk::{
+    api::startup::FirecrackerStartup, domain::config::SnapshotType,
+    infrastructure::process::events::VmEventKind,
+};
+use tempfile::tempdir;
+
+#[tokio::test]
+async fn startup_w_events() -> Result<()> {
+    let dir = tempdir()?;
+    let startup = FirecrackerStartup::new().state_poll_interval(Duration::from_millis(50));
+    let mut events = startup.subscribe();
+
+    let process = startup.start().await?;
+    process.start_vm().await?;
+    process.pause().await?;
+    process
+        .create_snapshot(
+            SnapshotType::Full,
+            dir.path().join("snapshot"),
+            dir.path().join("memory"),
+        )
+        .await?;
+    process.resume().await?;
+    process.stop().await?;
+
+    let mut kinds = vec![];
+    while let Ok(event) = events.try_recv() {
+        kinds.push(event.kind);
+    }
+    assert!(matches!(
+        kinds.as_slice(),
+        [
+            VmEventKind::Spawned { .. },
+            VmEventKind::ApiReady,
+            VmEventKind::Configured,
+            VmEventKind::Running,
+            VmEventKind::Paused,
+            VmEventKind::SnapshotCreated { .. },
+            VmEventKind::Resumed,
+            VmEventKind::Exited(_),
+        ]
+    ));
+    Ok(())
+}
@@ -0,0 +1,15 @@
+use anyhow::Result;
+use firecracker_sdk::{api::startup::FirecrackerStartup, infrastructure::process::ExitReason};
+
+#[tokio::test]
+async fn startup_w_exit() -> Result<()> {
+    let process = FirecrackerStartup::new().start().await?;
+    assert!(process.try_wait().is_none());
+
+    let exit = process.exit_events();
+    process.stop().await?;
+
+    let exit = exit.borrow().clone().unwrap();
+    assert_eq!(exit.reason(), ExitReason::CleanShutdown);
+    Ok(())
+}
@@ -0,0 +1,24 @@
+use anyhow::Result;
+use firecracker_sdk::{
+    api::startup::FirecrackerStartup, domain::models::InstanceState,
+    infrastructure::connection::TransportKind,
+};
+use http::Method;
+
+#[tokio::test]
+async fn startup_w_hyper() -> Result<()> {
+    let process = FirecrackerStartup::new()
+        .transport(TransportKind::Hyper)
+        .start()
+        .await?;
+
+    for _ in 0..3 {
+        let info = process.instance_info().await?;
+        assert_eq!(info.state, InstanceState::NotStarted);
+    }
+    let res = process.request_raw(Method::GET, "/version", None).await?;
+    assert!(res.status().is_success());
+
+    process.stop().await?;
+    Ok(())
+}
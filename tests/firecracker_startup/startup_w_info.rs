@@ -0,0 +1,21 @@
+use anyhow::Result;
+use firecracker_sdk::{api::startup::FirecrackerStartup, domain::models::InstanceState};
+
+#[tokio::test]
+async fn startup_w_info() -> Result<()> {
+    let process = FirecrackerStartup::new().start().await?;
+
+    let info = process.instance_info().await?;
+    assert_eq!(info.state, InstanceState::NotStarted);
+    let version = process.version().await?;
+    assert_eq!(info.vmm_version, version.firecracker_version);
+
+    process.start_vm().await?;
+    assert_eq!(process.instance_info().await?.state, InstanceState::Running);
+    let machine = process.machine_config().await?;
+    let vm = process.vm_config().await?;
+    assert_eq!(vm.machine_config, Some(machine));
+
+    process.stop().await?;
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use anyhow::Result;
+use firecracker_sdk::{
+    api::startup::FirecrackerStartup,
+    domain::models::{RateLimiter, TokenBucket},
+};
+use serde_json::json;
+
+#[tokio::test]
+async fn startup_w_reconcile() -> Result<()> {
+    let mut process = FirecrackerStartup::new().start().await?;
+    process.start_vm().await?;
+
+    let limiter = RateLimiter {
+        bandwidth: Some(TokenBucket {
+            size: 1024 * 1024,
+            one_time_burst: None,
+            refill_time: 1000,
+        }),
+        ops: None,
+    };
+    let desired = process
+        .config()
+        .clone()
+        .drive_rate_limiter(Some(limiter))
+        .mmds(json!({"latest": {"meta-data": {"instance-id": "i-0"}}}));
+
+    let report = process.reconcile(&desired).await?;
+    let applied: Vec<_> = report.applied().iter().map(|d| d.field.as_str()).collect();
+    assert_eq!(applied, ["drives.rootfs.rate_limiter", "mmds"]);
+    assert!(report.requires_restart().is_empty());
+
+    assert_eq!(process.diff(&desired).await?.live().count(), 0);
+
+    process.stop().await?;
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use std::{env, path::PathBuf};
+
+use anyhow::{Context, Result};
+use firecracker_sdk::{
+    api::startup::FirecrackerStartup,
+    infrastructure::{installer::FirecrackerInstaller, process::FirecrackerProcess},
+};
+use tempfile::tempdir;
+
+/// Returns the path of the Firecracker binary run by default
+fn default_binary() -> Result<PathBuf> {
+    let binary = env::var_os("FIRECRACKER").unwrap_or("firecracker".into());
+    if PathBuf::from(&binary).components().count() > 1 {
+        return Ok(binary.into());
+    }
+    env::split_paths(&env::var_os("PATH").unwrap_or_default())
+        .map(|dir| dir.join(&binary))
+        .find(|path| path.is_file())
+        .context("firecracker is not in PATH")
+}
+
+#[tokio::test]
+async fn startup_w_release() -> Result<()> {
+    let version = FirecrackerProcess::binary_version(default_binary()?).await?;
+    let dir = tempdir()?;
+    let installer = FirecrackerInstaller::new(dir.path());
+
+    let missing = FirecrackerStartup::new()
+        .installer(installer.clone())
+        .firecracker_release(version)
+        .start()
+        .await;
+    assert!(missing.is_err_and(|e| e.to_string().contains("is not installed")));
+
+    // Installs the default binary as the release, the jailer is not run
+    let release = installer.path(version);
+    tokio::fs::create_dir_all(&release).await?;
+    tokio::fs::copy(default_binary()?, release.join("firecracker")).await?;
+    tokio::fs::write(release.join("jailer"), "").await?;
+    assert_eq!(installer.installed().await?.len(), 1);
+
+    let process = FirecrackerStartup::new()
+        .installer(installer)
+        .firecracker_release(version)
+        .start()
+        .await?;
+    assert_eq!(process.detected_version(), version);
+    process.stop().await?;
+    Ok(())
+}
@@ -0,0 +1,26 @@
+use anyhow::Result;
+use firecracker_sdk::{api::startup::FirecrackerStartup, domain::models::InstanceInfo};
+use http::Method;
+use serde_json::json;
+
+#[tokio::test]
+async fn startup_w_request() -> Result<()> {
+    let process = FirecrackerStartup::new().start().await?;
+
+    let info: InstanceInfo = process.request(Method::GET, "/", ()).await?;
+    assert_eq!(info.app_name, "Firecracker");
+
+    let failed = process
+        .request::<_, ()>(Method::PUT, "/drives/bad", json!({}))
+        .await;
+    assert!(failed.is_err());
+
+    let res = process
+        .request_raw(Method::PUT, "/drives/bad", Some(b"{}".to_vec()))
+        .await?;
+    assert_eq!(res.status(), 400);
+    assert!(String::from_utf8(res.into_body())?.contains("fault_message"));
+
+    process.stop().await?;
+    Ok(())
+}
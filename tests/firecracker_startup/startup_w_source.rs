@@ -0,0 +1,49 @@
+use anyhow::Result;
+use firecracker_sdk::{
+    api::startup::FirecrackerStartup,
+    infrastructure::{
+        cache::ArtifactCache,
+        lockfile::{ArtifactMismatch, Lockfile},
+        source::{ArtifactKind, MemorySource},
+    },
+};
+use tempfile::tempdir;
+
+#[tokio::test]
+async fn startup_w_source() -> Result<()> {
+    let dir = tempdir()?;
+    let lockfile = dir.path().join("firecracker.lock");
+    let source = |rootfs: &'static [u8]| {
+        MemorySource::new()
+            .artifact(ArtifactKind::Kernel, b"kernel".as_slice())
+            .artifact(ArtifactKind::Rootfs, rootfs)
+    };
+
+    let process = FirecrackerStartup::new()
+        .artifact_cache(ArtifactCache::new(dir.path().join("cache")))
+        .artifact_source(source(b"rootfs"))
+        .lockfile(&lockfile)
+        .update_lockfile(true)
+        .start()
+        .await?;
+    process.stop().await?;
+    let locked = Lockfile::read(&lockfile).await?.unwrap();
+    assert_eq!(
+        locked.get(ArtifactKind::Kernel).unwrap().key,
+        "memory/kernel"
+    );
+    assert_eq!(
+        locked.get(ArtifactKind::Rootfs).unwrap().key,
+        "memory/rootfs"
+    );
+    assert!(locked.get(ArtifactKind::Initrd).is_none());
+
+    let changed = FirecrackerStartup::new()
+        .artifact_cache(ArtifactCache::new(dir.path().join("cache")))
+        .artifact_source(source(b"changed"))
+        .lockfile(&lockfile)
+        .start()
+        .await;
+    assert!(changed.is_err_and(|e| e.downcast_ref::<ArtifactMismatch>().is_some()));
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use firecracker_sdk::{
+    api::startup::FirecrackerStartup,
+    infrastructure::{
+        process::events::{VmEvent, VmEventKind},
+        supervisor::{Backoff, RestartPolicy, SupervisorEvent},
+    },
+};
+use tokio::sync::broadcast;
+
+/// Waits for the next `Running` event, i.e. a booted guest
+async fn booted(events: &mut broadcast::Receiver<VmEvent>) -> Result<()> {
+    while !matches!(events.recv().await?.kind, VmEventKind::Running) {}
+    Ok(())
+}
+
+#[tokio::test]
+async fn startup_w_supervisor() -> Result<()> {
+    let startup = FirecrackerStartup::new();
+    let mut vm_events = startup.subscribe();
+    let supervisor = startup
+        .supervise(RestartPolicy::OnFailure {
+            max_retries: 1,
+            backoff: Backoff {
+                initial: Duration::from_millis(10),
+                max: Duration::from_millis(10),
+            },
+        })
+        .await?;
+    let mut events = supervisor.subscribe();
+    booted(&mut vm_events).await?;
+
+    let pid = supervisor.process().await.pid().unwrap();
+    unsafe {
+        libc::kill(pid as i32, libc::SIGKILL);
+    }
+
+    assert!(matches!(
+        events.recv().await?,
+        SupervisorEvent::Restarting { attempt: 1, .. }
+    ));
+    assert!(matches!(
+        events.recv().await?,
+        SupervisorEvent::Restarted { attempt: 1 }
+    ));
+    assert!(supervisor.process().await.try_wait().is_none());
+    // The relaunched process publishes to the subscribers of the startup
+    booted(&mut vm_events).await?;
+
+    supervisor.stop().await?;
+    Ok(())
+}
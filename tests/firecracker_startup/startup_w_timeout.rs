@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use firecracker_sdk::{
+    api::startup::FirecrackerStartup,
+    infrastructure::{client::ClientOptions, connection::RequestTimeout},
+};
+
+/// Waits until the state in `/proc/<pid>/stat` is reached, as signals are delivered asynchronously
+async fn wait_for_state(pid: libc::pid_t, state: char) -> Result<()> {
+    loop {
+        let stat = tokio::fs::read_to_string(format!("/proc/{pid}/stat")).await?;
+        // The state follows the command name, which is in parentheses
+        if stat
+            .rsplit_once(") ")
+            .and_then(|(_, rest)| rest.chars().next())
+            == Some(state)
+        {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+}
+
+#[tokio::test]
+async fn startup_w_timeout() -> Result<()> {
+    let process = FirecrackerStartup::new()
+        .client_options(ClientOptions {
+            timeout: Some(Duration::from_millis(500)),
+            ..Default::default()
+        })
+        .start()
+        .await?;
+    let pid = process.pid().unwrap() as libc::pid_t;
+    process.instance_info().await?;
+
+    // A stopped process wedges the API
+    assert_eq!(unsafe { libc::kill(pid, libc::SIGSTOP) }, 0);
+    wait_for_state(pid, 'T').await?;
+    let error = process.instance_info().await.unwrap_err();
+    assert!(error.downcast_ref::<RequestTimeout>().is_some());
+
+    assert_eq!(unsafe { libc::kill(pid, libc::SIGCONT) }, 0);
+    let slow = process.client().timeout(Some(Duration::from_secs(5)));
+    slow.instance_info().await?;
+    process.instance_info().await?;
+
+    process.stop().await?;
+    Ok(())
+}
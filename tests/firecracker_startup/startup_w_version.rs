@@ -0,0 +1,23 @@
+use anyhow::Result;
+use firecracker_sdk::{
+    api::startup::FirecrackerStartup,
+    domain::compat::{Feature, MIN_SUPPORTED_VERSION},
+};
+
+#[tokio::test]
+async fn startup_w_version() -> Result<()> {
+    let binary = FirecrackerStartup::new().binary_version().await?;
+    assert!(binary >= MIN_SUPPORTED_VERSION);
+
+    let process = FirecrackerStartup::new().start().await?;
+    let detected = process.detected_version();
+    assert_eq!(detected, binary);
+    assert_eq!(
+        detected.to_string(),
+        process.version().await?.firecracker_version
+    );
+    assert!(detected.supports(Feature::SnapshotMemBackend));
+
+    process.stop().await?;
+    Ok(())
+}
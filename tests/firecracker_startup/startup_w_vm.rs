@@ -0,0 +1,49 @@
+use anyhow::{Result, bail};
+use firecracker_sdk::{api::startup::FirecrackerStartup, domain::config::SnapshotType};
+use tempfile::tempdir;
+
+#[tokio::test]
+async fn startup_w_vm() -> Result<()> {
+    let dir = tempdir()?;
+    let mut vm = FirecrackerStartup::new()
+        .launch()
+        .await?
+        .boot_args("console=ttyS0 reboot=k panic=1 pci=off")
+        .start()
+        .await?;
+    assert!(vm.pid().is_some() && vm.try_wait().is_none());
+    vm.snapshot(
+        SnapshotType::Full,
+        dir.path().join("snapshot"),
+        dir.path().join("memory"),
+    )
+    .await?;
+    let (snapshot, memory) = vm.snapshot_to_workspace(SnapshotType::Full).await?;
+    let snapshots = vm.config().workspace().snapshots_dir();
+    assert!(snapshot.starts_with(&snapshots) && memory.starts_with(&snapshots));
+
+    let vm = vm.pause().await?.resume().await?;
+    let stopped = vm.stop().await?;
+    assert!(
+        stopped
+            .config()
+            .kernel_image_path()
+            .ends_with("vmlinux.bin")
+    );
+    assert!(!stopped.exit().reason().is_failure());
+
+    // A failed transition gives the VM back in its previous state
+    let Err(failed) = FirecrackerStartup::new()
+        .launch()
+        .await?
+        .network_interface("net2", "06:00:AC:10:00:02", "tap1")
+        .start()
+        .await
+    else {
+        bail!("a MAC address used twice must fail the boot");
+    };
+    assert!(failed.to_string().contains("guest_mac"));
+    let stopped = failed.into_vm().stop().await?;
+    assert!(!stopped.exit().reason().is_failure());
+    Ok(())
+}